@@ -2,49 +2,49 @@
 //
 // Demonstrates:
 // - Concurrent operations with tokio
-// - Task spawning
+// - Task spawning with cloned client handles
 // - Shared state management
 // - Error handling in async context
 
-use ltp_client::LtpClient;
+use ltp_client::{LtpClient, LtpHandle};
 use serde_json::json;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
 
 struct ConcurrentLtpClient {
-    client: Arc<Mutex<LtpClient>>,
-    task_count: Arc<Mutex<u32>>,
+    client: LtpHandle,
+    task_count: Arc<AtomicU32>,
 }
 
 impl ConcurrentLtpClient {
-    fn new(url: impl Into<String>, client_id: impl Into<String>) -> Self {
-        Self {
-            client: Arc::new(Mutex::new(
-                LtpClient::new(url, client_id).with_default_context_tag("concurrent"),
-            )),
-            task_count: Arc::new(Mutex::new(0)),
-        }
-    }
-
-    async fn connect(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut client = self.client.lock().await;
-        client.connect().await?;
+    async fn connect(
+        url: impl Into<String>,
+        client_id: impl Into<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = LtpClient::new(url, client_id)
+            .with_default_context_tag("concurrent")
+            .connect()
+            .await?;
         println!("✓ Connected to LTP server");
-        Ok(())
+        Ok(Self {
+            client,
+            task_count: Arc::new(AtomicU32::new(0)),
+        })
     }
 
-    async fn send_concurrent_updates(&self, count: usize) -> Result<(), Box<dyn std::error::Error>> {
+    async fn send_concurrent_updates(
+        &self,
+        count: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let mut handles = Vec::new();
 
         for i in 0..count {
-            let client = Arc::clone(&self.client);
+            // Each task gets its own handle; no lock is held while sending.
+            let client = self.client.clone();
             let task_count = Arc::clone(&self.task_count);
 
             let handle = tokio::spawn(async move {
-                let mut client = client.lock().await;
-                let mut count = task_count.lock().await;
-
                 match client
                     .send_state_update(
                         "concurrent_update",
@@ -56,7 +56,7 @@ impl ConcurrentLtpClient {
                     .await
                 {
                     Ok(_) => {
-                        *count += 1;
+                        task_count.fetch_add(1, Ordering::Relaxed);
                         println!("Task {} completed", i);
                         Ok(())
                     }
@@ -75,15 +75,16 @@ impl ConcurrentLtpClient {
             handle.await??;
         }
 
-        let count = self.task_count.lock().await;
-        println!("Completed {} concurrent tasks", *count);
+        println!(
+            "Completed {} concurrent tasks",
+            self.task_count.load(Ordering::Relaxed)
+        );
 
         Ok(())
     }
 
     async fn disconnect(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut client = self.client.lock().await;
-        client.disconnect().await?;
+        self.client.disconnect().await?;
         println!("Disconnected from LTP server");
         Ok(())
     }
@@ -91,9 +92,8 @@ impl ConcurrentLtpClient {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let client = ConcurrentLtpClient::new("ws://localhost:8080", "concurrent-rust-example-001");
-
-    client.connect().await?;
+    let client =
+        ConcurrentLtpClient::connect("ws://localhost:8080", "concurrent-rust-example-001").await?;
 
     // Send concurrent updates
     println!("Sending 10 concurrent updates...");
@@ -105,4 +105,3 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
-
@@ -7,7 +7,7 @@
 // - Structured logging
 // - Graceful shutdown
 
-use ltp_client::LtpClient;
+use ltp_client::{LtpClient, LtpHandle};
 use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -38,7 +38,10 @@ impl ClientMetrics {
     fn to_map(&self) -> HashMap<String, String> {
         let mut map = HashMap::new();
         map.insert("messages_sent".to_string(), self.messages_sent.to_string());
-        map.insert("messages_received".to_string(), self.messages_received.to_string());
+        map.insert(
+            "messages_received".to_string(),
+            self.messages_received.to_string(),
+        );
         map.insert("errors".to_string(), self.errors.to_string());
         map.insert("reconnects".to_string(), self.reconnects.to_string());
         map.insert(
@@ -50,46 +53,37 @@ impl ClientMetrics {
 }
 
 struct ProductionLtpClient {
-    client: Arc<Mutex<LtpClient>>,
+    client: LtpHandle,
     metrics: Arc<Mutex<ClientMetrics>>,
-    url: String,
-    client_id: String,
 }
 
 impl ProductionLtpClient {
-    fn new(url: impl Into<String>, client_id: impl Into<String>) -> Self {
-        Self {
-            client: Arc::new(Mutex::new(
-                LtpClient::new(url.clone(), client_id.clone())
-                    .with_default_context_tag("production"),
-            )),
-            metrics: Arc::new(Mutex::new(ClientMetrics::new())),
-            url: url.into(),
-            client_id: client_id.into(),
-        }
-    }
-
-    async fn connect(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut client = self.client.lock().await;
-        client.connect().await?;
+    async fn connect(
+        url: impl Into<String>,
+        client_id: impl Into<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let client = LtpClient::new(url, client_id)
+            .with_default_context_tag("production")
+            .connect()
+            .await?;
         println!("✓ Connected to LTP server");
-        Ok(())
+        Ok(Self {
+            client,
+            metrics: Arc::new(Mutex::new(ClientMetrics::new())),
+        })
     }
 
     async fn send_batch_state_updates(
         &self,
         updates: Vec<serde_json::Value>,
     ) -> Result<Vec<bool>, Box<dyn std::error::Error>> {
-        let mut client = self.client.lock().await;
         let mut results = Vec::new();
         let mut metrics = self.metrics.lock().await;
 
         for update in updates {
-            match client
-                .send_state_update(
-                    "batch_update",
-                    update.clone(),
-                )
+            match self
+                .client
+                .send_state_update("batch_update", update.clone())
                 .await
             {
                 Ok(_) => {
@@ -112,10 +106,9 @@ impl ProductionLtpClient {
         logs: Vec<serde_json::Value>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("Sending affect log batch ({} entries)", logs.len());
-        let mut client = self.client.lock().await;
         let mut metrics = self.metrics.lock().await;
 
-        client
+        self.client
             .send_state_update("affect_log_batch", json!(logs))
             .await?;
 
@@ -129,8 +122,7 @@ impl ProductionLtpClient {
     }
 
     async fn disconnect(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut client = self.client.lock().await;
-        client.disconnect().await?;
+        self.client.disconnect().await?;
         println!("Disconnected from LTP server");
         Ok(())
     }
@@ -138,9 +130,8 @@ impl ProductionLtpClient {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let client = ProductionLtpClient::new("ws://localhost:8080", "prod-rust-example-001");
-
-    client.connect().await?;
+    let client =
+        ProductionLtpClient::connect("ws://localhost:8080", "prod-rust-example-001").await?;
 
     // Send a large batch of affect logs
     let affect_logs: Vec<serde_json::Value> = (1..=100)
@@ -180,4 +171,3 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
-
@@ -0,0 +1,384 @@
+//! API key storage for `AUTH_KEYS_FILE` and `AUTH_KEYS`.
+//!
+//! Keys are never kept in plaintext. A hashed file entry stores the public
+//! prefix of a key of the form `ltp_<prefix>_<secret>` together with a random
+//! salt and `SHA256(salt || key)`, so a handshake costs one map lookup and one
+//! hash instead of a comparison against every key. Legacy plaintext entries
+//! are still accepted but are reduced to `SHA256(key)` when loaded.
+//!
+//! Hashed entries may also carry a `KeyPolicy` restricting when and how the
+//! key may be used; plaintext keys are unrestricted.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use ltp_protocol::node::INCOMING_TYPES;
+
+/// Leading tag of generated keys.
+pub const KEY_TAG: &str = "ltp";
+
+/// One value of the `AUTH_KEYS_FILE` JSON object, keyed by client id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyEntry {
+    Hashed(HashedKey),
+    Plaintext(String),
+}
+
+/// A key stored as a salted SHA-256 digest, looked up by its public prefix.
+///
+/// The policy fields sit next to the digest in the file. They are parsed on
+/// their own so that `KeyPolicy`'s `deny_unknown_fields` applies, which serde
+/// skips for flattened fields: a misspelled restriction must not load as no
+/// restriction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "serde_json::Map<String, serde_json::Value>")]
+pub struct HashedKey {
+    pub prefix: String,
+    /// Hex salt.
+    pub salt: String,
+    /// Hex `SHA256(salt || key)`.
+    pub sha256: String,
+    #[serde(flatten)]
+    pub policy: KeyPolicy,
+}
+
+impl TryFrom<serde_json::Map<String, serde_json::Value>> for HashedKey {
+    type Error = String;
+
+    fn try_from(mut fields: serde_json::Map<String, serde_json::Value>) -> Result<Self, String> {
+        let mut take = |name: &str| match fields.remove(name) {
+            Some(serde_json::Value::String(value)) => Ok(value),
+            Some(_) => Err(format!("{} must be a string", name)),
+            None => Err(format!("missing field `{}`", name)),
+        };
+        let (prefix, salt, sha256) = (take("prefix")?, take("salt")?, take("sha256")?);
+        let policy =
+            serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| e.to_string())?;
+        Ok(Self {
+            prefix,
+            salt,
+            sha256,
+            policy,
+        })
+    }
+}
+
+/// Restrictions attached to one key. Unset fields do not restrict.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyPolicy {
+    /// Unix seconds before which the key is rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    /// Unix seconds from which the key is rejected, including on open sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Message types the key may send after `hello`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_types: Option<BTreeSet<String>>,
+    /// Per-connection message rate, replacing `RATE_LIMIT_RPS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_rps: Option<f64>,
+    /// Per-connection burst, replacing `RATE_LIMIT_BURST`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_burst: Option<f64>,
+    /// Sessions the key may hold open at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sessions: Option<usize>,
+    /// Messages per UTC day across all sessions, replacing `IDENTITY_DAILY_QUOTA`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_quota: Option<u64>,
+    /// Messages per UTC month across all sessions, replacing `IDENTITY_MONTHLY_QUOTA`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_quota: Option<u64>,
+}
+
+/// Why a key outside its validity window was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRejection {
+    NotYetValid,
+    Expired,
+}
+
+impl fmt::Display for KeyRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyRejection::NotYetValid => f.write_str("key not yet valid"),
+            KeyRejection::Expired => f.write_str("key expired"),
+        }
+    }
+}
+
+impl KeyPolicy {
+    /// Whether the key may be used at `now` (Unix seconds).
+    pub fn check_validity(&self, now: u64) -> Result<(), KeyRejection> {
+        if self.not_before.is_some_and(|t| now < t) {
+            return Err(KeyRejection::NotYetValid);
+        }
+        if self.expires_at.is_some_and(|t| now >= t) {
+            return Err(KeyRejection::Expired);
+        }
+        Ok(())
+    }
+
+    /// Whether the key may send a message of type `message_type`.
+    pub fn allows(&self, message_type: &str) -> bool {
+        self.allowed_types
+            .as_ref()
+            .is_none_or(|types| types.contains(message_type))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if let (Some(start), Some(end)) = (self.not_before, self.expires_at) {
+            if start >= end {
+                bail!("not_before must be earlier than expires_at");
+            }
+        }
+        for message_type in self.allowed_types.iter().flatten() {
+            if !INCOMING_TYPES.contains(&message_type.as_str()) {
+                bail!("unknown message type {} in allowed_types", message_type);
+            }
+        }
+        for (name, value) in [
+            ("rate_limit_rps", self.rate_limit_rps),
+            ("rate_limit_burst", self.rate_limit_burst),
+        ] {
+            if value.is_some_and(|v| !v.is_finite() || v <= 0.0) {
+                bail!("{} must be a positive number", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The client id a key belongs to and the restrictions that come with it.
+#[derive(Debug, Clone)]
+pub struct KeyGrant {
+    pub id: String,
+    pub policy: Arc<KeyPolicy>,
+}
+
+#[derive(Debug, Clone)]
+struct StoredKey {
+    grant: KeyGrant,
+    salt: Vec<u8>,
+    digest: [u8; 32],
+}
+
+/// Keys the node accepts, indexed for constant-cost lookup.
+#[derive(Debug, Clone, Default)]
+pub struct KeyStore {
+    by_prefix: HashMap<String, StoredKey>,
+    by_digest: HashMap<[u8; 32], String>,
+    plaintext_entries: usize,
+}
+
+impl KeyStore {
+    /// Parse the contents of an `AUTH_KEYS_FILE`.
+    pub fn from_json(content: &str) -> anyhow::Result<Self> {
+        let entries: HashMap<String, KeyEntry> =
+            serde_json::from_str(content).context("keys file must be a JSON object")?;
+        let mut store = Self::default();
+        for (id, entry) in entries {
+            match entry {
+                KeyEntry::Hashed(hashed) => store
+                    .insert_hashed(id.clone(), &hashed)
+                    .with_context(|| format!("invalid entry for {}", id))?,
+                KeyEntry::Plaintext(key) => {
+                    store.insert_plaintext(id, &key);
+                    store.plaintext_entries += 1;
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    /// Keys given as `id -> key` (the `AUTH_KEYS` variable).
+    pub fn from_plaintext(keys: HashMap<String, String>) -> Self {
+        let mut store = Self::default();
+        for (id, key) in keys {
+            store.insert_plaintext(id, &key);
+        }
+        store
+    }
+
+    pub fn insert_plaintext(&mut self, id: String, key: &str) {
+        self.by_digest
+            .insert(Sha256::digest(key.as_bytes()).into(), id);
+    }
+
+    fn insert_hashed(&mut self, id: String, hashed: &HashedKey) -> anyhow::Result<()> {
+        if !is_valid_prefix(&hashed.prefix) {
+            bail!("prefix must be 8 lowercase hex characters");
+        }
+        hashed.policy.validate()?;
+        let salt = hex::decode(&hashed.salt).context("salt is not valid hex")?;
+        let digest: [u8; 32] = hex::decode(&hashed.sha256)
+            .context("sha256 is not valid hex")?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("sha256 must be 32 bytes"))?;
+        if self.by_prefix.contains_key(&hashed.prefix) {
+            bail!("prefix {} is used by more than one key", hashed.prefix);
+        }
+        let grant = KeyGrant {
+            id,
+            policy: Arc::new(hashed.policy.clone()),
+        };
+        self.by_prefix.insert(
+            hashed.prefix.clone(),
+            StoredKey {
+                grant,
+                salt,
+                digest,
+            },
+        );
+        Ok(())
+    }
+
+    /// Client id and policy of `token`, if it is a configured key.
+    pub fn validate(&self, token: &str) -> Option<KeyGrant> {
+        if let Some(stored) = key_prefix(token).and_then(|prefix| self.by_prefix.get(prefix)) {
+            let digest = salted_digest(&stored.salt, token);
+            return constant_time_equal(&digest, &stored.digest).then(|| stored.grant.clone());
+        }
+        let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        self.by_digest.get(&digest).map(|id| KeyGrant {
+            id: id.clone(),
+            policy: Arc::default(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_prefix.len() + self.by_digest.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Entries of the loaded file still holding a plaintext key.
+    pub fn plaintext_entries(&self) -> usize {
+        self.plaintext_entries
+    }
+}
+
+/// `SHA256(salt || key)`.
+pub fn salted_digest(salt: &[u8], key: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(key.as_bytes());
+    hasher.finalize().into()
+}
+
+fn constant_time_equal(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff: u8 = 0;
+    for (&x, &y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+/// The `<prefix>` of a `ltp_<prefix>_<secret>` key.
+pub fn key_prefix(token: &str) -> Option<&str> {
+    let rest = token.strip_prefix(KEY_TAG)?.strip_prefix('_')?;
+    let (prefix, secret) = rest.split_once('_')?;
+    (is_valid_prefix(prefix) && !secret.is_empty()).then_some(prefix)
+}
+
+fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.len() == 8
+        && prefix
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ltp_3f9a1c2e_8c1d6b0e4f2a97d3c5b1e8f04a6d2c9b";
+
+    fn hashed_entry(salt: &[u8]) -> serde_json::Value {
+        serde_json::json!({
+            "prefix": "3f9a1c2e",
+            "salt": hex::encode(salt),
+            "sha256": hex::encode(salted_digest(salt, KEY)),
+        })
+    }
+
+    #[test]
+    fn validates_hashed_and_plaintext_entries() {
+        let content = serde_json::json!({
+            "partner-a": hashed_entry(b"0123456789abcdef"),
+            "legacy": "supersecret",
+        })
+        .to_string();
+        let store = KeyStore::from_json(&content).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.plaintext_entries(), 1);
+        assert_eq!(store.validate(KEY).unwrap().id, "partner-a");
+        assert_eq!(store.validate("supersecret").unwrap().id, "legacy");
+        assert!(store.validate("ltp_3f9a1c2e_wrong").is_none());
+        assert!(store.validate("ltp_00000000_8c1d").is_none());
+        assert!(!content.contains(KEY));
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let bad_prefix = serde_json::json!({
+            "a": {"prefix": "XYZ", "salt": "00", "sha256": hex::encode([0u8; 32])}
+        });
+        assert!(KeyStore::from_json(&bad_prefix.to_string()).is_err());
+
+        let duplicate = serde_json::json!({
+            "a": hashed_entry(b"salt-one"),
+            "b": hashed_entry(b"salt-two"),
+        });
+        assert!(KeyStore::from_json(&duplicate.to_string()).is_err());
+
+        let mut unknown_type = hashed_entry(b"salt");
+        unknown_type["allowed_types"] = serde_json::json!(["orientaton"]);
+        assert!(
+            KeyStore::from_json(&serde_json::json!({ "a": unknown_type }).to_string()).is_err()
+        );
+
+        // A misspelled restriction must not load as an unrestricted key.
+        for (field, value) in [
+            ("expire_at", serde_json::json!(5)),
+            ("allowed_type", serde_json::json!(["heartbeat"])),
+        ] {
+            let mut typo = hashed_entry(b"salt");
+            typo[field] = value;
+            assert!(KeyStore::from_json(&serde_json::json!({ "a": typo }).to_string()).is_err());
+        }
+    }
+
+    #[test]
+    fn applies_key_policy() {
+        let mut entry = hashed_entry(b"salt");
+        entry["not_before"] = serde_json::json!(100);
+        entry["expires_at"] = serde_json::json!(200);
+        entry["allowed_types"] = serde_json::json!(["heartbeat", "route_request"]);
+        entry["max_sessions"] = serde_json::json!(2);
+        let store =
+            KeyStore::from_json(&serde_json::json!({ "partner-a": entry }).to_string()).unwrap();
+
+        let policy = store.validate(KEY).unwrap().policy;
+        assert_eq!(policy.max_sessions, Some(2));
+        assert_eq!(policy.check_validity(99), Err(KeyRejection::NotYetValid));
+        assert_eq!(policy.check_validity(150), Ok(()));
+        assert_eq!(policy.check_validity(200), Err(KeyRejection::Expired));
+        assert!(policy.allows("heartbeat"));
+        assert!(!policy.allows("orientation"));
+        assert!(KeyPolicy::default().allows("orientation"));
+    }
+}
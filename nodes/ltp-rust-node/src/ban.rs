@@ -0,0 +1,352 @@
+//! Temporary IP bans and static allow/deny lists.
+//!
+//! Violations (auth failures, oversize messages, invalid JSON, rate-limit
+//! hits) are counted per client IP within a window. Crossing the threshold
+//! for any of them bans the IP, and each further ban of the same IP doubles in
+//! length up to a cap. Banned and deny-listed IPs are refused at accept time,
+//! before any TLS or websocket work. Allow-listed IPs are never banned.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use ipnet::IpNet;
+use serde::Serialize;
+
+use crate::{read_env_u64, read_env_usize};
+
+/// Kinds of misbehaviour counted towards a ban.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    AuthFailure,
+    Oversize,
+    InvalidJson,
+    RateLimit,
+}
+
+impl Violation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Violation::AuthFailure => "auth_failure",
+            Violation::Oversize => "oversize",
+            Violation::InvalidJson => "invalid_json",
+            Violation::RateLimit => "rate_limit",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Ban thresholds and static lists. A threshold of 0 never bans.
+#[derive(Debug, Clone)]
+pub struct BanConfig {
+    pub window: Duration,
+    /// Violations within `window` that trigger a ban, indexed by `Violation`.
+    pub thresholds: [usize; 4],
+    pub base_ban: Duration,
+    pub max_ban: Duration,
+    /// How long an unbanned IP is remembered, and so how long its ban length
+    /// keeps escalating.
+    pub forget_after: Duration,
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            thresholds: [10, 5, 20, 10],
+            base_ban: Duration::from_secs(60),
+            max_ban: Duration::from_secs(24 * 3600),
+            forget_after: Duration::from_secs(24 * 3600),
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl BanConfig {
+    /// Reads the `IP_BAN_*`, `IP_ALLOW_CIDRS` and `IP_DENY_CIDRS` variables.
+    /// Panics on a malformed CIDR, like the other startup checks.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let threshold = |key: &str, violation: Violation| {
+            read_env_usize(key, defaults.thresholds[violation.index()])
+        };
+        Self {
+            window: Duration::from_secs(read_env_u64(
+                "IP_BAN_WINDOW_SECS",
+                defaults.window.as_secs(),
+            )),
+            thresholds: [
+                threshold("IP_BAN_AUTH_FAILURES", Violation::AuthFailure),
+                threshold("IP_BAN_OVERSIZE", Violation::Oversize),
+                threshold("IP_BAN_INVALID_JSON", Violation::InvalidJson),
+                threshold("IP_BAN_RATE_LIMIT", Violation::RateLimit),
+            ],
+            base_ban: Duration::from_secs(read_env_u64(
+                "IP_BAN_BASE_SECS",
+                defaults.base_ban.as_secs(),
+            )),
+            max_ban: Duration::from_secs(read_env_u64(
+                "IP_BAN_MAX_SECS",
+                defaults.max_ban.as_secs(),
+            )),
+            forget_after: Duration::from_secs(read_env_u64(
+                "IP_BAN_FORGET_SECS",
+                defaults.forget_after.as_secs(),
+            )),
+            allow: parse_cidrs("IP_ALLOW_CIDRS"),
+            deny: parse_cidrs("IP_DENY_CIDRS"),
+        }
+    }
+}
+
+/// Comma-separated CIDRs; a bare address is taken as a single-host network.
+fn parse_cidrs(key: &str) -> Vec<IpNet> {
+    let Ok(raw) = std::env::var(key) else {
+        return Vec::new();
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .unwrap_or_else(|_| panic!("Invalid CIDR {} in {}", s, key))
+        })
+        .collect()
+}
+
+/// Whether a connection from an IP may proceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    DenyListed,
+    Banned { remaining: Duration },
+}
+
+impl Admission {
+    /// Label for `ip_connections_refused_total`, if refused.
+    pub fn refusal_reason(&self) -> Option<&'static str> {
+        match self {
+            Admission::Allowed => None,
+            Admission::DenyListed => Some("deny_list"),
+            Admission::Banned { .. } => Some("banned"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IpRecord {
+    window_start: Instant,
+    counts: [usize; 4],
+    bans: u32,
+    banned_until: Option<Instant>,
+    last_reason: Option<Violation>,
+    last_seen: Instant,
+}
+
+/// A ban newly imposed by `BanList::record`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewBan {
+    pub reason: Violation,
+    pub duration: Duration,
+    /// How many times this IP has now been banned.
+    pub offense: u32,
+}
+
+/// One currently banned IP, as shown on `/admin/bans`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BanInfo {
+    pub ip: IpAddr,
+    pub reason: &'static str,
+    pub remaining_secs: u64,
+    pub offenses: u32,
+}
+
+#[derive(Debug)]
+pub struct BanList {
+    config: BanConfig,
+    records: DashMap<IpAddr, IpRecord>,
+}
+
+impl BanList {
+    pub fn new(config: BanConfig) -> Self {
+        Self {
+            config,
+            records: DashMap::new(),
+        }
+    }
+
+    fn is_allow_listed(&self, ip: IpAddr) -> bool {
+        self.config.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    pub fn admit(&self, ip: IpAddr, now: Instant) -> Admission {
+        if self.is_allow_listed(ip) {
+            return Admission::Allowed;
+        }
+        if self.config.deny.iter().any(|net| net.contains(&ip)) {
+            return Admission::DenyListed;
+        }
+        match self.records.get(&ip).and_then(|r| r.banned_until) {
+            Some(until) if until > now => Admission::Banned {
+                remaining: until - now,
+            },
+            _ => Admission::Allowed,
+        }
+    }
+
+    /// Count a violation by `ip`, returning the ban it triggers, if any.
+    pub fn record(&self, ip: IpAddr, violation: Violation, now: Instant) -> Option<NewBan> {
+        let threshold = self.config.thresholds[violation.index()];
+        if threshold == 0 || self.is_allow_listed(ip) {
+            return None;
+        }
+        let mut record = self.records.entry(ip).or_insert_with(|| IpRecord {
+            window_start: now,
+            counts: [0; 4],
+            bans: 0,
+            banned_until: None,
+            last_reason: None,
+            last_seen: now,
+        });
+        record.last_seen = now;
+        if record.banned_until.is_some_and(|until| until > now) {
+            return None;
+        }
+        if now.duration_since(record.window_start) >= self.config.window {
+            record.window_start = now;
+            record.counts = [0; 4];
+        }
+        record.counts[violation.index()] += 1;
+        if record.counts[violation.index()] < threshold {
+            return None;
+        }
+
+        record.bans = record.bans.saturating_add(1);
+        let factor = 2u32.saturating_pow(record.bans - 1);
+        let duration = self
+            .config
+            .base_ban
+            .saturating_mul(factor)
+            .min(self.config.max_ban);
+        record.banned_until = Some(now + duration);
+        record.last_reason = Some(violation);
+        record.counts = [0; 4];
+        Some(NewBan {
+            reason: violation,
+            duration,
+            offense: record.bans,
+        })
+    }
+
+    /// Currently banned IPs, longest remaining first.
+    pub fn banned(&self, now: Instant) -> Vec<BanInfo> {
+        let mut bans: Vec<BanInfo> = self
+            .records
+            .iter()
+            .filter_map(|entry| {
+                let until = entry.banned_until.filter(|until| *until > now)?;
+                Some(BanInfo {
+                    ip: *entry.key(),
+                    reason: entry.last_reason.map_or("unknown", |v| v.as_str()),
+                    remaining_secs: (until - now).as_secs(),
+                    offenses: entry.bans,
+                })
+            })
+            .collect();
+        bans.sort_by_key(|b| std::cmp::Reverse(b.remaining_secs));
+        bans
+    }
+
+    /// Forget IPs that are not banned and have been quiet for `forget_after`.
+    /// Returns how many are banned right now.
+    pub fn sweep(&self, now: Instant) -> usize {
+        self.records.retain(|_, record| {
+            record.banned_until.is_some_and(|until| until > now)
+                || now.duration_since(record.last_seen) < self.config.forget_after
+        });
+        self.records
+            .iter()
+            .filter(|r| r.banned_until.is_some_and(|until| until > now))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn config() -> BanConfig {
+        BanConfig {
+            thresholds: [2, 1, 3, 3],
+            base_ban: Duration::from_secs(10),
+            max_ban: Duration::from_secs(25),
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["192.0.2.0/24".parse().unwrap()],
+            ..BanConfig::default()
+        }
+    }
+
+    #[test]
+    fn bans_escalate_and_expire() {
+        let bans = BanList::new(config());
+        let offender = ip("203.0.113.9");
+        let t0 = Instant::now();
+
+        assert_eq!(bans.record(offender, Violation::AuthFailure, t0), None);
+        let first = bans
+            .record(offender, Violation::AuthFailure, t0)
+            .expect("second failure bans");
+        assert_eq!(first.duration, Duration::from_secs(10));
+        assert!(matches!(bans.admit(offender, t0), Admission::Banned { .. }));
+        assert_eq!(bans.banned(t0)[0].reason, "auth_failure");
+
+        let t1 = t0 + Duration::from_secs(11);
+        assert_eq!(bans.admit(offender, t1), Admission::Allowed);
+        let second = bans.record(offender, Violation::Oversize, t1).unwrap();
+        assert_eq!(second.duration, Duration::from_secs(20));
+        assert_eq!(second.offense, 2);
+
+        let t2 = t1 + Duration::from_secs(21);
+        let third = bans.record(offender, Violation::Oversize, t2).unwrap();
+        assert_eq!(third.duration, Duration::from_secs(25));
+
+        assert_eq!(bans.sweep(t2), 1);
+        let later = t2 + Duration::from_secs(25) + config().forget_after;
+        assert_eq!(bans.sweep(later), 0);
+        assert!(bans.banned(later).is_empty());
+    }
+
+    #[test]
+    fn violations_outside_the_window_do_not_add_up() {
+        let bans = BanList::new(config());
+        let offender = ip("203.0.113.9");
+        let t0 = Instant::now();
+        assert_eq!(bans.record(offender, Violation::AuthFailure, t0), None);
+        let t1 = t0 + config().window;
+        assert_eq!(bans.record(offender, Violation::AuthFailure, t1), None);
+    }
+
+    #[test]
+    fn applies_static_lists() {
+        let bans = BanList::new(config());
+        let t0 = Instant::now();
+        assert_eq!(bans.admit(ip("192.0.2.7"), t0), Admission::DenyListed);
+
+        let trusted = ip("10.1.2.3");
+        for _ in 0..5 {
+            assert_eq!(bans.record(trusted, Violation::Oversize, t0), None);
+        }
+        assert_eq!(bans.admit(trusted, t0), Admission::Allowed);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use rand::{rngs::OsRng, RngCore};
+
+// Shared with the node so generated entries are checked by the same code
+// that loads them.
+#[allow(dead_code)]
+#[path = "../auth_keys.rs"]
+mod auth_keys;
+
+use auth_keys::{HashedKey, KeyEntry, KeyPolicy, KeyStore, KEY_TAG};
+
+const USAGE: &str = "\
+Usage: auth_keygen <client_id> [options]
+
+Generates an API key of the form ltp_<prefix>_<secret> and its AUTH_KEYS_FILE
+entry, which stores only a salted SHA-256 of the key. The key is printed once
+and cannot be recovered from the entry.
+
+Options:
+  --keys-file <file>          Add (or replace) the entry for <client_id> in this
+                              file instead of printing it
+  --not-before <unix_secs>    Reject the key before this time
+  --expires-at <unix_secs>    Reject the key from this time on
+  --allow <type,...>          Message types the key may send after hello
+                              (heartbeat, orientation, route_request)
+  --rate-limit-rps <n>        Per-connection message rate for this key
+  --rate-limit-burst <n>      Per-connection burst for this key
+  --max-sessions <n>          Sessions the key may hold open at once
+  --daily-quota <n>           Messages per UTC day across all sessions
+  --monthly-quota <n>         Messages per UTC month across all sessions";
+
+struct Args {
+    client_id: String,
+    keys_file: Option<PathBuf>,
+    policy: KeyPolicy,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T> {
+    value
+        .parse::<T>()
+        .map_err(|_| anyhow::anyhow!("{} expects a number, got {}", flag, value))
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let (client_id, rest) = args.split_first().context("missing client id")?;
+    if client_id.starts_with("--") {
+        bail!("missing client id");
+    }
+    let mut parsed = Args {
+        client_id: client_id.clone(),
+        keys_file: None,
+        policy: KeyPolicy::default(),
+    };
+    let policy = &mut parsed.policy;
+    let mut iter = rest.iter();
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .with_context(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--keys-file" => parsed.keys_file = Some(PathBuf::from(value)),
+            "--allow" => {
+                let types: BTreeSet<String> =
+                    value.split(',').map(|t| t.trim().to_string()).collect();
+                policy.allowed_types = Some(types);
+            }
+            "--not-before" => policy.not_before = Some(parse_number(flag, value)?),
+            "--expires-at" => policy.expires_at = Some(parse_number(flag, value)?),
+            "--rate-limit-rps" => policy.rate_limit_rps = Some(parse_number(flag, value)?),
+            "--rate-limit-burst" => policy.rate_limit_burst = Some(parse_number(flag, value)?),
+            "--max-sessions" => policy.max_sessions = Some(parse_number(flag, value)?),
+            "--daily-quota" => policy.daily_quota = Some(parse_number(flag, value)?),
+            "--monthly-quota" => policy.monthly_quota = Some(parse_number(flag, value)?),
+            other => bail!("unknown option {}", other),
+        }
+    }
+    Ok(parsed)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// A new key and its keys file entry, avoiding the prefixes in `taken`.
+fn generate(policy: &KeyPolicy, taken: &BTreeSet<String>) -> (String, HashedKey) {
+    let prefix = loop {
+        let prefix = random_hex(4);
+        if !taken.contains(&prefix) {
+            break prefix;
+        }
+    };
+    let key = format!("{}_{}_{}", KEY_TAG, prefix, random_hex(24));
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let entry = HashedKey {
+        prefix,
+        salt: hex::encode(salt),
+        sha256: hex::encode(auth_keys::salted_digest(&salt, &key)),
+        policy: policy.clone(),
+    };
+    (key, entry)
+}
+
+/// The keys file as a JSON object, empty if it does not exist yet.
+fn read_keys(path: &PathBuf) -> Result<serde_json::Map<String, serde_json::Value>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => match serde_json::from_str(&content)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?
+        {
+            serde_json::Value::Object(map) => Ok(map),
+            _ => bail!("{} must contain a JSON object", path.display()),
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(serde_json::Map::new()),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Prefixes of hashed entries other than the one for `client_id`, which is
+/// about to be replaced.
+fn taken_prefixes(
+    keys: &serde_json::Map<String, serde_json::Value>,
+    client_id: &str,
+) -> BTreeSet<String> {
+    keys.iter()
+        .filter(|(id, _)| id.as_str() != client_id)
+        .filter_map(|(_, entry)| entry.get("prefix")?.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Insert `entry` and check that the node would load the result.
+fn merge(
+    keys: &mut serde_json::Map<String, serde_json::Value>,
+    client_id: &str,
+    entry: HashedKey,
+) -> Result<bool> {
+    let value = serde_json::to_value(KeyEntry::Hashed(entry))?;
+    let replaced = keys.insert(client_id.to_string(), value).is_some();
+    KeyStore::from_json(&serde_json::Value::Object(keys.clone()).to_string())
+        .context("the node would reject the resulting keys file")?;
+    Ok(replaced)
+}
+
+fn write_keys(path: &PathBuf, keys: &serde_json::Map<String, serde_json::Value>) -> Result<()> {
+    // Write next to the file and rename so the node never reloads a partial
+    // file. The copy gets the original's permissions before any content.
+    let tmp = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    match std::fs::metadata(path) {
+        Ok(meta) => file
+            .set_permissions(meta.permissions())
+            .with_context(|| format!("Failed to set permissions on {}", tmp.display()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+    file.write_all((serde_json::to_string_pretty(keys)? + "\n").as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+    let args = match parse_args(&raw) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let mut keys = match &args.keys_file {
+        Some(path) => read_keys(path)?,
+        None => serde_json::Map::new(),
+    };
+    let (key, entry) = generate(&args.policy, &taken_prefixes(&keys, &args.client_id));
+    let replaced = merge(&mut keys, &args.client_id, entry)?;
+    match &args.keys_file {
+        Some(path) => {
+            write_keys(path, &keys)?;
+            eprintln!(
+                "{} key for {} in {}",
+                if replaced { "Replaced" } else { "Added" },
+                args.client_id,
+                path.display()
+            );
+        }
+        None => {
+            eprintln!("AUTH_KEYS_FILE entry:");
+            eprintln!("{}", serde_json::to_string_pretty(&keys)?);
+        }
+    }
+    eprintln!("API key (shown once):");
+    println!("{}", key);
+    Ok(())
+}
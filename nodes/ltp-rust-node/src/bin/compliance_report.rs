@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::VerifyingKey;
+use ltp_trace::{ComplianceCheck, TraceReader};
+
+const USAGE: &str = "\
+Usage: compliance_report <trace_file.jsonl> [options]
+
+Writes a report matching schemas/ltp-fintech-compliance-report.v0.1.json and
+exits with status 1 if the trace does not pass.
+
+Options:
+  --keyring <file>        JSON object of key id -> ed25519 public key (hex)
+  --auth-keys <file>      AUTH_KEYS_FILE of the node; its ids are the known identities
+  --node-version <name>   Node that wrote the trace (default: ltp-rust-node@<this version>)
+  --out <file>            Write the report to a file instead of stdout";
+
+struct Args {
+    path: PathBuf,
+    keyring: Option<PathBuf>,
+    auth_keys: Option<PathBuf>,
+    node_version: String,
+    out: Option<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let (path, rest) = args.split_first().context("missing trace file")?;
+    let mut parsed = Args {
+        path: PathBuf::from(path),
+        keyring: None,
+        auth_keys: None,
+        node_version: concat!("ltp-rust-node@", env!("CARGO_PKG_VERSION")).to_string(),
+        out: None,
+    };
+
+    let mut iter = rest.iter();
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .with_context(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--keyring" => parsed.keyring = Some(PathBuf::from(value)),
+            "--auth-keys" => parsed.auth_keys = Some(PathBuf::from(value)),
+            "--node-version" => parsed.node_version = value.clone(),
+            "--out" => parsed.out = Some(PathBuf::from(value)),
+            other => bail!("unknown option {}", other),
+        }
+    }
+    Ok(parsed)
+}
+
+fn read_json_object(path: &PathBuf) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    match serde_json::from_str(&content)
+        .with_context(|| format!("{} is not valid JSON", path.display()))?
+    {
+        serde_json::Value::Object(map) => Ok(map),
+        _ => bail!("{} must contain a JSON object", path.display()),
+    }
+}
+
+fn load_keyring(path: &PathBuf) -> Result<BTreeMap<String, VerifyingKey>> {
+    read_json_object(path)?
+        .into_iter()
+        .map(|(key_id, value)| {
+            let key_hex = value
+                .as_str()
+                .with_context(|| format!("key {} must be a hex string", key_id))?;
+            let bytes: [u8; 32] = hex::decode(key_hex)
+                .with_context(|| format!("key {} is not valid hex", key_id))?
+                .as_slice()
+                .try_into()
+                .with_context(|| format!("key {} must be 32 bytes", key_id))?;
+            let key = VerifyingKey::from_bytes(&bytes)
+                .with_context(|| format!("key {} is not a valid ed25519 point", key_id))?;
+            Ok((key_id, key))
+        })
+        .collect()
+}
+
+fn main() -> Result<()> {
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+    let args = match parse_args(&raw) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let mut check = ComplianceCheck::new(args.node_version.clone());
+    if let Some(path) = &args.keyring {
+        check = check.with_keyring(load_keyring(path)?);
+    }
+    if let Some(path) = &args.auth_keys {
+        check = check.with_known_identities(read_json_object(path)?.into_iter().map(|(id, _)| id));
+    }
+
+    let reader = TraceReader::open(&args.path)
+        .with_context(|| format!("Failed to open {}", args.path.display()))?;
+    let report = check.run(reader);
+
+    let rendered = serde_json::to_string_pretty(&report)?;
+    match &args.out {
+        Some(path) => std::fs::write(path, rendered + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => println!("{}", rendered),
+    }
+
+    for (session_id, reason) in &report.identity_binding.violations {
+        eprintln!("identity binding: session {}: {}", session_id, reason);
+    }
+    if let Some(reason) = &report.trace_integrity.violation {
+        eprintln!("trace integrity: {}", reason);
+    }
+    if !report.passed() {
+        eprintln!("Compliance check FAILED");
+        std::process::exit(1);
+    }
+    eprintln!("Compliance check PASSED");
+    Ok(())
+}
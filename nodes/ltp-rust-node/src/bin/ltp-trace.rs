@@ -0,0 +1,271 @@
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use ltp_trace::{
+    export_session, verify_disclosure, RedactionPolicy, Redactor, SessionTimelines, TraceFilter,
+    TraceReader, TraceStats,
+};
+
+const USAGE: &str = "\
+Usage: ltp-trace <command> <trace_file.jsonl> [options]
+
+Commands:
+  filter     Print matching entries as JSONL
+  timeline   Print per-session timelines
+  stats      Print frame, session and error statistics (--json for JSON)
+  export     Export one session in the canonical/trace.v0.1.json shape
+  disclose   Check a disclosed value against a redaction commitment
+
+Filter options (all commands):
+  --session <id>        Only entries of this session
+  --direction <in|out>  Only inbound or outbound frames
+  --since <epoch_ms>    Only entries at or after this time
+  --until <epoch_ms>    Only entries at or before this time
+  --type <frame_type>   Only frames with this `type` (e.g. route_request)
+
+Export options:
+  --out <file>          Write to a file instead of stdout (requires --session)
+
+Disclose options:
+  --index <i>           Entry index (`i`) holding the commitment
+  --pointer <ptr>       Redacted JSON pointer (e.g. /api_key)
+  --value <json>        Disclosed original value, as JSON (e.g. '\"k-123\"')
+  --salt <hex>          Disclosed salt; derived from LTP_TRACE_REDACTION_KEY if omitted";
+
+struct Args {
+    command: String,
+    path: PathBuf,
+    filter: TraceFilter,
+    json: bool,
+    out: Option<PathBuf>,
+    index: Option<u64>,
+    pointer: Option<String>,
+    value: Option<String>,
+    salt: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let (command, path, rest) = match args {
+        [command, path, rest @ ..] => (command.clone(), PathBuf::from(path), rest),
+        _ => bail!("missing command or trace file"),
+    };
+
+    let mut parsed = Args {
+        command,
+        path,
+        filter: TraceFilter::default(),
+        json: false,
+        out: None,
+        index: None,
+        pointer: None,
+        value: None,
+        salt: None,
+    };
+
+    let mut iter = rest.iter();
+    while let Some(flag) = iter.next() {
+        if flag == "--json" {
+            parsed.json = true;
+            continue;
+        }
+        let value = iter
+            .next()
+            .with_context(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--session" => parsed.filter.session_id = Some(value.clone()),
+            "--direction" => parsed.filter.direction = Some(value.clone()),
+            "--since" => {
+                parsed.filter.since_ms = Some(value.parse().context("--since expects epoch ms")?)
+            }
+            "--until" => {
+                parsed.filter.until_ms = Some(value.parse().context("--until expects epoch ms")?)
+            }
+            "--type" => parsed.filter.frame_type = Some(value.clone()),
+            "--out" => parsed.out = Some(PathBuf::from(value)),
+            "--index" => parsed.index = Some(value.parse().context("--index expects an integer")?),
+            "--pointer" => parsed.pointer = Some(value.clone()),
+            "--value" => parsed.value = Some(value.clone()),
+            "--salt" => parsed.salt = Some(value.clone()),
+            other => bail!("unknown option {}", other),
+        }
+    }
+    Ok(parsed)
+}
+
+fn for_each_match(
+    args: &Args,
+    mut f: impl FnMut(ltp_trace::TraceEntry) -> Result<()>,
+) -> Result<()> {
+    let reader = TraceReader::open(&args.path)
+        .with_context(|| format!("Failed to open {}", args.path.display()))?;
+    for record in reader {
+        let record = record?;
+        if args.filter.matches(&record.entry) {
+            f(record.entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_filter(args: &Args) -> Result<()> {
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for_each_match(args, |entry| {
+        serde_json::to_writer(&mut out, &entry)?;
+        out.write_all(b"\n")?;
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(())
+}
+
+fn run_timeline(args: &Args) -> Result<()> {
+    let mut timelines = SessionTimelines::default();
+    for_each_match(args, |entry| {
+        timelines.record(&entry);
+        Ok(())
+    })?;
+
+    for (session_id, events) in timelines.iter() {
+        println!("session {} ({} frames)", session_id, events.len());
+        for event in events {
+            println!(
+                "  +{:>8}ms  i={:<8} {:<3} {}",
+                event.offset_ms, event.i, event.direction, event.frame_type
+            );
+        }
+    }
+    Ok(())
+}
+
+fn run_stats(args: &Args) -> Result<()> {
+    let mut stats = TraceStats::default();
+    for_each_match(args, |entry| {
+        stats.record(&entry);
+        Ok(())
+    })?;
+
+    if args.json {
+        let mut value = serde_json::to_value(&stats)?;
+        value["sessions"] = stats.session_count().into();
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    println!("entries:  {}", stats.entries);
+    println!("sessions: {}", stats.session_count());
+    if let (Some(first), Some(last)) = (stats.first_timestamp_ms, stats.last_timestamp_ms) {
+        println!("span:     {} .. {} ({} ms)", first, last, last - first);
+    }
+    println!("frames by direction:");
+    for (direction, count) in &stats.frames_by_direction {
+        println!("  {:<20} {}", direction, count);
+    }
+    println!("frames by type:");
+    for (frame_type, count) in &stats.frames_by_type {
+        println!("  {:<20} {}", frame_type, count);
+    }
+    println!("error frames: {}", stats.error_frames);
+    for (code, count) in &stats.errors_by_code {
+        println!("  {:<20} {}", code, count);
+    }
+    Ok(())
+}
+
+fn run_export(args: &Args) -> Result<()> {
+    let session_id = args
+        .filter
+        .session_id
+        .clone()
+        .context("export requires --session <id>")?;
+
+    let mut entries = Vec::new();
+    for_each_match(args, |entry| {
+        entries.push(entry);
+        Ok(())
+    })?;
+    if entries.is_empty() {
+        bail!("no entries found for session {}", session_id);
+    }
+
+    let exported = serde_json::to_string_pretty(&export_session(&session_id, &entries))?;
+    match &args.out {
+        Some(path) => std::fs::write(path, exported + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => println!("{}", exported),
+    }
+    Ok(())
+}
+
+fn run_disclose(args: &Args) -> Result<()> {
+    let index = args.index.context("disclose requires --index <i>")?;
+    let pointer = args
+        .pointer
+        .as_deref()
+        .context("disclose requires --pointer <ptr>")?;
+    let value: serde_json::Value = serde_json::from_str(
+        args.value
+            .as_deref()
+            .context("disclose requires --value <json>")?,
+    )
+    .context("--value must be JSON (quote strings, e.g. '\"k-123\"')")?;
+
+    let salt = match &args.salt {
+        Some(salt) => salt.clone(),
+        None => {
+            let key_hex = std::env::var("LTP_TRACE_REDACTION_KEY")
+                .context("pass --salt or set LTP_TRACE_REDACTION_KEY")?;
+            let key =
+                hex::decode(key_hex.trim()).context("LTP_TRACE_REDACTION_KEY is not valid hex")?;
+            Redactor::new(RedactionPolicy::default(), key).salt_for(index, pointer)
+        }
+    };
+
+    let reader = TraceReader::open(&args.path)
+        .with_context(|| format!("Failed to open {}", args.path.display()))?;
+    for record in reader {
+        let entry = record?.entry;
+        if entry.i != index {
+            continue;
+        }
+        let commitment = entry
+            .redactions
+            .get(pointer)
+            .with_context(|| format!("entry {} has no redaction at {}", index, pointer))?;
+        if !verify_disclosure(commitment, &salt, &value) {
+            bail!("value does not match commitment {}", commitment);
+        }
+        println!(
+            "Disclosure verified: entry {} {} = {}",
+            index, pointer, value
+        );
+        println!("commitment: {}", commitment);
+        println!("salt:       {}", salt);
+        return Ok(());
+    }
+    bail!("entry {} not found", index)
+}
+
+fn main() -> Result<()> {
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+    let args = match parse_args(&raw) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    match args.command.as_str() {
+        "filter" => run_filter(&args),
+        "timeline" => run_timeline(&args),
+        "stats" => run_stats(&args),
+        "export" => run_export(&args),
+        "disclose" => run_disclose(&args),
+        other => {
+            eprintln!("error: unknown command {}\n\n{}", other, USAGE);
+            std::process::exit(1);
+        }
+    }
+}
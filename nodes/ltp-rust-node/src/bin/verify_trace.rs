@@ -1,115 +1,82 @@
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sha2::{Digest, Sha256};
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use ed25519_dalek::VerifyingKey;
+use ltp_trace::{SessionVerifier, TraceReader, TraceVerifier};
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TraceEntry {
-    pub i: u64,
-    pub timestamp_ms: u64,
-    pub direction: String,
-    pub session_id: String,
-    pub frame: Value,
-    pub prev_hash: String,
-    pub hash: String,
+fn parse_public_key(key_hex: &str) -> Result<VerifyingKey> {
+    let bytes = hex::decode(key_hex).context("public key is not valid hex")?;
+    let bytes: [u8; 32] = bytes
+        .as_slice()
+        .try_into()
+        .context("public key must be 32 bytes")?;
+    VerifyingKey::from_bytes(&bytes).context("public key is not a valid ed25519 point")
 }
 
-fn canonicalize_json(v: &Value) -> Value {
-    match v {
-        Value::Object(map) => {
-            let mut sorted = BTreeMap::new();
-            for (k, val) in map.iter() {
-                sorted.insert(k.clone(), canonicalize_json(val));
-            }
-            let mut new_map = serde_json::Map::new();
-            for (k, val) in sorted {
-                new_map.insert(k, val);
-            }
-            Value::Object(new_map)
-        }
-        Value::Array(arr) => Value::Array(arr.iter().map(canonicalize_json).collect()),
-        _ => v.clone(),
-    }
-}
-
-fn canonical_json_bytes(frame: &Value) -> Result<Vec<u8>> {
-    let canon = canonicalize_json(frame);
-    let bytes = serde_json::to_vec(&canon)?;
-    Ok(bytes)
-}
-
-fn verify_trace_file(path: PathBuf) -> Result<()> {
-    let file = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
-    let reader = BufReader::new(file);
-
-    let mut prev_hash = "0".repeat(64);
-    let mut expected_i = 0;
-
-    for (line_num, line) in reader.lines().enumerate() {
-        let line = line?;
-        if line.trim().is_empty() {
-            continue;
-        }
-
-        let entry: TraceEntry = serde_json::from_str(&line)
-            .with_context(|| format!("Failed to parse line {}", line_num + 1))?;
+fn verify_trace_file(
+    path: PathBuf,
+    public_key: Option<VerifyingKey>,
+    session: Option<String>,
+) -> Result<()> {
+    let reader =
+        TraceReader::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
 
-        if entry.i != expected_i {
-            anyhow::bail!(
-                "Sequence break at line {}: expected i={}, got i={}",
-                line_num + 1,
-                expected_i,
-                entry.i
-            );
-        }
-
-        if entry.prev_hash != prev_hash {
-            anyhow::bail!(
-                "Hash chain broken at line {} (i={}): prev_hash mismatch.\nExpected: {}\nGot:      {}",
-                line_num + 1,
-                entry.i,
-                prev_hash,
-                entry.prev_hash
-            );
+    let report = match &session {
+        Some(session_id) => {
+            let mut verifier = SessionVerifier::new(session_id.clone());
+            if let Some(key) = public_key {
+                verifier = verifier.with_verifying_key(key);
+            }
+            verifier.verify_reader(reader)?
         }
-
-        let frame_bytes = canonical_json_bytes(&entry.frame)?;
-        let mut hasher = Sha256::new();
-        hasher.update(prev_hash.as_bytes());
-        hasher.update(&frame_bytes);
-        let computed_hash = format!("{:x}", hasher.finalize());
-
-        if computed_hash != entry.hash {
-            anyhow::bail!(
-                "Integrity check failed at line {} (i={}).\nExpected hash: {}\nActual hash:   {}",
-                line_num + 1,
-                entry.i,
-                computed_hash,
-                entry.hash
-            );
+        None => {
+            let mut verifier = TraceVerifier::new();
+            if let Some(key) = public_key {
+                verifier = verifier.with_verifying_key(key);
+            }
+            verifier.verify_reader(reader)?
         }
+    };
 
-        prev_hash = entry.hash;
-        expected_i += 1;
+    match &session {
+        Some(session_id) => println!(
+            "Session {} verified successfully. {} entries processed.",
+            session_id, report.entries
+        ),
+        None => println!(
+            "Trace verified successfully. {} entries processed.",
+            report.entries
+        ),
     }
+    if report.signed_entries > 0 {
+        println!("{} entries carry signatures.", report.signed_entries);
+    }
+    Ok(())
+}
 
-    println!(
-        "Trace verified successfully. {} entries processed.",
-        expected_i
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} <trace_file.jsonl> [--public-key <ed25519_hex>] [--session <id>]",
+        program
     );
-    Ok(())
+    std::process::exit(1);
 }
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <trace_file.jsonl>", args[0]);
-        std::process::exit(1);
+    let Some(path) = args.get(1) else {
+        usage(&args[0]);
+    };
+
+    let mut public_key = None;
+    let mut session = None;
+    let mut rest = args[2..].iter();
+    while let Some(flag) = rest.next() {
+        match (flag.as_str(), rest.next()) {
+            ("--public-key", Some(key)) => public_key = Some(parse_public_key(key)?),
+            ("--session", Some(id)) => session = Some(id.clone()),
+            _ => usage(&args[0]),
+        }
     }
 
-    verify_trace_file(PathBuf::from(&args[1]))
+    verify_trace_file(PathBuf::from(path), public_key, session)
 }
@@ -1,5 +1,9 @@
+mod auth_keys;
+mod ban;
 mod node;
-mod protocol;
+mod proxy;
+mod quota;
+mod replay;
 mod state;
 #[cfg(test)]
 mod tests;
@@ -10,15 +14,16 @@ use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
 use axum::{http::StatusCode, routing::get, Router};
 use dashmap::DashMap;
 use futures_util::{SinkExt, StreamExt};
+use ltp_protocol::node::{self as protocol, Correlated, LtpIncomingMessage, LtpOutgoingMessage};
 use prometheus::{Encoder, IntCounter, IntCounterVec, IntGauge, Registry, TextEncoder};
-use protocol::{LtpIncomingMessage, LtpOutgoingMessage};
 use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::sync::watch;
@@ -34,9 +39,15 @@ use tokio_tungstenite::{
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::auth_keys::{KeyGrant, KeyPolicy, KeyStore};
+use crate::ban::{BanConfig, BanList, Violation};
 use crate::node::build_route_suggestion;
+use crate::quota::{IdentityLimits, IdentityQuota};
 use crate::state::LtpNodeState;
 use crate::trace::TraceLogger;
+use ltp_trace::{
+    BatchStats, SessionClose, SessionOpen, SessionRecord, WriterObserver, NODE_DIRECTION,
+};
 
 const DEFAULT_ADDR: &str = "127.0.0.1:7070";
 const DEFAULT_METRICS_ADDR: &str = "127.0.0.1:9090";
@@ -52,6 +63,8 @@ struct Config {
     node_id: String,
     metrics_addr: String,
     max_connections: usize,
+    /// Concurrent connections per client IP; 0 means unlimited.
+    max_connections_per_ip: usize,
     max_message_bytes: usize,
     max_sessions_total: usize,
     handshake_timeout_ms: u64,
@@ -62,14 +75,34 @@ struct Config {
     ip_rate_limit_rps: f64,
     ip_rate_limit_burst: f64,
     ip_rate_limit_ttl_secs: u64,
+    identity_rate_limit_rps: f64,
+    identity_rate_limit_burst: f64,
+    /// Messages per identity per UTC day; 0 means unlimited.
+    identity_daily_quota: u64,
+    /// Messages per identity per UTC month; 0 means unlimited.
+    identity_monthly_quota: u64,
+    ip_bans: BanConfig,
     auth: AuthConfig,
     trust_proxy: bool,
+    /// Expect a PROXY protocol v1/v2 header on every accepted connection.
+    proxy_protocol: bool,
     audit_log_file: String,
     allow_proxy_cidr: Vec<ipnet::IpNet>,
 }
 
 impl Config {
     fn from_env() -> Self {
+        Self::load(true)
+    }
+
+    /// Configuration for `replay`, which never listens, so the bind and
+    /// proxy exposure checks are skipped rather than fatal.
+    fn for_replay() -> Self {
+        Self::load(false)
+    }
+
+    /// Read the configuration; `serving` enforces the exposure checks.
+    fn load(serving: bool) -> Self {
         let addr = std::env::var("LTP_NODE_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
 
         // P1-2: Hard Non-Exposure Guarantee
@@ -79,7 +112,7 @@ impl Config {
             .unwrap_or(false);
 
         // Check if addr is 0.0.0.0 (any) and not allowed
-        if !is_unsafe_bind_allowed {
+        if serving && !is_unsafe_bind_allowed {
             // Very basic check. For more robustness could parse IpAddr.
             if addr.contains("0.0.0.0") || addr.contains("[::]") {
                 panic!("FATAL: Binding to 0.0.0.0 is prohibited by default (Fintech P1 Safety). Set LTP_ALLOW_UNSAFE_EXPOSE=true if you really mean it.");
@@ -91,6 +124,7 @@ impl Config {
         let metrics_addr = std::env::var("LTP_NODE_METRICS_ADDR")
             .unwrap_or_else(|_| DEFAULT_METRICS_ADDR.to_string());
         let max_connections = read_env_usize("LTP_NODE_MAX_CONNECTIONS", 10_000);
+        let max_connections_per_ip = read_env_usize("LTP_NODE_MAX_CONNECTIONS_PER_IP", 100);
         let max_message_bytes = read_env_usize(
             "LTP_NODE_MAX_MESSAGE_BYTES",
             read_env_usize("MAX_MESSAGE_BYTES", DEFAULT_MAX_MESSAGE_BYTES),
@@ -104,19 +138,28 @@ impl Config {
         let ip_rate_limit_rps = read_env_f64("IP_RATE_LIMIT_RPS", 5.0);
         let ip_rate_limit_burst = read_env_f64("IP_RATE_LIMIT_BURST", 10.0);
         let ip_rate_limit_ttl_secs = read_env_u64("IP_RATE_LIMIT_TTL_SECS", 600);
+        let identity_rate_limit_rps = read_env_f64("IDENTITY_RATE_LIMIT_RPS", 50.0);
+        let identity_rate_limit_burst = read_env_f64("IDENTITY_RATE_LIMIT_BURST", 100.0);
+        let identity_daily_quota = read_env_u64("IDENTITY_DAILY_QUOTA", 0);
+        let identity_monthly_quota = read_env_u64("IDENTITY_MONTHLY_QUOTA", 0);
+        let ip_bans = BanConfig::from_env();
         let trust_proxy = std::env::var("TRUST_PROXY")
             .ok()
             .map(|v| v.to_lowercase() == "true")
             .unwrap_or(false);
+        let proxy_protocol = std::env::var("PROXY_PROTOCOL")
+            .ok()
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
 
         // P1-2: TRUST_PROXY safety
-        let allow_proxy_cidr = if trust_proxy {
+        let allow_proxy_cidr = if serving && (trust_proxy || proxy_protocol) {
             if let Ok(cidrs_str) = std::env::var("LTP_ALLOW_PROXY_CIDR") {
                 cidrs_str.split(',')
                     .map(|s| s.trim().parse().expect("Invalid CIDR in LTP_ALLOW_PROXY_CIDR"))
                     .collect()
             } else {
-                 panic!("FATAL: TRUST_PROXY=true requires LTP_ALLOW_PROXY_CIDR to be set (Fintech P1 Safety).");
+                 panic!("FATAL: TRUST_PROXY=true or PROXY_PROTOCOL=true requires LTP_ALLOW_PROXY_CIDR to be set (Fintech P1 Safety).");
             }
         } else {
             vec![]
@@ -131,6 +174,7 @@ impl Config {
             node_id,
             metrics_addr,
             max_connections,
+            max_connections_per_ip,
             max_message_bytes,
             max_sessions_total,
             handshake_timeout_ms,
@@ -141,14 +185,27 @@ impl Config {
             ip_rate_limit_rps,
             ip_rate_limit_burst,
             ip_rate_limit_ttl_secs,
+            identity_rate_limit_rps,
+            identity_rate_limit_burst,
+            identity_daily_quota,
+            identity_monthly_quota,
+            ip_bans,
             auth,
             trust_proxy,
+            proxy_protocol,
             audit_log_file,
             allow_proxy_cidr,
         }
     }
 }
 
+impl Config {
+    /// Whether the TCP peer is a proxy rather than the client itself.
+    fn behind_proxy(&self) -> bool {
+        self.trust_proxy || self.proxy_protocol
+    }
+}
+
 fn read_env_usize(key: &str, default: usize) -> usize {
     std::env::var(key)
         .ok()
@@ -185,10 +242,20 @@ enum IdentitySource {
     MtlsSubject,
 }
 
+impl IdentitySource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IdentitySource::Off => "off",
+            IdentitySource::ApiKey => "api_key",
+            IdentitySource::MtlsSubject => "mtls_subject",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct AuthConfig {
     mode: AuthMode,
-    keys: Arc<std::sync::RwLock<HashMap<String, String>>>,
+    keys: Arc<std::sync::RwLock<KeyStore>>,
     jwt_secret: Option<String>,
     keys_file: Option<String>,
     keys_reload_interval: Duration,
@@ -213,7 +280,7 @@ impl AuthConfig {
             DEFAULT_AUTH_KEYS_RELOAD_SECS,
         ));
         let keys_file = std::env::var("AUTH_KEYS_FILE").ok();
-        let mut source_map = HashMap::new();
+        let mut source_map = KeyStore::default();
 
         let fail_closed = Arc::new(AtomicBool::new(false));
         if let Some(path) = keys_file.as_ref() {
@@ -238,7 +305,7 @@ impl AuthConfig {
         }
 
         if let Ok(raw_keys) = std::env::var("AUTH_KEYS") {
-            source_map.extend(parse_keys(&raw_keys));
+            source_map = KeyStore::from_plaintext(parse_keys(&raw_keys));
         }
 
         if matches!(mode, AuthMode::Jwt) {
@@ -304,11 +371,18 @@ impl AuthConfig {
         });
     }
 
-    fn load_keys_file(path: &str) -> anyhow::Result<(HashMap<String, String>, u64)> {
+    fn load_keys_file(path: &str) -> anyhow::Result<(KeyStore, u64)> {
         let content = std::fs::read_to_string(path)?;
-        let map = serde_json::from_str::<HashMap<String, String>>(&content)?;
+        let store = KeyStore::from_json(&content)?;
+        if store.plaintext_entries() > 0 {
+            warn!(
+                file = %path,
+                plaintext_keys = store.plaintext_entries(),
+                "AUTH_KEYS_FILE holds plaintext keys; reissue them with auth_keygen"
+            );
+        }
         let hash = hash_string(&content);
-        Ok((map, hash))
+        Ok((store, hash))
     }
 
     fn auth_enabled(&self) -> bool {
@@ -340,7 +414,19 @@ impl AuthConfig {
                         "missing api key".to_string(),
                     ))
                 })?;
-                self.validate_api_key(&token)
+                let Some(grant) = self.validate_api_key(&token)? else {
+                    return Ok(None);
+                };
+                grant
+                    .policy
+                    .check_validity(unix_now_secs())
+                    .map_err(|rejection| {
+                        Box::new(build_error_response(
+                            http::StatusCode::UNAUTHORIZED,
+                            rejection.to_string(),
+                        ))
+                    })?;
+                Ok(Some(grant.id))
             }
             AuthMode::Jwt => {
                 let _ = &self.jwt_secret;
@@ -352,14 +438,9 @@ impl AuthConfig {
         }
     }
 
-    fn validate_api_key(&self, token: &str) -> Result<Option<String>, Box<ErrorResponse>> {
+    fn validate_api_key(&self, token: &str) -> Result<Option<KeyGrant>, Box<ErrorResponse>> {
         let keys_guard = self.keys.read().expect("auth keys poisoned");
-        for (id, key) in keys_guard.iter() {
-            if constant_time_equal(key.as_bytes(), token.as_bytes()) {
-                return Ok(Some(id.clone()));
-            }
-        }
-        Ok(None)
+        Ok(keys_guard.validate(token))
     }
 }
 
@@ -370,17 +451,6 @@ fn auth_id_for_key(api_key: &str) -> String {
     format!("{:016x}", hasher.finish())
 }
 
-fn constant_time_equal(a: &[u8], b: &[u8]) -> bool {
-    if a.len() != b.len() {
-        return false;
-    }
-    let mut diff: u8 = 0;
-    for (&x, &y) in a.iter().zip(b.iter()) {
-        diff |= x ^ y;
-    }
-    diff == 0
-}
-
 fn extract_api_key(headers: &http::HeaderMap) -> Option<String> {
     if let Some(value) = headers.get("x-api-key") {
         if let Ok(val) = value.to_str() {
@@ -403,16 +473,6 @@ fn extract_api_key(headers: &http::HeaderMap) -> Option<String> {
     None
 }
 
-fn extract_forwarded_for(headers: &http::HeaderMap) -> Option<IpAddr> {
-    headers.get("x-forwarded-for").and_then(|v| {
-        v.to_str().ok().and_then(|s| {
-            s.split(',')
-                .next()
-                .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
-        })
-    })
-}
-
 fn build_error_response(status: StatusCode, message: String) -> ErrorResponse {
     Response::builder()
         .status(status)
@@ -437,8 +497,13 @@ struct Metrics {
     rate_limit_violations_total: IntCounter,
     auth_failures_total: IntCounter,
     capacity_rejections: IntCounter,
+    connections_rejected: IntCounterVec,
     oversize_messages_total: IntCounter,
     ip_rate_limit_violations_total: IntCounter,
+    identity_throttled_total: IntCounterVec,
+    ip_bans_total: IntCounterVec,
+    ip_banned_current: IntGauge,
+    ip_connections_refused_total: IntCounterVec,
     log_suppressed_total: IntCounterVec,
     auth_keys_reload_success_total: IntCounter,
     auth_keys_reload_failure_total: IntCounter,
@@ -446,6 +511,10 @@ struct Metrics {
     janitor_sweep_duration: prometheus::Histogram,
     janitor_skipped_lock: IntCounter,
     janitor_expired_last_sweep: IntGauge,
+    trace_queue_depth: IntGauge,
+    trace_queue_full_total: IntCounter,
+    trace_batch_entries: prometheus::Histogram,
+    trace_write_duration: prometheus::Histogram,
 }
 
 impl Metrics {
@@ -482,6 +551,13 @@ impl Metrics {
             "ltp_capacity_rejections_total",
             "Rejected due to capacity limits",
         )?;
+        let connections_rejected = IntCounterVec::new(
+            prometheus::Opts::new(
+                "ltp_connections_rejected_total",
+                "Connections rejected before the websocket upgrade by connection limits",
+            ),
+            &["limit"],
+        )?;
         let oversize_messages_total = IntCounter::new(
             "oversize_messages_total",
             "Messages rejected for being too large",
@@ -490,6 +566,25 @@ impl Metrics {
             "ip_rate_limit_violations_total",
             "Rate limit violations per IP",
         )?;
+        let identity_throttled_total = IntCounterVec::new(
+            prometheus::Opts::new(
+                "identity_throttled_total",
+                "Messages refused by per-identity rate limits and quotas",
+            ),
+            &["limit"],
+        )?;
+        let ip_bans_total = IntCounterVec::new(
+            prometheus::Opts::new("ip_bans_total", "Temporary IP bans imposed"),
+            &["reason"],
+        )?;
+        let ip_banned_current = IntGauge::new("ip_banned_current", "Currently banned IPs")?;
+        let ip_connections_refused_total = IntCounterVec::new(
+            prometheus::Opts::new(
+                "ip_connections_refused_total",
+                "Connections refused for banned or deny-listed IPs",
+            ),
+            &["reason"],
+        )?;
         let log_suppressed_total = IntCounterVec::new(
             prometheus::Opts::new("log_suppressed_total", "Suppressed logs due to throttling"),
             &["category"],
@@ -516,6 +611,27 @@ impl Metrics {
             "Number of sessions expired in last sweep",
         )?;
 
+        let trace_queue_depth = IntGauge::new(
+            "ltp_trace_queue_depth",
+            "Trace entries waiting for the writer after the last batch",
+        )?;
+        let trace_queue_full_total = IntCounter::new(
+            "ltp_trace_queue_full_total",
+            "Trace log calls that waited for room in the writer queue",
+        )?;
+        let trace_batch_entries = prometheus::Histogram::with_opts(
+            prometheus::HistogramOpts::new(
+                "ltp_trace_batch_entries",
+                "Entries per trace write batch",
+            )
+            .buckets(vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0]),
+        )?;
+        let trace_write_duration =
+            prometheus::Histogram::with_opts(prometheus::HistogramOpts::new(
+                "ltp_trace_write_duration_seconds",
+                "Time to write, flush and sync one trace batch",
+            ))?;
+
         registry.register(Box::new(connections.clone()))?;
         registry.register(Box::new(sessions.clone()))?;
         registry.register(Box::new(sessions_expired.clone()))?;
@@ -526,8 +642,13 @@ impl Metrics {
         registry.register(Box::new(rate_limit_violations_total.clone()))?;
         registry.register(Box::new(auth_failures_total.clone()))?;
         registry.register(Box::new(capacity_rejections.clone()))?;
+        registry.register(Box::new(connections_rejected.clone()))?;
         registry.register(Box::new(oversize_messages_total.clone()))?;
         registry.register(Box::new(ip_rate_limit_violations_total.clone()))?;
+        registry.register(Box::new(identity_throttled_total.clone()))?;
+        registry.register(Box::new(ip_bans_total.clone()))?;
+        registry.register(Box::new(ip_banned_current.clone()))?;
+        registry.register(Box::new(ip_connections_refused_total.clone()))?;
         registry.register(Box::new(log_suppressed_total.clone()))?;
         registry.register(Box::new(auth_keys_reload_success_total.clone()))?;
         registry.register(Box::new(auth_keys_reload_failure_total.clone()))?;
@@ -535,6 +656,10 @@ impl Metrics {
         registry.register(Box::new(janitor_sweep_duration.clone()))?;
         registry.register(Box::new(janitor_skipped_lock.clone()))?;
         registry.register(Box::new(janitor_expired_last_sweep.clone()))?;
+        registry.register(Box::new(trace_queue_depth.clone()))?;
+        registry.register(Box::new(trace_queue_full_total.clone()))?;
+        registry.register(Box::new(trace_batch_entries.clone()))?;
+        registry.register(Box::new(trace_write_duration.clone()))?;
 
         Ok(Self {
             registry,
@@ -548,8 +673,13 @@ impl Metrics {
             rate_limit_violations_total,
             auth_failures_total,
             capacity_rejections,
+            connections_rejected,
             oversize_messages_total,
             ip_rate_limit_violations_total,
+            identity_throttled_total,
+            ip_bans_total,
+            ip_banned_current,
+            ip_connections_refused_total,
             log_suppressed_total,
             auth_keys_reload_success_total,
             auth_keys_reload_failure_total,
@@ -557,6 +687,10 @@ impl Metrics {
             janitor_sweep_duration,
             janitor_skipped_lock,
             janitor_expired_last_sweep,
+            trace_queue_depth,
+            trace_queue_full_total,
+            trace_batch_entries,
+            trace_write_duration,
         })
     }
 
@@ -569,6 +703,19 @@ impl Metrics {
     }
 }
 
+impl WriterObserver for Metrics {
+    fn batch_written(&self, stats: &BatchStats) {
+        self.trace_queue_depth.set(stats.queue_depth as i64);
+        self.trace_batch_entries.observe(stats.entries as f64);
+        self.trace_write_duration
+            .observe(stats.write_latency.as_secs_f64());
+    }
+
+    fn queue_full(&self) {
+        self.trace_queue_full_total.inc();
+    }
+}
+
 #[derive(Clone)]
 struct AppContext {
     config: Arc<Config>,
@@ -577,6 +724,12 @@ struct AppContext {
     ip_limiters: Arc<DashMap<IpAddr, IpLimiterState>>,
     log_throttle: Arc<LogThrottle>,
     tracer: Arc<TraceLogger>,
+    /// Open sessions per auth id, for `KeyPolicy::max_sessions`.
+    key_sessions: Arc<DashMap<String, usize>>,
+    identity_limits: Arc<IdentityLimits>,
+    bans: Arc<BanList>,
+    /// Open connections per client IP, for `max_connections_per_ip`.
+    ip_connections: Arc<DashMap<IpAddr, usize>>,
 }
 
 #[derive(Debug, Clone)]
@@ -614,6 +767,17 @@ impl TokenBucket {
             false
         }
     }
+
+    /// Time until the next token is available, as of the last `allow`.
+    fn retry_after(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        if self.refill_per_sec <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -646,6 +810,94 @@ impl LogThrottle {
 struct AuthContext {
     auth_id: String,
     session_id: String,
+    policy: Arc<KeyPolicy>,
+}
+
+/// One of a key's open sessions, counted against `KeyPolicy::max_sessions`
+/// until dropped.
+#[derive(Debug)]
+struct KeySessionSlot {
+    counts: Arc<DashMap<String, usize>>,
+    auth_id: String,
+}
+
+impl KeySessionSlot {
+    fn acquire(
+        counts: &Arc<DashMap<String, usize>>,
+        auth_id: &str,
+        max_sessions: Option<usize>,
+    ) -> Option<Self> {
+        let mut count = counts.entry(auth_id.to_string()).or_insert(0);
+        if max_sessions.is_some_and(|max| *count >= max) {
+            return None;
+        }
+        *count += 1;
+        Some(Self {
+            counts: counts.clone(),
+            auth_id: auth_id.to_string(),
+        })
+    }
+}
+
+impl Drop for KeySessionSlot {
+    fn drop(&mut self) {
+        if let Some(mut count) = self.counts.get_mut(&self.auth_id) {
+            *count = count.saturating_sub(1);
+        }
+        self.counts.remove_if(&self.auth_id, |_, count| *count == 0);
+    }
+}
+
+/// One open connection counted against its client IP; released on drop.
+struct IpConnectionSlot {
+    counts: Arc<DashMap<IpAddr, usize>>,
+    ip: IpAddr,
+}
+
+impl IpConnectionSlot {
+    fn acquire(counts: &Arc<DashMap<IpAddr, usize>>, ip: IpAddr, max: usize) -> Option<Self> {
+        let mut count = counts.entry(ip).or_insert(0);
+        if max > 0 && *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(Self {
+            counts: counts.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for IpConnectionSlot {
+    fn drop(&mut self) {
+        if let Some(mut count) = self.counts.get_mut(&self.ip) {
+            *count = count.saturating_sub(1);
+        }
+        self.counts.remove_if(&self.ip, |_, count| *count == 0);
+    }
+}
+
+fn unix_now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Where a connection came from, recorded in the session's `session_open`.
+#[derive(Debug)]
+struct ConnectionInfo {
+    peer: SocketAddr,
+    client_ip: IpAddr,
+    identity_source: IdentitySource,
+}
+
+impl ConnectionInfo {
+    /// The trusted proxy the client came through, when the client address was
+    /// taken from forwarding headers.
+    fn proxy_hop(&self) -> Option<SocketAddr> {
+        (self.client_ip != self.peer.ip()).then_some(self.peer)
+    }
 }
 
 #[derive(Debug)]
@@ -693,6 +945,11 @@ fn parse_keys(input: &str) -> HashMap<String, String> {
 async fn main() -> anyhow::Result<()> {
     init_tracing();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("replay") {
+        return run_replay(&args[1..]).await;
+    }
+
     // Config::from_env already enforces P1-2 safety checks (panic on unsafe bind/proxy)
     let config = Arc::new(Config::from_env());
     let initial_keys_len = config.auth.keys.read().map(|k| k.len() as i64).unwrap_or(0);
@@ -721,7 +978,9 @@ async fn main() -> anyhow::Result<()> {
         warn!("AUTH_MODE=api_key configured without keys; authentication will fail closed");
     }
 
-    let tracer = Arc::new(TraceLogger::new(&config.audit_log_file).await?);
+    let tracer = Arc::new(
+        trace::open_trace_logger(&config.audit_log_file, Some(metrics.clone())).await?,
+    );
     info!(file = %config.audit_log_file, "trace integrity logger initialized");
 
     let ctx = AppContext {
@@ -731,25 +990,40 @@ async fn main() -> anyhow::Result<()> {
         ip_limiters: Arc::new(DashMap::new()),
         log_throttle: Arc::new(LogThrottle::default()),
         tracer,
+        key_sessions: Arc::new(DashMap::new()),
+        identity_limits: Arc::new(IdentityLimits::default()),
+        bans: Arc::new(BanList::new(config.ip_bans.clone())),
+        ip_connections: Arc::new(DashMap::new()),
     };
     config
         .auth
         .start_reload_task(metrics.clone(), shutdown_rx.clone());
 
-    let metrics_app = Router::new().route(
-        "/metrics",
-        get({
-            let metrics = metrics.clone();
-            move || {
+    let metrics_app = Router::new()
+        .route(
+            "/metrics",
+            get({
                 let metrics = metrics.clone();
-                async move {
-                    metrics
-                        .render()
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+                move || {
+                    let metrics = metrics.clone();
+                    async move {
+                        metrics
+                            .render()
+                            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+                    }
                 }
-            }
-        }),
-    );
+            }),
+        )
+        .route(
+            "/admin/bans",
+            get({
+                let bans = ctx.bans.clone();
+                move || {
+                    let bans = bans.clone();
+                    async move { axum::Json(bans.banned(Instant::now())) }
+                }
+            }),
+        );
 
     let metrics_config = config.clone();
     let mut metrics_shutdown = shutdown_rx.clone();
@@ -804,6 +1078,10 @@ async fn main() -> anyhow::Result<()> {
             accept_result = listener.accept() => {
                 let (stream, peer) = accept_result?;
 
+                if refuse_ip(&ctx, peer.ip()) {
+                    continue;
+                }
+
                 if ctx.metrics.connections.get() as usize >= ctx.config.max_connections {
                     warn!(
                         remote_addr = %peer,
@@ -811,12 +1089,29 @@ async fn main() -> anyhow::Result<()> {
                         "rejecting connection: connection limit reached"
                     );
                     ctx.metrics.capacity_rejections.inc();
+                    ctx.metrics
+                        .connections_rejected
+                        .with_label_values(&["max_connections"])
+                        .inc();
                     continue;
                 }
 
+                // Behind a trusted proxy the peer is the proxy; the client IP
+                // is only known from the PROXY header or the upgrade request.
+                let ip_slot = if ctx.config.behind_proxy() {
+                    None
+                } else {
+                    match acquire_ip_slot(&ctx, peer.ip()) {
+                        Some(slot) => Some(slot),
+                        None => continue,
+                    }
+                };
+
                 let ctx_clone = ctx.clone();
                 tokio::spawn(async move {
-                    if let Err(err) = handle_connection(stream, peer, ctx_clone.clone()).await {
+                    if let Err(err) =
+                        handle_connection(stream, peer, ctx_clone.clone(), ip_slot).await
+                    {
                         error!(remote_addr = %peer, error = ?err, "connection handler error");
                     }
                 });
@@ -832,54 +1127,118 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn run_replay(args: &[String]) -> anyhow::Result<()> {
+    // Replay never touches the audit log; frames are only diffed in memory.
+    let scratch_trace =
+        std::env::temp_dir().join(format!("ltp-replay-{}.jsonl", Uuid::new_v4()));
+    let config = Arc::new(Config::for_replay());
+    let ctx = AppContext {
+        config: config.clone(),
+        state: Arc::new(LtpNodeState::new()),
+        metrics: Arc::new(Metrics::new()?),
+        ip_limiters: Arc::new(DashMap::new()),
+        log_throttle: Arc::new(LogThrottle::default()),
+        tracer: Arc::new(TraceLogger::open(&scratch_trace, None).await?),
+        key_sessions: Arc::new(DashMap::new()),
+        identity_limits: Arc::new(IdentityLimits::default()),
+        bans: Arc::new(BanList::new(config.ip_bans.clone())),
+        ip_connections: Arc::new(DashMap::new()),
+    };
+    let result = replay::run(ctx, args).await;
+    let _ = std::fs::remove_file(&scratch_trace);
+    if !result? {
+        std::process::exit(2);
+    }
+    Ok(())
+}
+
 fn init_tracing() {
     use tracing_subscriber::filter::EnvFilter;
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     tracing_subscriber::fmt().with_env_filter(filter).init();
 }
 
+#[allow(clippy::result_large_err)]
 async fn handle_connection(
-    stream: tokio::net::TcpStream,
+    mut stream: tokio::net::TcpStream,
     peer: SocketAddr,
     ctx: AppContext,
+    ip_slot: Option<IpConnectionSlot>,
 ) -> anyhow::Result<()> {
+    // P1-2: only configured proxies may speak for other clients. Checked
+    // before reading anything from the socket, whatever the auth mode.
+    if ctx.config.behind_proxy() && !proxy::is_trusted(peer.ip(), &ctx.config.allow_proxy_cidr) {
+        warn!(remote_addr = %peer, "connection rejected: trusted proxy enabled but peer not in allowed CIDR");
+        return Ok(());
+    }
+
+    let handshake_timeout = Duration::from_millis(ctx.config.handshake_timeout_ms);
+    let mut transport_ip = peer.ip();
+    if ctx.config.proxy_protocol {
+        match timeout(handshake_timeout, proxy::read_proxy_header(&mut stream)).await {
+            Ok(Ok(source)) => {
+                if let Some(source) = source {
+                    transport_ip = source.ip();
+                }
+            }
+            Ok(Err(err)) => {
+                warn!(remote_addr = %peer, error = %err, "rejecting connection: bad PROXY protocol header");
+                return Ok(());
+            }
+            Err(_) => {
+                warn!(remote_addr = %peer, "PROXY protocol header timed out");
+                return Ok(());
+            }
+        }
+    }
+
     let auth_identity: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
     let client_ip_override: Arc<Mutex<Option<IpAddr>>> = Arc::new(Mutex::new(None));
     let metrics_for_handshake = ctx.metrics.clone();
     let auth_config = ctx.config.auth.clone();
     let auth_identity_for_cb = auth_identity.clone();
-    let trust_proxy = ctx.config.trust_proxy;
     let client_ip_for_cb = client_ip_override.clone();
+    let ctx_for_cb = ctx.clone();
+    let forwarded_slot: Arc<Mutex<Option<IpConnectionSlot>>> = Arc::new(Mutex::new(None));
+    let forwarded_slot_for_cb = forwarded_slot.clone();
     let identity_source = auth_config.identity_source();
 
     let ws_stream = match timeout(
-        Duration::from_millis(ctx.config.handshake_timeout_ms),
+        handshake_timeout,
         accept_hdr_async(stream, move |req: &Request, response: Response| {
-            if !auth_config.auth_enabled() {
-                return Ok(response);
+            let config = &ctx_for_cb.config;
+            let client_ip = if config.trust_proxy {
+                proxy::client_ip(req.headers(), transport_ip, &config.allow_proxy_cidr)
+            } else {
+                transport_ip
+            };
+            if let Ok(mut guard) = client_ip_for_cb.lock() {
+                *guard = Some(client_ip);
             }
 
-            if trust_proxy {
-                if let Some(ip) = extract_forwarded_for(req.headers()) {
-                     // P1-2: Enforce proxy allows list
-                     // We need access to allowed_cidrs here. But closure capture is tricky.
-                     // For now, let's just extract. We will validate in the main body OR
-                     // we need to pass allowed cidrs to this closure.
-                     // The Config is in AppContext but not passed to this closure easily without Arc.
-                     // However, we rely on the fact that if trust_proxy is true, we panic at startup if allow list is missing.
-                     // But we should CHECK the ip against the list.
-                     // Since `ctx` isn't available inside `accept_hdr_async`'s callback easily (it moves),
-                     // and we want to keep code simple.
-                     // Let's rely on post-handshake check? No, X-Forwarded-For is used for Rate Limiting.
-                     // We should validate the *peer* IP (the proxy) against allowed CIDRs.
-                     // But `peer` is available in `handle_connection`.
-
-                     if let Ok(mut guard) = client_ip_for_cb.lock() {
-                        *guard = Some(ip);
-                    }
+            // The accept loop only saw the proxy; check the real client too.
+            if client_ip != peer.ip() && refuse_ip(&ctx_for_cb, client_ip) {
+                return Err(build_error_response(
+                    StatusCode::FORBIDDEN,
+                    "forbidden".to_string(),
+                ));
+            }
+            if config.behind_proxy() {
+                let Some(slot) = acquire_ip_slot(&ctx_for_cb, client_ip) else {
+                    return Err(build_error_response(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        "too many connections".to_string(),
+                    ));
+                };
+                if let Ok(mut guard) = forwarded_slot_for_cb.lock() {
+                    *guard = Some(slot);
                 }
             }
 
+            if !auth_config.auth_enabled() {
+                return Ok(response);
+            }
+
             match auth_config.authenticate_header(req) {
                 Ok(identity) => {
                     if let Some(id) = identity {
@@ -891,6 +1250,7 @@ async fn handle_connection(
                 }
                 Err(err) => {
                     metrics_for_handshake.auth_failures_total.inc();
+                    record_violation(&ctx_for_cb, client_ip, Violation::AuthFailure);
                     Err(*err)
                 }
             }
@@ -909,16 +1269,7 @@ async fn handle_connection(
         }
     };
 
-    // P1-2: Proxy CIDR check
-    if ctx.config.trust_proxy {
-        let is_allowed = ctx.config.allow_proxy_cidr.iter().any(|cidr| cidr.contains(&peer.ip()));
-        if !is_allowed {
-             warn!(remote_addr = %peer, "connection rejected: trusted proxy enabled but peer not in allowed CIDR");
-             // Close connection
-             return Ok(());
-        }
-    }
-
+    let _ip_slot = ip_slot.or_else(|| forwarded_slot.lock().ok().and_then(|mut g| g.take()));
     ctx.metrics.connections.inc();
     let client_ip = client_ip_override
         .lock()
@@ -934,24 +1285,41 @@ async fn handle_connection(
 
     let (mut write, mut read) = ws_stream.split();
     let mut parse_sampler = ParseErrorSampler::new(Duration::from_secs(1));
-    let mut rate_limiter = TokenBucket::new(ctx.config.rate_limit_rps, ctx.config.rate_limit_burst);
     let mut last_invalid_json_log: Option<Instant> = None;
 
-    let auth_ctx =
-        match perform_handshake(&mut write, &mut read, &ctx, peer, &mut parse_sampler).await? {
-            Some(auth_ctx) => auth_ctx,
+    let conn = ConnectionInfo {
+        peer,
+        client_ip,
+        identity_source,
+    };
+    let (auth_ctx, _key_slot) =
+        match perform_handshake(&mut write, &mut read, &ctx, &conn, &mut parse_sampler).await? {
+            Some(accepted) => accepted,
             None => {
                 ctx.metrics.connections.dec();
                 return Ok(());
             }
         };
     let active_session = auth_ctx.session_id.clone();
+    let mut rate_limiter = TokenBucket::new(
+        auth_ctx
+            .policy
+            .rate_limit_rps
+            .unwrap_or(ctx.config.rate_limit_rps),
+        auth_ctx
+            .policy
+            .rate_limit_burst
+            .unwrap_or(ctx.config.rate_limit_burst),
+    );
+    let session_started = Instant::now();
+    let mut close_reason = "stream_ended";
 
     while let Some(msg) = read.next().await {
         let msg = match msg {
             Ok(m) => m,
             Err(err) => {
                 warn!(remote_addr = %peer, error = ?err, "websocket read error");
+                close_reason = "read_error";
                 break;
             }
         };
@@ -973,12 +1341,14 @@ async fn handle_connection(
                         .messages_rejected
                         .with_label_values(&["rate_limit"])
                         .inc();
+                    record_violation(&ctx, client_ip, Violation::RateLimit);
                     let _ = write
                         .send(Message::Close(Some(CloseFrame {
                             code: CloseCode::Policy,
                             reason: "ip rate limit exceeded".into(),
                         })))
                         .await;
+                    close_reason = "ip_rate_limit";
                     break;
                 }
 
@@ -1000,12 +1370,26 @@ async fn handle_connection(
                         .messages_rejected
                         .with_label_values(&["rate_limit"])
                         .inc();
+                    record_violation(&ctx, client_ip, Violation::RateLimit);
+                    let err_msg = Correlated::new(
+                        correlation_id_of(&text),
+                        LtpOutgoingMessage::Error {
+                            code: protocol::ErrorCode::RateLimit,
+                            message: Some("rate limit exceeded".to_string()),
+                            retry_after_ms: Some(retry_after_ms(rate_limiter.retry_after())),
+                        },
+                    );
+                    if let Err(e) = ctx.tracer.log("out", &active_session, &err_msg).await {
+                        warn!(error = ?e, "trace logging failed for rate limit error");
+                    }
+                    let _ = send_json(&mut write, &err_msg).await;
                     let _ = write
                         .send(Message::Close(Some(CloseFrame {
                             code: CloseCode::Policy,
                             reason: "rate limit exceeded".into(),
                         })))
                         .await;
+                    close_reason = "rate_limit";
                     break;
                 }
 
@@ -1029,34 +1413,86 @@ async fn handle_connection(
                         .with_label_values(&["too_large"])
                         .inc();
                     ctx.metrics.oversize_messages_total.inc();
+                    record_violation(&ctx, client_ip, Violation::Oversize);
                     let _ = write
                         .send(Message::Close(Some(CloseFrame {
                             code: CloseCode::Size,
                             reason: "message too large".into(),
                         })))
                         .await;
+                    close_reason = "message_too_large";
                     break;
                 }
 
-                match serde_json::from_str::<LtpIncomingMessage>(&text) {
+                match serde_json::from_str::<Correlated<LtpIncomingMessage>>(&text) {
                     Ok(incoming) => {
                         ctx.metrics
                             .messages_total
-                            .with_label_values(&[incoming_type(&incoming)])
+                            .with_label_values(&[incoming_type(&incoming.message)])
                             .inc();
 
                         if let Err(e) = ctx.tracer.log("in", &active_session, &incoming).await {
                             warn!(error = ?e, "trace logging failed for incoming message");
                         }
 
+                        let Correlated {
+                            correlation_id,
+                            message: incoming,
+                        } = incoming;
+                        // Heartbeats keep the session alive and do not count against
+                        // the identity's bucket or quotas.
+                        let limited = if matches!(incoming, LtpIncomingMessage::Heartbeat { .. }) {
+                            Ok(())
+                        } else {
+                            check_identity_limits(&ctx, &auth_ctx)
+                        };
+                        if let Err(throttled) = limited {
+                            if !log_throttled(&ctx, "identity_limit", || {
+                                warn!(
+                                    remote_addr = %peer,
+                                    auth_id = %auth_ctx.auth_id,
+                                    limit = throttled.limit.as_str(),
+                                    retry_after_ms = retry_after_ms(throttled.retry_after),
+                                    "refusing message: identity limit reached"
+                                );
+                            }) {
+                                ctx.metrics
+                                    .log_suppressed_total
+                                    .with_label_values(&["identity_limit"])
+                                    .inc();
+                            }
+                            let err_msg = Correlated::new(
+                                correlation_id,
+                                LtpOutgoingMessage::Error {
+                                    code: protocol::ErrorCode::RateLimit,
+                                    message: Some(throttled.limit.to_string()),
+                                    retry_after_ms: Some(retry_after_ms(throttled.retry_after)),
+                                },
+                            );
+                            if let Err(e) = ctx.tracer.log("out", &active_session, &err_msg).await {
+                                warn!(error = ?e, "trace logging failed for rate limit error");
+                            }
+                            if let Err(err) = send_json(&mut write, &err_msg).await {
+                                warn!(remote_addr = %peer, error = ?err, "failed to send rate limit error");
+                                close_reason = "send_failed";
+                                break;
+                            }
+                            // Not an IP violation: the identity's bucket and quotas
+                            // are shared by every address it connects from.
+                            continue;
+                        }
                         if let Some(responses) = process_message(incoming, &ctx, &auth_ctx).await {
                             let mut should_close = false;
                             for response in responses {
-                                if let Err(e) = ctx.tracer.log("out", &active_session, &response).await {
+                                // Replies carry the id of the message they answer.
+                                let response = Correlated::new(correlation_id.clone(), response);
+                                if let Err(e) =
+                                    ctx.tracer.log("out", &active_session, &response).await
+                                {
                                     warn!(error = ?e, "trace logging failed for outgoing message");
                                 }
 
-                                if let LtpOutgoingMessage::Error { code, .. } = &response {
+                                if let LtpOutgoingMessage::Error { code, .. } = &response.message {
                                     if matches!(
                                         code,
                                         protocol::ErrorCode::Forbidden
@@ -1076,6 +1512,7 @@ async fn handle_connection(
                             }
                             if should_close {
                                 let _ = write.close().await;
+                                close_reason = "policy";
                                 break;
                             }
                         }
@@ -1096,12 +1533,24 @@ async fn handle_connection(
                         let err_msg = LtpOutgoingMessage::Error {
                             code: protocol::ErrorCode::Invalid,
                             message: Some("invalid message".to_string()),
+                            retry_after_ms: None,
                         };
                         if let Err(e) = ctx.tracer.log("out", &active_session, &err_msg).await {
                             warn!(error = ?e, "trace logging failed for error message");
                         }
                         if let Err(err) = send_json(&mut write, &err_msg).await {
                             warn!(remote_addr = %peer, error = ?err, "failed to send error");
+                            close_reason = "send_failed";
+                            break;
+                        }
+                        if record_violation(&ctx, client_ip, Violation::InvalidJson) {
+                            let _ = write
+                                .send(Message::Close(Some(CloseFrame {
+                                    code: CloseCode::Policy,
+                                    reason: "ip banned".into(),
+                                })))
+                                .await;
+                            close_reason = "ip_banned";
                             break;
                         }
                     }
@@ -1126,19 +1575,35 @@ async fn handle_connection(
                 let err_msg = LtpOutgoingMessage::Error {
                     code: protocol::ErrorCode::Invalid,
                     message: Some("binary messages are not supported".to_string()),
+                    retry_after_ms: None,
                 };
                 if let Err(e) = ctx.tracer.log("out", &active_session, &err_msg).await {
                     warn!(error = ?e, "trace logging failed for binary error");
                 }
                 if let Err(err) = send_json(&mut write, &err_msg).await {
                     warn!(remote_addr = %peer, error = ?err, "failed to send binary error");
+                    close_reason = "send_failed";
                     break;
                 }
+                if record_violation(&ctx, client_ip, Violation::InvalidJson) {
+                    let _ = write
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: "ip banned".into(),
+                        })))
+                        .await;
+                    close_reason = "ip_banned";
+                    break;
+                }
+            }
+            Message::Close(_) => {
+                close_reason = "client_closed";
+                break;
             }
-            Message::Close(_) => break,
             Message::Ping(p) => {
                 if let Err(err) = write.send(Message::Pong(p)).await {
                     warn!(remote_addr = %peer, error = ?err, "failed to respond to ping");
+                    close_reason = "send_failed";
                     break;
                 }
             }
@@ -1156,6 +1621,21 @@ async fn handle_connection(
             "session removed on disconnect"
         );
     }
+    let close = SessionRecord::SessionClose(SessionClose {
+        auth_id: auth_ctx.auth_id.clone(),
+        reason: close_reason.to_string(),
+        duration_ms: session_started.elapsed().as_millis() as u64,
+    });
+    if let Err(e) = ctx
+        .tracer
+        .log(NODE_DIRECTION, &active_session, &close.to_frame())
+        .await
+    {
+        warn!(error = ?e, "trace logging failed for session close");
+    }
+    if let Err(e) = ctx.tracer.end_session(&active_session).await {
+        warn!(error = ?e, "failed to close session trace chain");
+    }
 
     ctx.metrics.connections.dec();
     info!(
@@ -1184,9 +1664,10 @@ async fn perform_handshake(
         tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
     >,
     ctx: &AppContext,
-    peer: SocketAddr,
+    conn: &ConnectionInfo,
     parse_sampler: &mut ParseErrorSampler,
-) -> anyhow::Result<Option<AuthContext>> {
+) -> anyhow::Result<Option<(AuthContext, KeySessionSlot)>> {
+    let peer = conn.peer;
     while let Some(msg) = read.next().await {
         let msg = match msg {
             Ok(m) => m,
@@ -1209,6 +1690,7 @@ async fn perform_handshake(
                         .messages_rejected
                         .with_label_values(&["too_large"])
                         .inc();
+                    record_violation(ctx, conn.client_ip, Violation::Oversize);
                     let _ = write
                         .send(Message::Close(Some(CloseFrame {
                             code: CloseCode::Size,
@@ -1219,13 +1701,69 @@ async fn perform_handshake(
                 }
 
                 match serde_json::from_str::<LtpIncomingMessage>(&text) {
-                    Ok(LtpIncomingMessage::Hello { api_key, .. }) => {
+                    Ok(LtpIncomingMessage::Hello {
+                        api_key,
+                        client_label,
+                    }) => {
                         let valid = match ctx.config.auth.validate_api_key(&api_key) {
-                            Ok(Some(id)) => Some(id),
+                            Ok(Some(grant)) => Some(grant),
                             _ => None,
                         };
 
-                        if let Some(auth_id) = valid {
+                        if let Some(KeyGrant {
+                            id: auth_id,
+                            policy,
+                        }) = valid
+                        {
+                            if let Err(rejection) = policy.check_validity(unix_now_secs()) {
+                                warn!(
+                                    remote_addr = %peer,
+                                    auth_id = %auth_id,
+                                    reason = %rejection,
+                                    "handshake rejected: key outside its validity window"
+                                );
+                                ctx.metrics
+                                    .messages_rejected
+                                    .with_label_values(&["unauthorized"])
+                                    .inc();
+                                let _ = send_json(
+                                    write,
+                                    &LtpOutgoingMessage::Error {
+                                        code: protocol::ErrorCode::Unauthorized,
+                                        message: Some(rejection.to_string()),
+                                        retry_after_ms: None,
+                                    },
+                                )
+                                .await;
+                                let _ = write.close().await;
+                                return Ok(None);
+                            }
+                            let Some(slot) = KeySessionSlot::acquire(
+                                &ctx.key_sessions,
+                                &auth_id,
+                                policy.max_sessions,
+                            ) else {
+                                warn!(
+                                    remote_addr = %peer,
+                                    auth_id = %auth_id,
+                                    "handshake rejected: key session quota reached"
+                                );
+                                ctx.metrics
+                                    .messages_rejected
+                                    .with_label_values(&["rate_limit"])
+                                    .inc();
+                                let _ = send_json(
+                                    write,
+                                    &LtpOutgoingMessage::Error {
+                                        code: protocol::ErrorCode::RateLimit,
+                                        message: Some("key session limit reached".to_string()),
+                                        retry_after_ms: None,
+                                    },
+                                )
+                                .await;
+                                let _ = write.close().await;
+                                return Ok(None);
+                            };
                             let session_id = Uuid::new_v4().to_string();
                             let created = ctx.state.touch_heartbeat(&session_id).await;
                             if created && reject_when_over_capacity(ctx, &session_id) {
@@ -1234,6 +1772,7 @@ async fn perform_handshake(
                                     &LtpOutgoingMessage::Error {
                                         code: protocol::ErrorCode::RateLimit,
                                         message: Some("session limit reached".to_string()),
+                                        retry_after_ms: None,
                                     },
                                 )
                                 .await;
@@ -1246,6 +1785,21 @@ async fn perform_handshake(
                                 session_id = %session_id,
                                 "handshake authorized"
                             );
+                            let open = SessionRecord::SessionOpen(SessionOpen {
+                                auth_id: auth_id.clone(),
+                                identity_source: conn.identity_source.as_str().to_string(),
+                                client_ip: conn.client_ip.to_string(),
+                                proxy_hop: conn.proxy_hop().map(|hop| hop.to_string()),
+                                client_label,
+                                node_id: ctx.config.node_id.clone(),
+                            });
+                            if let Err(e) = ctx
+                                .tracer
+                                .log(NODE_DIRECTION, &session_id, &open.to_frame())
+                                .await
+                            {
+                                warn!(error = ?e, "trace logging failed for session open");
+                            }
                             let ack = LtpOutgoingMessage::HelloAck {
                                 node_id: ctx.config.node_id.clone(),
                                 accepted: true,
@@ -1255,21 +1809,27 @@ async fn perform_handshake(
                                 warn!(error = ?e, "trace logging failed for handshake ack");
                             }
                             send_json(write, &ack).await?;
-                            return Ok(Some(AuthContext {
-                                auth_id,
-                                session_id,
-                            }));
+                            return Ok(Some((
+                                AuthContext {
+                                    auth_id,
+                                    session_id,
+                                    policy,
+                                },
+                                slot,
+                            )));
                         } else {
                             warn!(remote_addr = %peer, "handshake unauthorized");
                             ctx.metrics
                                 .messages_rejected
                                 .with_label_values(&["unauthorized"])
                                 .inc();
+                            record_violation(ctx, conn.client_ip, Violation::AuthFailure);
                             let _ = send_json(
                                 write,
                                 &LtpOutgoingMessage::Error {
                                     code: protocol::ErrorCode::Unauthorized,
                                     message: Some("unauthorized".to_string()),
+                                    retry_after_ms: None,
                                 },
                             )
                             .await;
@@ -1288,6 +1848,7 @@ async fn perform_handshake(
                             &LtpOutgoingMessage::Error {
                                 code: protocol::ErrorCode::Invalid,
                                 message: Some("handshake required".to_string()),
+                                retry_after_ms: None,
                             },
                         )
                         .await;
@@ -1307,11 +1868,13 @@ async fn perform_handshake(
                             .messages_rejected
                             .with_label_values(&["invalid_json"])
                             .inc();
+                        record_violation(ctx, conn.client_ip, Violation::InvalidJson);
                         let _ = send_json(
                             write,
                             &LtpOutgoingMessage::Error {
                                 code: protocol::ErrorCode::Invalid,
                                 message: Some("invalid handshake".to_string()),
+                                retry_after_ms: None,
                             },
                         )
                         .await;
@@ -1333,11 +1896,13 @@ async fn perform_handshake(
                     .messages_rejected
                     .with_label_values(&["invalid_json"])
                     .inc();
+                record_violation(ctx, conn.client_ip, Violation::InvalidJson);
                 let _ = send_json(
                     write,
                     &LtpOutgoingMessage::Error {
                         code: protocol::ErrorCode::Invalid,
                         message: Some("binary handshake not supported".to_string()),
+                        retry_after_ms: None,
                     },
                 )
                 .await;
@@ -1356,10 +1921,32 @@ async fn process_message(
     ctx: &AppContext,
     auth: &AuthContext,
 ) -> Option<Vec<LtpOutgoingMessage>> {
+    if !matches!(incoming, LtpIncomingMessage::Hello { .. }) {
+        let refusal = match auth.policy.check_validity(unix_now_secs()) {
+            Err(rejection) => Some(rejection.to_string()),
+            Ok(()) if !auth.policy.allows(incoming_type(&incoming)) => {
+                Some("message type not allowed for this key".to_string())
+            }
+            Ok(()) => None,
+        };
+        if let Some(message) = refusal {
+            ctx.metrics
+                .messages_rejected
+                .with_label_values(&["forbidden"])
+                .inc();
+            return Some(vec![LtpOutgoingMessage::Error {
+                code: protocol::ErrorCode::Forbidden,
+                message: Some(message),
+                retry_after_ms: None,
+            }]);
+        }
+    }
+
     match incoming {
         LtpIncomingMessage::Hello { .. } => Some(vec![LtpOutgoingMessage::Error {
             code: protocol::ErrorCode::Invalid,
             message: Some("handshake already completed".to_string()),
+            retry_after_ms: None,
         }]),
         LtpIncomingMessage::Heartbeat {
             session_id,
@@ -1373,6 +1960,7 @@ async fn process_message(
                 return Some(vec![LtpOutgoingMessage::Error {
                     code: protocol::ErrorCode::Forbidden,
                     message: Some("session mismatch".to_string()),
+                    retry_after_ms: None,
                 }]);
             }
             let created = ctx.state.touch_heartbeat(&auth.session_id).await;
@@ -1380,6 +1968,7 @@ async fn process_message(
                 return Some(vec![LtpOutgoingMessage::Error {
                     code: protocol::ErrorCode::RateLimit,
                     message: Some("session limit reached".to_string()),
+                    retry_after_ms: None,
                 }]);
             }
             Some(vec![LtpOutgoingMessage::HeartbeatAck {
@@ -1400,6 +1989,7 @@ async fn process_message(
                 return Some(vec![LtpOutgoingMessage::Error {
                     code: protocol::ErrorCode::Forbidden,
                     message: Some("session mismatch".to_string()),
+                    retry_after_ms: None,
                 }]);
             }
             let created = ctx
@@ -1410,6 +2000,7 @@ async fn process_message(
                 return Some(vec![LtpOutgoingMessage::Error {
                     code: protocol::ErrorCode::RateLimit,
                     message: Some("session limit reached".to_string()),
+                    retry_after_ms: None,
                 }]);
             }
             None
@@ -1423,6 +2014,7 @@ async fn process_message(
                 return Some(vec![LtpOutgoingMessage::Error {
                     code: protocol::ErrorCode::Forbidden,
                     message: Some("session mismatch".to_string()),
+                    retry_after_ms: None,
                 }]);
             }
             Some(vec![
@@ -1437,7 +2029,7 @@ async fn send_json(
         tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
         Message,
     >,
-    message: &LtpOutgoingMessage,
+    message: &impl Serialize,
 ) -> WsResult<()> {
     let payload = serde_json::to_string(message).expect("serialization should succeed");
     write.send(Message::Text(payload)).await
@@ -1489,6 +2081,9 @@ fn spawn_janitor(
             if ip_removed > 0 {
                 info!(removed = ip_removed, "expired idle ip limiters");
             }
+            ctx.metrics
+                .ip_banned_current
+                .set(ctx.bans.sweep(Instant::now()) as i64);
 
             if stats.expired > 0 {
                 ctx.metrics.sessions.sub(stats.expired as i64);
@@ -1547,6 +2142,121 @@ fn check_ip_rate_limit(ctx: &AppContext, ip: IpAddr) -> bool {
     allow
 }
 
+/// Count a new connection against its client IP, or reject it when the IP
+/// is at `max_connections_per_ip`.
+fn acquire_ip_slot(ctx: &AppContext, ip: IpAddr) -> Option<IpConnectionSlot> {
+    let slot =
+        IpConnectionSlot::acquire(&ctx.ip_connections, ip, ctx.config.max_connections_per_ip);
+    if slot.is_none() {
+        if !log_throttled(ctx, "per_ip_connections", || {
+            warn!(
+                client_ip = %ip,
+                max_connections_per_ip = ctx.config.max_connections_per_ip,
+                "rejecting connection: per-ip connection limit reached"
+            );
+        }) {
+            ctx.metrics
+                .log_suppressed_total
+                .with_label_values(&["per_ip_connections"])
+                .inc();
+        }
+        ctx.metrics
+            .connections_rejected
+            .with_label_values(&["per_ip"])
+            .inc();
+    }
+    slot
+}
+
+/// Refuse a connection from a banned or deny-listed IP.
+fn refuse_ip(ctx: &AppContext, ip: IpAddr) -> bool {
+    let admission = ctx.bans.admit(ip, Instant::now());
+    let Some(reason) = admission.refusal_reason() else {
+        return false;
+    };
+    if !log_throttled(ctx, "ip_refused", || {
+        warn!(client_ip = %ip, reason, "refusing connection from blocked ip");
+    }) {
+        ctx.metrics
+            .log_suppressed_total
+            .with_label_values(&["ip_refused"])
+            .inc();
+    }
+    ctx.metrics
+        .ip_connections_refused_total
+        .with_label_values(&[reason])
+        .inc();
+    true
+}
+
+/// Count a violation towards a temporary ban of `ip`. Returns true when this
+/// violation bans it.
+fn record_violation(ctx: &AppContext, ip: IpAddr, violation: Violation) -> bool {
+    let Some(ban) = ctx.bans.record(ip, violation, Instant::now()) else {
+        return false;
+    };
+    warn!(
+        client_ip = %ip,
+        reason = ban.reason.as_str(),
+        ban_secs = ban.duration.as_secs(),
+        offense = ban.offense,
+        "banning ip"
+    );
+    ctx.metrics
+        .ip_bans_total
+        .with_label_values(&[ban.reason.as_str()])
+        .inc();
+    ctx.metrics.ip_banned_current.inc();
+    true
+}
+
+/// The `correlation_id` of a message refused before it is parsed.
+fn correlation_id_of(text: &str) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct Id {
+        correlation_id: Option<String>,
+    }
+    serde_json::from_str::<Id>(text).ok()?.correlation_id
+}
+
+/// Count one message against the limits shared by all of the identity's
+/// connections.
+fn check_identity_limits(ctx: &AppContext, auth: &AuthContext) -> Result<(), quota::Throttled> {
+    let nonzero = |quota: u64| (quota > 0).then_some(quota);
+    let quota = IdentityQuota {
+        rate_rps: ctx.config.identity_rate_limit_rps,
+        rate_burst: ctx.config.identity_rate_limit_burst,
+        daily: auth
+            .policy
+            .daily_quota
+            .or(nonzero(ctx.config.identity_daily_quota)),
+        monthly: auth
+            .policy
+            .monthly_quota
+            .or(nonzero(ctx.config.identity_monthly_quota)),
+    };
+    let result = ctx
+        .identity_limits
+        .check(&auth.auth_id, &quota, unix_now_secs());
+    if let Err(throttled) = &result {
+        ctx.metrics
+            .identity_throttled_total
+            .with_label_values(&[throttled.limit.as_str()])
+            .inc();
+        ctx.metrics
+            .messages_rejected
+            .with_label_values(&["rate_limit"])
+            .inc();
+    }
+    result
+}
+
+fn retry_after_ms(retry_after: Duration) -> u64 {
+    u64::try_from(retry_after.as_millis())
+        .unwrap_or(u64::MAX)
+        .max(1)
+}
+
 fn log_throttled<F: FnOnce()>(ctx: &AppContext, category: &'static str, log_fn: F) -> bool {
     if ctx
         .log_throttle
@@ -1595,10 +2305,9 @@ mod auth_unit_tests {
     fn constant_time_compare_matches() {
         let config = AuthConfig {
             mode: AuthMode::ApiKey,
-            keys: Arc::new(std::sync::RwLock::new(HashMap::from([(
-                "id".to_string(),
-                "supersecret".to_string(),
-            )]))),
+            keys: Arc::new(std::sync::RwLock::new(KeyStore::from_plaintext(
+                HashMap::from([("id".to_string(), "supersecret".to_string())]),
+            ))),
             jwt_secret: None,
             keys_file: None,
             keys_reload_interval: Duration::from_secs(30),
@@ -1608,4 +2317,48 @@ mod auth_unit_tests {
         assert!(config.validate_api_key("supersecret").unwrap().is_some());
         assert!(config.validate_api_key("wrong").unwrap().is_none());
     }
+
+    #[test]
+    fn header_auth_refuses_keys_outside_their_validity_window() {
+        const KEY: &str = "ltp_3f9a1c2e_8c1d6b0e4f2a97d3c5b1e8f04a6d2c9b";
+        let keys = |policy: serde_json::Value| {
+            let mut entry = serde_json::json!({
+                "prefix": "3f9a1c2e",
+                "salt": hex::encode(b"salt"),
+                "sha256": hex::encode(auth_keys::salted_digest(b"salt", KEY)),
+            });
+            entry
+                .as_object_mut()
+                .unwrap()
+                .extend(policy.as_object().unwrap().clone());
+            let content = serde_json::json!({ "partner-a": entry }).to_string();
+            Arc::new(std::sync::RwLock::new(
+                KeyStore::from_json(&content).unwrap(),
+            ))
+        };
+        let config = |keys| AuthConfig {
+            mode: AuthMode::ApiKey,
+            keys,
+            jwt_secret: None,
+            keys_file: None,
+            keys_reload_interval: Duration::from_secs(30),
+            last_loaded_hash: Arc::new(Mutex::new(None)),
+            fail_closed: Arc::new(AtomicBool::new(false)),
+        };
+        let req = Request::builder()
+            .header("x-api-key", KEY)
+            .body(())
+            .unwrap();
+
+        let current = config(keys(serde_json::json!({})));
+        assert_eq!(
+            current.authenticate_header(&req).unwrap().as_deref(),
+            Some("partner-a")
+        );
+        let expired = config(keys(serde_json::json!({"expires_at": 1})));
+        let refused = expired.authenticate_header(&req).unwrap_err();
+        assert_eq!(refused.status(), http::StatusCode::UNAUTHORIZED);
+        let future = config(keys(serde_json::json!({"not_before": u64::MAX - 1})));
+        assert!(future.authenticate_header(&req).is_err());
+    }
 }
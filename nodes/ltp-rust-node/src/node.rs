@@ -1,8 +1,8 @@
-use crate::protocol::{
-    LtpOutgoingMessage, RouteDebugInfo, Sector, TimeOrientationBoostPayload,
+use crate::state::LtpNodeState;
+use ltp_protocol::node::{
+    LtpOutgoingMessage, RouteDebugInfo, RouteSuggestion, Sector, TimeOrientationBoostPayload,
     TimeOrientationDirectionPayload,
 };
-use crate::state::LtpNodeState;
 
 pub async fn build_route_suggestion(state: &LtpNodeState, session_id: &str) -> LtpOutgoingMessage {
     let client_state = state.snapshot(session_id).await;
@@ -43,13 +43,13 @@ pub async fn build_route_suggestion(state: &LtpNodeState, session_id: &str) -> L
         }
     }
 
-    LtpOutgoingMessage::RouteSuggestion {
+    LtpOutgoingMessage::RouteSuggestion(RouteSuggestion {
         session_id: session_id.to_string(),
-        suggested_sector: suggested_sector.to_string(), // Fixed mismatch
+        suggested_sector,
         reason: Some(reason),
         debug: Some(RouteDebugInfo {
             focus_momentum: fm,
             time_orientation: to,
         }),
-    }
+    })
 }
@@ -0,0 +1,314 @@
+//! Client address resolution behind trusted proxies.
+//!
+//! Two sources are supported, and may be combined:
+//!
+//! - The PROXY protocol (v1 text or v2 binary) header a layer-4 proxy sends
+//!   before any application data (`PROXY_PROTOCOL=true`).
+//! - The RFC 7239 `Forwarded` header, or `X-Forwarded-For` when no
+//!   `Forwarded` header is present, added by HTTP proxies (`TRUST_PROXY=true`).
+//!
+//! Forwarding headers are walked right to left: each hop was appended by the
+//! proxy in front of it, so only hops added by trusted proxies can be
+//! believed. The client is the first untrusted address in that walk.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use ipnet::IpNet;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Longest v1 header allowed by the specification, including CRLF.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+pub fn is_trusted(ip: IpAddr, trusted: &[IpNet]) -> bool {
+    let ip = ip.to_canonical();
+    trusted.iter().any(|net| net.contains(&ip))
+}
+
+/// The client address of a request that reached us from `peer`.
+///
+/// `peer` is returned unchanged unless it is trusted. A hop that is
+/// `unknown`, obfuscated or malformed ends the walk at the last trusted hop,
+/// since nothing to its left can be verified.
+pub fn client_ip(headers: &http::HeaderMap, peer: IpAddr, trusted: &[IpNet]) -> IpAddr {
+    let mut client = peer.to_canonical();
+    if !is_trusted(client, trusted) {
+        return client;
+    }
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        let Some(ip) = hop else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip, trusted) {
+            break;
+        }
+    }
+    client
+}
+
+/// Forwarding hops, leftmost (furthest from us) first. `None` marks a hop
+/// without a usable address.
+fn forwarded_hops(headers: &http::HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = headers
+        .get_all(http::header::FORWARDED)
+        .iter()
+        .map(|v| v.to_str().unwrap_or(""))
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|value| split_outside_quotes(value, ','))
+            .map(|element| {
+                split_outside_quotes(element, ';')
+                    .into_iter()
+                    .find_map(|pair| {
+                        let (name, value) = pair.split_once('=')?;
+                        name.trim()
+                            .eq_ignore_ascii_case("for")
+                            .then(|| parse_node(value.trim().trim_matches('"')))
+                    })
+                    .flatten()
+            })
+            .collect();
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|v| v.to_str().unwrap_or("").split(','))
+        .map(|hop| parse_node(hop.trim()))
+        .collect()
+}
+
+fn split_outside_quotes(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+/// An address as written in `for=` or `X-Forwarded-For`: a bare address,
+/// `a.b.c.d:port`, or `[v6]` with an optional port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (addr, _) = rest.split_once(']')?;
+        return addr.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    let (addr, port) = node.rsplit_once(':')?;
+    port.parse::<u16>().ok()?;
+    addr.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("proxy protocol: {message}"),
+    )
+}
+
+/// Read a PROXY protocol header from the start of a connection, consuming
+/// exactly its bytes. Returns the original source address, or `None` for
+/// `UNKNOWN`/`LOCAL` headers (health checks from the proxy itself).
+pub async fn read_proxy_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<SocketAddr>> {
+    let mut prefix = [0u8; 5];
+    reader.read_exact(&mut prefix).await?;
+    if &prefix == b"PROXY" {
+        read_v1(reader).await
+    } else if prefix == V2_SIGNATURE[..5] {
+        read_v2(reader).await
+    } else {
+        Err(invalid("missing header"))
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut line = b"PROXY".to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        line.push(reader.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid("not ascii"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family, src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad source address"))?;
+            let port: u16 = src_port.parse().map_err(|_| invalid("bad source port"))?;
+            match (*family, ip) {
+                ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => {
+                    Ok(Some(SocketAddr::new(ip, port)))
+                }
+                _ => Err(invalid("address does not match family")),
+            }
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0u8; 11];
+    reader.read_exact(&mut header).await?;
+    if header[..7] != V2_SIGNATURE[5..] {
+        return Err(invalid("bad v2 signature"));
+    }
+    let version_command = header[7];
+    let family = header[8];
+    let len = u16::from_be_bytes([header[9], header[10]]) as usize;
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported version"));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+
+    match version_command & 0x0f {
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err(invalid("unsupported command")),
+    }
+    // High nibble: address family; low nibble: transport (stream/dgram).
+    match family >> 4 {
+        0x1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x2 if body.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        0x1 | 0x2 => Err(invalid("truncated address block")),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> http::HeaderMap {
+        let mut map = http::HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        map
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn walks_x_forwarded_for_right_to_left() {
+        let proxy = ip("10.0.0.1");
+        // The leftmost hop is client-supplied and must not win.
+        let h = headers(&[("x-forwarded-for", "1.1.1.1, 203.0.113.7, 10.0.0.2")]);
+        assert_eq!(client_ip(&h, proxy, &trusted()), ip("203.0.113.7"));
+
+        let split = headers(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-for", "203.0.113.7"),
+        ]);
+        assert_eq!(client_ip(&split, proxy, &trusted()), ip("203.0.113.7"));
+
+        let all_trusted = headers(&[("x-forwarded-for", "10.1.1.1, 10.0.0.2")]);
+        assert_eq!(client_ip(&all_trusted, proxy, &trusted()), ip("10.1.1.1"));
+
+        let garbage = headers(&[("x-forwarded-for", "203.0.113.7, nonsense, 10.0.0.2")]);
+        assert_eq!(client_ip(&garbage, proxy, &trusted()), ip("10.0.0.2"));
+
+        // Headers from an untrusted peer are ignored.
+        assert_eq!(
+            client_ip(&h, ip("198.51.100.1"), &trusted()),
+            ip("198.51.100.1")
+        );
+        assert_eq!(client_ip(&http::HeaderMap::new(), proxy, &trusted()), proxy);
+    }
+
+    #[test]
+    fn prefers_rfc7239_forwarded() {
+        let h = headers(&[
+            (
+                "forwarded",
+                "for=192.0.2.60;proto=https, for=\"[2001:db8::1]:4711\";by=10.0.0.3",
+            ),
+            ("forwarded", "for=10.0.0.9:80"),
+            ("x-forwarded-for", "198.51.100.9"),
+        ]);
+        assert_eq!(client_ip(&h, ip("10.0.0.1"), &trusted()), ip("2001:db8::1"));
+
+        let obfuscated = headers(&[("forwarded", "for=192.0.2.60, for=_hidden, for=10.0.0.2")]);
+        assert_eq!(
+            client_ip(&obfuscated, ip("10.0.0.1"), &trusted()),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_proxy_protocol_v1() {
+        let mut input: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.5 51000 7070\r\nGET / HTTP/1.1";
+        let source = read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(source, Some("203.0.113.7:51000".parse().unwrap()));
+        assert_eq!(input, b"GET / HTTP/1.1");
+
+        let mut unknown: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_proxy_header(&mut unknown).await.unwrap(), None);
+
+        let mut mismatched: &[u8] = b"PROXY TCP6 203.0.113.7 10.0.0.5 1 2\r\n";
+        assert!(read_proxy_header(&mut mismatched).await.is_err());
+        let mut plain: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(read_proxy_header(&mut plain).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_proxy_protocol_v2() {
+        let mut frame = V2_SIGNATURE.to_vec();
+        frame.extend_from_slice(&[0x21, 0x11, 0x00, 0x0f]);
+        frame.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 5]);
+        frame.extend_from_slice(&51000u16.to_be_bytes());
+        frame.extend_from_slice(&7070u16.to_be_bytes());
+        frame.extend_from_slice(&[0x04, 0x00, 0x00]); // empty NOOP TLV
+        frame.extend_from_slice(b"payload");
+        let mut input: &[u8] = &frame;
+        let source = read_proxy_header(&mut input).await.unwrap();
+        assert_eq!(source, Some("203.0.113.7:51000".parse().unwrap()));
+        assert_eq!(input, b"payload");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            read_proxy_header(&mut local.as_slice()).await.unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Limits shared by every connection of one identity (`auth_id`).
+//!
+//! Per-connection and per-IP buckets do not stop a key that opens many
+//! connections from many addresses. `IdentityLimits` keeps one token bucket
+//! and one pair of daily/monthly message counters per `auth_id`. Periods are
+//! UTC calendar days and months. Counters live in memory only: a restart
+//! refills every bucket and resets every quota, so a quota bounds usage per
+//! node process rather than strictly per period.
+
+use std::fmt;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use ltp_trace::calendar::{civil_from_days, days_from_civil};
+
+use crate::TokenBucket;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Which identity limit refused a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throttle {
+    Rate,
+    DailyQuota,
+    MonthlyQuota,
+}
+
+impl Throttle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Throttle::Rate => "rate",
+            Throttle::DailyQuota => "daily_quota",
+            Throttle::MonthlyQuota => "monthly_quota",
+        }
+    }
+}
+
+impl fmt::Display for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Throttle::Rate => f.write_str("identity rate limit exceeded"),
+            Throttle::DailyQuota => f.write_str("daily message quota exhausted"),
+            Throttle::MonthlyQuota => f.write_str("monthly message quota exhausted"),
+        }
+    }
+}
+
+/// A refused message and when the identity may send again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    pub limit: Throttle,
+    pub retry_after: Duration,
+}
+
+/// Limits applying to one identity; `None` quotas are unlimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdentityQuota {
+    pub rate_rps: f64,
+    pub rate_burst: f64,
+    pub daily: Option<u64>,
+    pub monthly: Option<u64>,
+}
+
+#[derive(Debug)]
+struct IdentityUsage {
+    bucket: TokenBucket,
+    /// `(rate_rps, rate_burst)` the bucket was built with.
+    rate: (f64, f64),
+    day: u64,
+    daily: u64,
+    month: u64,
+    monthly: u64,
+}
+
+/// Shared per-identity buckets and quota counters.
+#[derive(Debug, Default)]
+pub struct IdentityLimits {
+    usage: DashMap<String, IdentityUsage>,
+}
+
+impl IdentityLimits {
+    /// Count one message from `auth_id` at `now` (Unix seconds), or say why
+    /// it must be refused. Refused messages do not count against quotas.
+    pub fn check(&self, auth_id: &str, quota: &IdentityQuota, now: u64) -> Result<(), Throttled> {
+        let day = now / SECS_PER_DAY;
+        let month = month_index(day);
+        let mut usage = self
+            .usage
+            .entry(auth_id.to_string())
+            .or_insert_with(|| IdentityUsage {
+                bucket: TokenBucket::new(quota.rate_rps, quota.rate_burst),
+                rate: (quota.rate_rps, quota.rate_burst),
+                day,
+                daily: 0,
+                month,
+                monthly: 0,
+            });
+        if usage.rate != (quota.rate_rps, quota.rate_burst) {
+            usage.bucket = TokenBucket::new(quota.rate_rps, quota.rate_burst);
+            usage.rate = (quota.rate_rps, quota.rate_burst);
+        }
+        if usage.day != day {
+            usage.day = day;
+            usage.daily = 0;
+        }
+        if usage.month != month {
+            usage.month = month;
+            usage.monthly = 0;
+        }
+
+        if quota.daily.is_some_and(|max| usage.daily >= max) {
+            return Err(Throttled {
+                limit: Throttle::DailyQuota,
+                retry_after: Duration::from_secs((day + 1) * SECS_PER_DAY - now),
+            });
+        }
+        if quota.monthly.is_some_and(|max| usage.monthly >= max) {
+            return Err(Throttled {
+                limit: Throttle::MonthlyQuota,
+                retry_after: Duration::from_secs(next_month_start(day) * SECS_PER_DAY - now),
+            });
+        }
+        if !usage.bucket.allow() {
+            return Err(Throttled {
+                limit: Throttle::Rate,
+                retry_after: usage.bucket.retry_after(),
+            });
+        }
+        usage.daily += 1;
+        usage.monthly += 1;
+        Ok(())
+    }
+
+    /// Messages counted for `auth_id` in the current day and month.
+    #[cfg(test)]
+    pub fn usage(&self, auth_id: &str) -> Option<(u64, u64)> {
+        self.usage.get(auth_id).map(|u| (u.daily, u.monthly))
+    }
+}
+
+fn month_index(day: u64) -> u64 {
+    let (year, month, _) = civil_from_days(day as i64);
+    year as u64 * 12 + u64::from(month - 1)
+}
+
+fn next_month_start(day: u64) -> u64 {
+    let start = match civil_from_days(day as i64) {
+        (year, 12, _) => days_from_civil(year + 1, 1, 1),
+        (year, month, _) => days_from_civil(year, month + 1, 1),
+    };
+    start as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-29T23:59:00Z
+    const LEAP_DAY_EVENING: u64 = 1_709_251_140;
+
+    fn quota(daily: Option<u64>, monthly: Option<u64>) -> IdentityQuota {
+        IdentityQuota {
+            rate_rps: 1_000.0,
+            rate_burst: 1_000.0,
+            daily,
+            monthly,
+        }
+    }
+
+    #[test]
+    fn computes_calendar_periods() {
+        let day = LEAP_DAY_EVENING / SECS_PER_DAY;
+        assert_eq!(month_index(day), 2024 * 12 + 1);
+        assert_eq!(next_month_start(day), day + 1);
+        let december = days_from_civil(2023, 12, 1) as u64;
+        assert_eq!(
+            next_month_start(december),
+            days_from_civil(2024, 1, 1) as u64
+        );
+    }
+
+    #[test]
+    fn enforces_quotas_and_resets_them() {
+        let limits = IdentityLimits::default();
+        let q = quota(Some(2), Some(3));
+        assert!(limits.check("k", &q, LEAP_DAY_EVENING).is_ok());
+        assert!(limits.check("k", &q, LEAP_DAY_EVENING).is_ok());
+        assert_eq!(
+            limits.check("k", &q, LEAP_DAY_EVENING),
+            Err(Throttled {
+                limit: Throttle::DailyQuota,
+                retry_after: Duration::from_secs(60),
+            })
+        );
+        assert_eq!(limits.usage("k"), Some((2, 2)));
+
+        // Next day is also the next month: both counters reset.
+        let next_day = LEAP_DAY_EVENING + 120;
+        assert!(limits.check("k", &q, next_day).is_ok());
+        assert_eq!(limits.usage("k"), Some((1, 1)));
+
+        let monthly = quota(None, Some(1));
+        assert!(limits.check("m", &monthly, LEAP_DAY_EVENING).is_ok());
+        assert_eq!(
+            limits
+                .check("m", &monthly, LEAP_DAY_EVENING)
+                .unwrap_err()
+                .limit,
+            Throttle::MonthlyQuota
+        );
+    }
+
+    #[test]
+    fn shares_one_bucket_per_identity() {
+        let limits = IdentityLimits::default();
+        let q = IdentityQuota {
+            rate_rps: 1.0,
+            rate_burst: 2.0,
+            daily: None,
+            monthly: None,
+        };
+        assert!(limits.check("k", &q, 0).is_ok());
+        assert!(limits.check("k", &q, 0).is_ok());
+        let throttled = limits.check("k", &q, 0).unwrap_err();
+        assert_eq!(throttled.limit, Throttle::Rate);
+        assert!(throttled.retry_after > Duration::ZERO);
+        assert!(throttled.retry_after <= Duration::from_secs(1));
+        assert!(limits.check("other", &q, 0).is_ok());
+        assert_eq!(limits.usage("k"), Some((2, 2)));
+
+        // A changed rate takes effect for identities that already have a bucket.
+        let raised = IdentityQuota {
+            rate_burst: 5.0,
+            ..q
+        };
+        for _ in 0..5 {
+            assert!(limits.check("k", &raised, 0).is_ok());
+        }
+        assert!(limits.check("k", &raised, 0).is_err());
+    }
+}
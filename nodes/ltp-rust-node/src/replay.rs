@@ -0,0 +1,351 @@
+//! Deterministic replay of recorded traces through `process_message`.
+//!
+//! Inbound entries are grouped per session and fed, in trace order, through
+//! the node logic with a fresh `LtpNodeState`. The frames produced for each
+//! input are diffed against the `out` entries recorded after it, with
+//! nondeterministic fields (JSON pointers) removed from both sides first.
+
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use futures_util::FutureExt;
+use ltp_trace::session::{NODE_DIRECTION, SESSION_CLOSE_FRAME_TYPE};
+use ltp_trace::{canonicalize_json, TraceEntry, TraceReader};
+use serde_json::Value;
+
+use crate::state::LtpNodeState;
+use crate::{process_message, AppContext, AuthContext};
+use ltp_protocol::node::{Correlated, LtpIncomingMessage};
+
+/// Fields that legitimately differ between a recording and a replay.
+pub const DEFAULT_NONDETERMINISTIC_FIELDS: &[&str] = &["/node_id"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// The recorded inbound frame no longer parses as an `LtpIncomingMessage`.
+    UnparseableInput { i: u64, error: String },
+    /// Processing the input panicked or produced a frame that does not
+    /// serialize, instead of producing replies.
+    ProcessingFailed { i: u64, error: String },
+    /// Replay produced a frame that differs from the recorded one.
+    Mismatch {
+        i: u64,
+        expected: Value,
+        actual: Value,
+    },
+    /// Replay produced a frame where none was recorded.
+    Unexpected { i: u64, actual: Value },
+    /// A frame was recorded that replay did not produce.
+    Missing { i: u64, expected: Value },
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub sessions: usize,
+    pub inputs: usize,
+    pub outputs_compared: usize,
+    /// Divergences per session, keyed by session id.
+    pub divergences: BTreeMap<String, Vec<Divergence>>,
+}
+
+impl ReplayReport {
+    pub fn is_clean(&self) -> bool {
+        self.divergences.values().all(Vec::is_empty)
+    }
+}
+
+/// One recorded input with the outputs recorded after it.
+struct Step {
+    input: TraceEntry,
+    recorded: Vec<TraceEntry>,
+}
+
+/// A session being replayed: its own node state and the step whose outputs
+/// are still being collected.
+struct SessionReplay {
+    ctx: AppContext,
+    auth: AuthContext,
+    step: Option<Step>,
+}
+
+/// Replays entries in the order they are read. Only sessions that have not
+/// closed yet are held, each with a single pending step, so memory follows
+/// the number of concurrent sessions rather than the size of the trace.
+struct Replayer<'a> {
+    base: &'a AppContext,
+    ignore: &'a [String],
+    sessions: HashMap<String, SessionReplay>,
+    report: ReplayReport,
+}
+
+impl<'a> Replayer<'a> {
+    fn new(base: &'a AppContext, ignore: &'a [String]) -> Self {
+        Self {
+            base,
+            ignore,
+            sessions: HashMap::new(),
+            report: ReplayReport::default(),
+        }
+    }
+
+    async fn push(&mut self, entry: TraceEntry) {
+        match entry.direction.as_str() {
+            "in" => {
+                if !self.sessions.contains_key(&entry.session_id) {
+                    let session = self.open(&entry.session_id).await;
+                    self.sessions.insert(entry.session_id.clone(), session);
+                }
+                let session_id = entry.session_id.clone();
+                let step = Step {
+                    input: entry,
+                    recorded: Vec::new(),
+                };
+                if let Some(done) = self.session(&session_id).and_then(|s| s.step.replace(step)) {
+                    self.replay(&session_id, done).await;
+                }
+            }
+            "out" => {
+                // Outputs before the first input (hello_ack) come from the
+                // handshake, not process_message.
+                if let Some(step) = self
+                    .session(&entry.session_id)
+                    .and_then(|s| s.step.as_mut())
+                {
+                    step.recorded.push(entry);
+                }
+            }
+            NODE_DIRECTION if entry.frame_type() == Some(SESSION_CLOSE_FRAME_TYPE) => {
+                self.close(&entry.session_id).await;
+            }
+            _ => {}
+        }
+    }
+
+    /// Replay what is still pending and return the report.
+    async fn finish(mut self) -> ReplayReport {
+        let open: Vec<String> = self.sessions.keys().cloned().collect();
+        for session_id in open {
+            self.close(&session_id).await;
+        }
+        self.report
+    }
+
+    async fn open(&mut self, session_id: &str) -> SessionReplay {
+        self.report.sessions += 1;
+        self.report
+            .divergences
+            .entry(session_id.to_string())
+            .or_default();
+
+        let mut ctx = self.base.clone();
+        ctx.state = Arc::new(LtpNodeState::new());
+        // perform_handshake registers the session before any message is processed.
+        ctx.state.touch_heartbeat(session_id).await;
+        SessionReplay {
+            ctx,
+            auth: AuthContext {
+                auth_id: "replay".to_string(),
+                session_id: session_id.to_string(),
+                policy: Default::default(),
+            },
+            step: None,
+        }
+    }
+
+    async fn close(&mut self, session_id: &str) {
+        if let Some(step) = self.session(session_id).and_then(|s| s.step.take()) {
+            self.replay(session_id, step).await;
+        }
+        self.sessions.remove(session_id);
+    }
+
+    fn session(&mut self, session_id: &str) -> Option<&mut SessionReplay> {
+        self.sessions.get_mut(session_id)
+    }
+
+    async fn replay(&mut self, session_id: &str, step: Step) {
+        let Some(session) = self.sessions.get(session_id) else {
+            return;
+        };
+        let ignore = self.ignore;
+        let report = &mut self.report;
+        let divergences = report
+            .divergences
+            .entry(session_id.to_string())
+            .or_default();
+
+        report.inputs += 1;
+        let i = step.input.i;
+        let incoming: Correlated<LtpIncomingMessage> =
+            match serde_json::from_value(step.input.frame) {
+                Ok(incoming) => incoming,
+                Err(err) => {
+                    divergences.push(Divergence::UnparseableInput {
+                        i,
+                        error: err.to_string(),
+                    });
+                    return;
+                }
+            };
+
+        let replies = AssertUnwindSafe(process_message(
+            incoming.message,
+            &session.ctx,
+            &session.auth,
+        ))
+        .catch_unwind()
+        .await;
+        // `None` is a message the node does not answer, such as orientation.
+        let produced: Result<Vec<Value>, String> = match replies {
+            Ok(replies) => replies
+                .unwrap_or_default()
+                .into_iter()
+                .map(|m| {
+                    let reply = Correlated::new(incoming.correlation_id.clone(), m);
+                    serde_json::to_value(reply)
+                        .map(|frame| normalize(frame, ignore))
+                        .map_err(|err| err.to_string())
+                })
+                .collect(),
+            Err(panic) => Err(panic_message(panic.as_ref())),
+        };
+        let produced = match produced {
+            Ok(produced) => produced,
+            Err(error) => {
+                divergences.push(Divergence::ProcessingFailed { i, error });
+                return;
+            }
+        };
+        let mut recorded = step
+            .recorded
+            .into_iter()
+            .map(|e| normalize(e.frame, ignore));
+
+        for actual in produced {
+            match recorded.next() {
+                Some(expected) => {
+                    report.outputs_compared += 1;
+                    if expected != actual {
+                        divergences.push(Divergence::Mismatch {
+                            i,
+                            expected,
+                            actual,
+                        });
+                    }
+                }
+                None => divergences.push(Divergence::Unexpected { i, actual }),
+            }
+        }
+        for expected in recorded {
+            // INVALID errors for unparseable or binary frames are sent by the
+            // connection loop without an inbound trace entry.
+            if !is_connection_level_error(&expected) {
+                divergences.push(Divergence::Missing { i, expected });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub async fn replay_entries(
+    base: &AppContext,
+    entries: &[TraceEntry],
+    ignore: &[String],
+) -> ReplayReport {
+    let mut replayer = Replayer::new(base, ignore);
+    for entry in entries {
+        replayer.push(entry.clone()).await;
+    }
+    replayer.finish().await
+}
+
+/// Replay a trace file, reading it one entry at a time.
+pub async fn replay_file(
+    base: &AppContext,
+    path: &Path,
+    ignore: &[String],
+) -> anyhow::Result<ReplayReport> {
+    let reader =
+        TraceReader::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut replayer = Replayer::new(base, ignore);
+    for record in reader {
+        replayer.push(record?.entry).await;
+    }
+    Ok(replayer.finish().await)
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "process_message panicked".to_string())
+}
+
+fn normalize(mut frame: Value, ignore: &[String]) -> Value {
+    for pointer in ignore {
+        remove_pointer(&mut frame, pointer);
+    }
+    canonicalize_json(&frame)
+}
+
+fn remove_pointer(value: &mut Value, pointer: &str) {
+    let Some((parent, last)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    let last = last.replace("~1", "/").replace("~0", "~");
+    match value.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.remove(&last);
+        }
+        Some(Value::Array(items)) => {
+            if let Ok(idx) = last.parse::<usize>() {
+                if idx < items.len() {
+                    items.remove(idx);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_connection_level_error(frame: &Value) -> bool {
+    frame.get("type").and_then(Value::as_str) == Some("error")
+        && frame.get("code").and_then(Value::as_str) == Some("INVALID")
+}
+
+/// `ltp-rust-node replay <trace.jsonl> [--ignore <json-pointer>]...`
+pub async fn run(base: AppContext, args: &[String]) -> anyhow::Result<bool> {
+    let (path, rest) = args
+        .split_first()
+        .context("usage: ltp-rust-node replay <trace.jsonl> [--ignore <json-pointer>]...")?;
+
+    let mut ignore: Vec<String> = DEFAULT_NONDETERMINISTIC_FIELDS
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut iter = rest.iter();
+    while let Some(flag) = iter.next() {
+        match (flag.as_str(), iter.next()) {
+            ("--ignore", Some(pointer)) => ignore.push(pointer.clone()),
+            _ => anyhow::bail!("unknown or incomplete option {}", flag),
+        }
+    }
+
+    let report = replay_file(&base, Path::new(path), &ignore).await?;
+    for (session_id, divergences) in &report.divergences {
+        for divergence in divergences {
+            println!("session {}: {:?}", session_id, divergence);
+        }
+    }
+    let divergent: usize = report.divergences.values().map(Vec::len).sum();
+    println!(
+        "Replayed {} sessions, {} inputs, {} outputs compared, {} divergences.",
+        report.sessions, report.inputs, report.outputs_compared, divergent
+    );
+    Ok(report.is_clean())
+}
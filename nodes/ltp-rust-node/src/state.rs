@@ -4,7 +4,7 @@ use std::time::{Duration, Instant};
 use dashmap::DashMap;
 use tokio::sync::Mutex;
 
-use crate::protocol::TimeOrientationBoostPayload;
+use ltp_protocol::node::TimeOrientationBoostPayload;
 
 #[derive(Debug, Clone)]
 pub struct SessionState {
@@ -1,14 +1,13 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use crate::node::build_route_suggestion;
-use crate::protocol::{
-    ErrorCode, LtpIncomingMessage, LtpOutgoingMessage, Sector, TimeOrientationBoostPayload,
-    TimeOrientationDirectionPayload,
-};
 use crate::state::LtpNodeState;
 use crate::{process_message, AppContext, AuthConfig, AuthMode, Config, Metrics, TokenBucket};
 use dashmap::DashMap;
-use std::collections::HashMap;
+use ltp_protocol::node::{
+    ErrorCode, LtpIncomingMessage, LtpOutgoingMessage, RouteSuggestion, Sector,
+    TimeOrientationBoostPayload, TimeOrientationDirectionPayload,
+};
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -43,14 +42,14 @@ async fn builds_route_suggestion_with_orientation() {
 
     let suggestion = build_route_suggestion(&state, "session-1").await;
     match suggestion {
-        LtpOutgoingMessage::RouteSuggestion {
+        LtpOutgoingMessage::RouteSuggestion(RouteSuggestion {
             suggested_sector,
             reason,
             debug,
             ..
-        } => {
-            assert_eq!(suggested_sector, Sector::FuturePlanning.to_string());
-            assert!(reason.unwrap_or_default().len() > 0);
+        }) => {
+            assert_eq!(suggested_sector, Sector::FuturePlanning);
+            assert!(!reason.unwrap_or_default().is_empty());
             let debug = debug.expect("debug block should be set");
             assert_eq!(debug.time_orientation.as_ref(), Some(&payload));
         }
@@ -63,13 +62,13 @@ async fn builds_default_route_when_no_state() {
     let state = LtpNodeState::new();
     let suggestion = build_route_suggestion(&state, "unknown-client").await;
     match suggestion {
-        LtpOutgoingMessage::RouteSuggestion {
+        LtpOutgoingMessage::RouteSuggestion(RouteSuggestion {
             suggested_sector,
             reason,
             debug,
             ..
-        } => {
-            assert_eq!(suggested_sector, Sector::BaseNeutral.to_string());
+        }) => {
+            assert_eq!(suggested_sector, Sector::BaseNeutral);
             assert_eq!(reason, Some("default".to_string()));
             assert!(debug.is_some());
         }
@@ -102,7 +101,7 @@ async fn validate_api_key_accepts_and_rejects() {
     let cfg = test_config();
     {
         let mut keys = cfg.auth.keys.write().unwrap();
-        keys.insert("valid-id".to_string(), "valid-key".to_string());
+        keys.insert_plaintext("valid-id".to_string(), "valid-key");
     }
     assert!(cfg.auth.validate_api_key("valid-key").unwrap().is_some());
     assert!(cfg.auth.validate_api_key("invalid").unwrap().is_none());
@@ -114,6 +113,7 @@ async fn rejects_mismatched_session_id() {
     let auth = crate::AuthContext {
         auth_id: "auth".to_string(),
         session_id: "correct-session".to_string(),
+        policy: Default::default(),
     };
     let result = process_message(
         LtpIncomingMessage::Heartbeat {
@@ -131,6 +131,82 @@ async fn rejects_mismatched_session_id() {
     }
 }
 
+#[tokio::test]
+async fn enforces_key_policy() {
+    let ctx = test_app_context();
+    ctx.state.touch_heartbeat("s1").await;
+    let heartbeat = || LtpIncomingMessage::Heartbeat {
+        session_id: "s1".to_string(),
+        timestamp_ms: 1,
+    };
+    let orientation = LtpIncomingMessage::Orientation {
+        session_id: "s1".to_string(),
+        focus_momentum: Some(0.5),
+        time_orientation: None,
+    };
+    let mut auth = crate::AuthContext {
+        auth_id: "partner".to_string(),
+        session_id: "s1".to_string(),
+        policy: Arc::new(crate::KeyPolicy {
+            allowed_types: Some(["heartbeat".to_string()].into()),
+            ..Default::default()
+        }),
+    };
+
+    let ok = process_message(heartbeat(), &ctx, &auth).await.unwrap();
+    assert!(matches!(ok[0], LtpOutgoingMessage::HeartbeatAck { .. }));
+    let refused = process_message(orientation, &ctx, &auth).await.unwrap();
+    assert!(matches!(
+        &refused[0],
+        LtpOutgoingMessage::Error { code: ErrorCode::Forbidden, message: Some(m), .. }
+            if m == "message type not allowed for this key"
+    ));
+
+    auth.policy = Arc::new(crate::KeyPolicy {
+        expires_at: Some(1),
+        ..Default::default()
+    });
+    let expired = process_message(heartbeat(), &ctx, &auth).await.unwrap();
+    assert!(matches!(
+        &expired[0],
+        LtpOutgoingMessage::Error { code: ErrorCode::Forbidden, message: Some(m), .. } if m == "key expired"
+    ));
+
+    let first = crate::KeySessionSlot::acquire(&ctx.key_sessions, "partner", Some(1)).unwrap();
+    assert!(crate::KeySessionSlot::acquire(&ctx.key_sessions, "partner", Some(1)).is_none());
+    drop(first);
+    assert!(ctx.key_sessions.is_empty());
+    assert!(crate::KeySessionSlot::acquire(&ctx.key_sessions, "partner", Some(1)).is_some());
+}
+
+#[tokio::test]
+async fn limits_concurrent_connections_per_ip() {
+    let mut config = test_config();
+    config.max_connections_per_ip = 2;
+    let ctx = AppContext {
+        config: Arc::new(config),
+        ..test_app_context()
+    };
+    let ip: std::net::IpAddr = "203.0.113.5".parse().unwrap();
+    let other: std::net::IpAddr = "203.0.113.6".parse().unwrap();
+
+    let first = crate::acquire_ip_slot(&ctx, ip).unwrap();
+    let _second = crate::acquire_ip_slot(&ctx, ip).unwrap();
+    assert!(crate::acquire_ip_slot(&ctx, ip).is_none());
+    assert!(crate::acquire_ip_slot(&ctx, other).is_some());
+    assert_eq!(
+        ctx.metrics
+            .connections_rejected
+            .with_label_values(&["per_ip"])
+            .get(),
+        1
+    );
+
+    drop(first);
+    assert!(crate::acquire_ip_slot(&ctx, ip).is_some());
+    assert!(!ctx.ip_connections.contains_key(&other));
+}
+
 #[tokio::test]
 async fn token_bucket_enforces_limit() {
     let mut bucket = TokenBucket::new(2.0, 2.0);
@@ -139,12 +215,95 @@ async fn token_bucket_enforces_limit() {
     assert!(!bucket.allow());
 }
 
+#[test]
+fn refusals_before_parsing_keep_the_correlation_id() {
+    let text = r#"{"type": "route_request", "correlation_id": "r-7", "session_id": "s"}"#;
+    assert_eq!(crate::correlation_id_of(text).as_deref(), Some("r-7"));
+    assert_eq!(crate::correlation_id_of(r#"{"type": "heartbeat"}"#), None);
+    assert_eq!(crate::correlation_id_of("not json"), None);
+}
+
+fn trace_entry(i: u64, direction: &str, frame: serde_json::Value) -> ltp_trace::TraceEntry {
+    ltp_trace::TraceEntry {
+        i,
+        timestamp_ms: 1_000 + i,
+        direction: direction.to_string(),
+        session_id: "replay-session".to_string(),
+        frame,
+        redactions: Default::default(),
+        prev_hash: String::new(),
+        session_seq: None,
+        session_prev_hash: None,
+        hash: String::new(),
+        signature: None,
+        alg: None,
+    }
+}
+
+#[tokio::test]
+async fn replay_matches_recorded_outputs() {
+    let ctx = test_app_context();
+    let mut entries = vec![
+        trace_entry(
+            0,
+            "out",
+            serde_json::json!({"type": "hello_ack", "node_id": "other-node", "accepted": true, "session_id": "replay-session"}),
+        ),
+        trace_entry(
+            1,
+            "in",
+            serde_json::json!({"type": "orientation", "session_id": "replay-session", "focus_momentum": 0.4, "time_orientation": {"direction": "past", "strength": 0.5}}),
+        ),
+        trace_entry(
+            2,
+            "in",
+            serde_json::json!({"type": "route_request", "correlation_id": "r-1", "session_id": "replay-session"}),
+        ),
+        trace_entry(
+            3,
+            "out",
+            serde_json::json!({"type": "route_suggestion", "correlation_id": "r-1", "session_id": "replay-session", "suggested_sector": "retrospective_safe", "reason": "client leaning towards past", "debug": {"focus_momentum": 0.4, "time_orientation": {"direction": "past", "strength": 0.5}}}),
+        ),
+        trace_entry(
+            4,
+            "in",
+            serde_json::json!({"type": "heartbeat", "session_id": "replay-session", "timestamp_ms": 7}),
+        ),
+        trace_entry(
+            5,
+            "out",
+            serde_json::json!({"type": "heartbeat_ack", "session_id": "replay-session", "timestamp_ms": 7}),
+        ),
+    ];
+    let ignore = vec!["/node_id".to_string()];
+
+    let report = crate::replay::replay_entries(&ctx, &entries, &ignore).await;
+    assert_eq!(report.sessions, 1);
+    assert_eq!(report.inputs, 3);
+    assert_eq!(report.outputs_compared, 2);
+    assert!(
+        report.is_clean(),
+        "unexpected divergences: {:?}",
+        report.divergences
+    );
+
+    entries[3].frame["suggested_sector"] = "future_planning".into();
+    let report = crate::replay::replay_entries(&ctx, &entries, &ignore).await;
+    let divergences = &report.divergences["replay-session"];
+    assert_eq!(divergences.len(), 1);
+    assert!(matches!(
+        divergences[0],
+        crate::replay::Divergence::Mismatch { i: 2, .. }
+    ));
+}
+
 fn test_config() -> Config {
     Config {
         addr: "127.0.0.1:1".to_string(),
         node_id: "node-test".to_string(),
         metrics_addr: "127.0.0.1:9090".to_string(),
         max_connections: 10,
+        max_connections_per_ip: 10,
         max_message_bytes: 1024,
         max_sessions_total: 100,
         handshake_timeout_ms: 1000,
@@ -155,9 +314,14 @@ fn test_config() -> Config {
         ip_rate_limit_rps: 10.0,
         ip_rate_limit_burst: 20.0,
         ip_rate_limit_ttl_secs: 60,
+        identity_rate_limit_rps: 100.0,
+        identity_rate_limit_burst: 100.0,
+        identity_daily_quota: 0,
+        identity_monthly_quota: 0,
+        ip_bans: crate::ban::BanConfig::default(),
         auth: AuthConfig {
             mode: AuthMode::ApiKey,
-            keys: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            keys: Arc::new(std::sync::RwLock::new(Default::default())),
             jwt_secret: None,
             keys_file: None,
             keys_reload_interval: Duration::from_secs(60),
@@ -165,7 +329,9 @@ fn test_config() -> Config {
             fail_closed: Arc::new(AtomicBool::new(false)),
         },
         trust_proxy: false,
+        proxy_protocol: false,
         audit_log_file: "test_audit.log".to_string(),
+        allow_proxy_cidr: vec![],
     }
 }
 
@@ -199,11 +365,17 @@ fn test_app_context() -> AppContext {
     // But refactoring all tests is annoying.
     // Hack: spawn a thread to run the runtime block_on.
     let log_file = config.audit_log_file.clone();
-    let tracer = Arc::new(std::thread::spawn(move || {
-        tokio::runtime::Runtime::new().unwrap().block_on(async {
-            crate::trace::TraceLogger::new(&log_file).await.unwrap()
+    let tracer = Arc::new(
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                crate::trace::open_trace_logger(&log_file, None)
+                    .await
+                    .unwrap()
+            })
         })
-    }).join().unwrap());
+        .join()
+        .unwrap(),
+    );
 
     AppContext {
         config,
@@ -212,5 +384,91 @@ fn test_app_context() -> AppContext {
         ip_limiters: Arc::new(DashMap::new()),
         log_throttle: Arc::new(crate::LogThrottle::default()),
         tracer,
+        key_sessions: Arc::new(DashMap::new()),
+        identity_limits: Default::default(),
+        bans: Arc::new(crate::ban::BanList::new(Default::default())),
+        ip_connections: Arc::new(DashMap::new()),
+    }
+}
+
+#[tokio::test]
+async fn records_session_open_and_close_in_trace() {
+    use futures_util::{SinkExt, StreamExt};
+    use ltp_trace::{SessionRecord, TraceReader};
+    use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let trace_path = dir.path().join("trace.jsonl");
+    let mut config = test_config();
+    config.audit_log_file = trace_path.display().to_string();
+    config
+        .auth
+        .keys
+        .write()
+        .unwrap()
+        .insert_plaintext("key-1".to_string(), "secret");
+    let ctx = AppContext {
+        config: Arc::new(config),
+        state: Arc::new(LtpNodeState::new()),
+        metrics: Arc::new(Metrics::new().expect("metrics")),
+        ip_limiters: Arc::new(DashMap::new()),
+        log_throttle: Arc::new(crate::LogThrottle::default()),
+        tracer: Arc::new(
+            crate::trace::open_trace_logger(&trace_path, None)
+                .await
+                .unwrap(),
+        ),
+        key_sessions: Arc::new(DashMap::new()),
+        identity_limits: Default::default(),
+        bans: Arc::new(crate::ban::BanList::new(Default::default())),
+        ip_connections: Arc::new(DashMap::new()),
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, peer) = listener.accept().await.unwrap();
+        crate::handle_connection(stream, peer, ctx, None).await
+    });
+
+    let mut request = format!("ws://{}", addr).into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("x-api-key", "secret".parse().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    let hello =
+        serde_json::json!({"type": "hello", "api_key": "secret", "client_label": "teller-7"});
+    ws.send(Message::Text(hello.to_string())).await.unwrap();
+    let ack = ws.next().await.unwrap().unwrap();
+    assert!(ack.to_text().unwrap().contains("hello_ack"));
+    ws.close(None).await.unwrap();
+    server.await.unwrap().unwrap();
+
+    let entries: Vec<_> = TraceReader::open(&trace_path)
+        .unwrap()
+        .map(|record| record.unwrap().entry)
+        .collect();
+    assert_eq!(entries.len(), 3);
+    assert!(entries
+        .iter()
+        .all(|e| e.session_id == entries[0].session_id));
+    match SessionRecord::from_entry(&entries[0]) {
+        Some(SessionRecord::SessionOpen(open)) => {
+            assert_eq!(open.auth_id, "key-1");
+            assert_eq!(open.identity_source, "api_key");
+            assert_eq!(open.client_ip, "127.0.0.1");
+            assert_eq!(open.proxy_hop, None);
+            assert_eq!(open.client_label.as_deref(), Some("teller-7"));
+            assert_eq!(open.node_id, "node-test");
+        }
+        other => panic!("expected session_open, got {:?}", other),
+    }
+    assert_eq!(entries[1].frame_type(), Some("hello_ack"));
+    match SessionRecord::from_entry(&entries[2]) {
+        Some(SessionRecord::SessionClose(close)) => {
+            assert_eq!(close.auth_id, "key-1");
+            assert_eq!(close.reason, "client_closed");
+        }
+        other => panic!("expected session_close, got {:?}", other),
     }
 }
@@ -5,7 +5,7 @@ use serde_json::json;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== LTP Rust Client Example ===\n");
 
-    let mut client = LtpClient::new("ws://localhost:8080", "rust-example-1")
+    let client = LtpClient::new("ws://localhost:8080", "rust-example-1")
         .with_device_fingerprint("rust-example")
         .with_intent("resonant_link")
         .with_default_context_tag("evening_reflection")
@@ -13,7 +13,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_heartbeat_timeout(45_000);
 
     println!("Connecting to LTP server...");
-    client.connect().await?;
+    let client = client.connect().await?;
     println!("✓ Connected!\n");
 
     // Send initial state update
@@ -75,4 +75,3 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
-
@@ -1,116 +1,112 @@
+use crate::connection::ConnectionTask;
 use crate::crypto;
-use crate::error::{LtpError, Result};
+use crate::error::{IntegrityError, LtpError, Result};
+use crate::events::{CloseReason, LtpEvent, LtpEvents};
+use crate::handle::LtpHandle;
+use crate::outbox::Outbox;
+use crate::reconnect::ReconnectPolicy;
 use crate::types::*;
 use futures_util::{SinkExt, StreamExt};
-use serde::Serialize;
-use std::time::Instant;
+use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
-type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
 
+/// Inbound nonces remembered for replay detection.
+const MAX_SEEN_NONCES: usize = 10_000;
+
+/// Builder for a connection to an LTP server.
+///
+/// Configure it, take its `events()`, then `connect` to get an `LtpHandle`.
 pub struct LtpClient {
-    url: String,
-    client_id: String,
-    device_fingerprint: Option<String>,
-    intent: Option<String>,
-    capabilities: Option<Vec<String>>,
-    metadata: Option<serde_json::Value>,
+    dialer: Dialer,
     default_context_tag: Option<String>,
-    thread_id: Option<String>,
-    session_id: Option<String>,
-    heartbeat_interval_ms: u64,
-    heartbeat_timeout_ms: u64,
-    is_connected: bool,
-    last_pong_time: Option<Instant>,
-    write: Option<futures_util::stream::SplitSink<WsStream, Message>>,
-    // v0.6.0 Security features
-    enable_ecdh_key_exchange: bool,
-    enable_metadata_encryption: bool,
-    secret_key: Option<String>,
-    session_mac_key: Option<String>,
-    ecdh_private_key: Option<String>,
-    ecdh_public_key: Option<String>,
-    session_encryption_key: Option<String>,
-    last_sent_hash: Option<String>,
-    last_received_hash: Option<String>,
-    seen_nonces: std::collections::HashSet<String>,
+    content_encoding: ContentEncoding,
+    reconnect: Option<ReconnectPolicy>,
+    outbox: Option<Outbox>,
+    request_timeout_ms: u64,
+    session: Session,
+    events_tx: mpsc::UnboundedSender<LtpEvent>,
+    events_rx: Option<mpsc::UnboundedReceiver<LtpEvent>>,
 }
 
 impl LtpClient {
     /// Create a new LTP client instance
     pub fn new(url: impl Into<String>, client_id: impl Into<String>) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
         Self {
-            url: url.into(),
-            client_id: client_id.into(),
-            device_fingerprint: None,
-            intent: Some("resonant_link".to_string()),
-            capabilities: Some(vec![
-                "state-update".to_string(),
-                "events".to_string(),
-                "ping-pong".to_string(),
-            ]),
-            metadata: None,
+            dialer: Dialer {
+                url: url.into(),
+                client_id: client_id.into(),
+                device_fingerprint: None,
+                intent: Some("resonant_link".to_string()),
+                capabilities: Some(vec![
+                    "state-update".to_string(),
+                    "events".to_string(),
+                    "ping-pong".to_string(),
+                ]),
+                metadata: None,
+                heartbeat_interval_ms: 15_000,
+                heartbeat_timeout_ms: 45_000,
+                handshake_timeout_ms: 10_000,
+                // v0.6.0 Security features initialization
+                enable_ecdh_key_exchange: false,
+                enable_metadata_encryption: false,
+                secret_key: None,
+            },
             default_context_tag: None,
-            thread_id: None,
-            session_id: None,
-            heartbeat_interval_ms: 15_000,
-            heartbeat_timeout_ms: 45_000,
-            is_connected: false,
-            last_pong_time: None,
-            write: None,
-            // v0.6.0 Security features initialization
-            enable_ecdh_key_exchange: false,
-            enable_metadata_encryption: false,
-            secret_key: None,
-            session_mac_key: None,
-            ecdh_private_key: None,
-            ecdh_public_key: None,
-            session_encryption_key: None,
-            last_sent_hash: None,
-            last_received_hash: None,
-            seen_nonces: std::collections::HashSet::new(),
+            content_encoding: ContentEncoding::Json,
+            reconnect: None,
+            outbox: None,
+            request_timeout_ms: 30_000,
+            session: Session::default(),
+            events_tx,
+            events_rx: Some(events_rx),
         }
     }
 
     /// Enable ECDH key exchange (v0.6+)
     pub fn with_ecdh_key_exchange(mut self, enable: bool) -> Self {
-        self.enable_ecdh_key_exchange = enable;
+        self.dialer.enable_ecdh_key_exchange = enable;
         self
     }
 
     /// Enable metadata encryption (v0.6+)
     pub fn with_metadata_encryption(mut self, enable: bool) -> Self {
-        self.enable_metadata_encryption = enable;
+        self.dialer.enable_metadata_encryption = enable;
         self
     }
 
     /// Set session encryption key directly (v0.6+)
     pub fn with_session_encryption_key(mut self, encryption_key: impl Into<String>) -> Self {
-        self.session_encryption_key = Some(encryption_key.into());
+        self.session.session_encryption_key = Some(encryption_key.into());
         self
     }
 
     /// Set secret key for authenticated ECDH and signing (v0.6+)
     pub fn with_secret_key(mut self, secret_key: impl Into<String>) -> Self {
-        self.secret_key = Some(secret_key.into());
+        self.dialer.secret_key = Some(secret_key.into());
         self
     }
 
     /// Set session MAC key (v0.6+)
     pub fn with_session_mac_key(mut self, mac_key: impl Into<String>) -> Self {
-        self.session_mac_key = Some(mac_key.into());
+        self.session.session_mac_key = Some(mac_key.into());
         self
     }
 
     /// Set device fingerprint
     pub fn with_device_fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
-        self.device_fingerprint = Some(fingerprint.into());
+        self.dialer.device_fingerprint = Some(fingerprint.into());
         self
     }
 
     /// Set intent
     pub fn with_intent(mut self, intent: impl Into<String>) -> Self {
-        self.intent = Some(intent.into());
+        self.dialer.intent = Some(intent.into());
         self
     }
 
@@ -120,105 +116,84 @@ impl LtpClient {
         self
     }
 
+    /// Set the encoding `send_state_update` prefers. With `Toon`, tabular
+    /// data is sent as a TOON table named after the kind and anything else
+    /// as JSON.
+    pub fn with_content_encoding(mut self, encoding: ContentEncoding) -> Self {
+        self.content_encoding = encoding;
+        self
+    }
+
     /// Set heartbeat interval
     pub fn with_heartbeat_interval(mut self, interval_ms: u64) -> Self {
-        self.heartbeat_interval_ms = interval_ms;
+        self.dialer.heartbeat_interval_ms = interval_ms;
         self
     }
 
     /// Set heartbeat timeout
     pub fn with_heartbeat_timeout(mut self, timeout_ms: u64) -> Self {
-        self.heartbeat_timeout_ms = timeout_ms;
+        self.dialer.heartbeat_timeout_ms = timeout_ms;
         self
     }
 
-    /// Connect to the LTP server
-    pub async fn connect(&mut self) -> Result<()> {
-        let url = url::Url::parse(&self.url)
-            .map_err(|e| LtpError::InvalidState(format!("Invalid URL: {}", e)))?;
-
-        let (ws_stream, _) = connect_async(url).await?;
-        let (write, mut read) = ws_stream.split();
-        self.write = Some(write);
-
-        // Send handshake
-        if self.thread_id.is_some() {
-            self.send_handshake_resume().await?;
-        } else {
-            self.send_handshake_init().await?;
-        }
-
-        // Wait for handshake_ack
-        let ack = self.wait_for_handshake_ack(&mut read).await?;
-        self.thread_id = Some(ack.thread_id.clone());
-        self.session_id = Some(ack.session_id.clone());
-        self.is_connected = true;
-        self.last_pong_time = Some(Instant::now());
-
-        // Update heartbeat interval if provided by server
-        if ack.heartbeat_interval_ms > 0 {
-            self.heartbeat_interval_ms = ack.heartbeat_interval_ms;
-        }
-
-        // Handle ECDH key exchange (v0.6+)
-        if self.enable_ecdh_key_exchange {
-            self.handle_ecdh_key_exchange(&ack)?;
-        }
-
-        // Start message handling loop (simplified - in production would be more robust)
-        // Note: In a real implementation, this would need to share state with the client
-        // For now, this is a simplified version
-        tokio::spawn(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        // Handle incoming messages
-                        // TODO: Parse and process LTP messages with security features
-                        eprintln!("Received: {}", text);
-                    }
-                    Ok(Message::Close(_)) => {
-                        break;
-                    }
-                    Err(e) => {
-                        eprintln!("WebSocket error: {}", e);
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-        });
-
-        Ok(())
+    /// Set how long opening the socket and completing the handshake may
+    /// take before `connect` fails with `HandshakeTimeout`
+    pub fn with_handshake_timeout(mut self, timeout_ms: u64) -> Self {
+        self.dialer.handshake_timeout_ms = timeout_ms;
+        self
     }
 
-    /// Send a state update
-    pub async fn send_state_update<T: Serialize>(&mut self, kind: &str, data: T) -> Result<()> {
-        if !self.is_connected {
-            return Err(LtpError::NotConnected);
-        }
-
-        let envelope = self.build_state_update_envelope(kind, data)?;
-        self.send_envelope(envelope).await
+    /// Set how long `LtpHandle::request` waits for a reply
+    pub fn with_request_timeout(mut self, timeout_ms: u64) -> Self {
+        self.request_timeout_ms = timeout_ms;
+        self
     }
 
-    /// Send an event
-    pub async fn send_event<T: Serialize>(&mut self, event_type: &str, data: T) -> Result<()> {
-        if !self.is_connected {
-            return Err(LtpError::NotConnected);
-        }
+    /// Reconnect automatically, resuming the thread, whenever an established
+    /// connection is lost.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
 
-        let envelope = self.build_event_envelope(event_type, data)?;
-        self.send_envelope(envelope).await
+    /// Queue messages sent while disconnected and send them, in order, once
+    /// connected again.
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
     }
 
-    /// Get current thread ID
-    pub fn thread_id(&self) -> Option<&String> {
-        self.thread_id.as_ref()
+    /// Take the stream of inbound events. Returns `None` after the first call.
+    ///
+    /// Events from every connection of this client arrive on the same stream,
+    /// buffered until received; see `LtpEvents`.
+    pub fn events(&mut self) -> Option<LtpEvents> {
+        self.events_rx.take().map(LtpEvents::new)
     }
 
-    /// Get current session ID
-    pub fn session_id(&self) -> Option<&String> {
-        self.session_id.as_ref()
+    /// Connect to the LTP server and start the connection task.
+    ///
+    /// The task owns the socket and the session state; the returned handle
+    /// sends commands to it and can be cloned freely.
+    pub async fn connect(self) -> Result<LtpHandle> {
+        let connection = self.dialer.dial(self.session.thread_id.as_deref()).await?;
+        let client_id = self.dialer.client_id.clone();
+        let task = ConnectionTask::new(
+            self.dialer,
+            self.reconnect,
+            self.session,
+            self.outbox,
+            self.events_tx,
+        );
+        let (commands, status) = task.spawn(connection);
+        Ok(LtpHandle::new(
+            commands,
+            status,
+            client_id,
+            self.default_context_tag,
+            self.content_encoding,
+            Duration::from_millis(self.request_timeout_ms),
+        ))
     }
 
     /// Prepare an envelope with all security features applied without sending it over the network.
@@ -229,23 +204,143 @@ impl LtpClient {
         &mut self,
         envelope: LtpEnvelope,
     ) -> Result<LtpEnvelope> {
-        self.finalize_envelope(envelope)
+        self.session.finalize(envelope, &self.dialer)
+    }
+}
+
+/// Connection and message security settings.
+#[derive(Debug, Clone)]
+pub(crate) struct Dialer {
+    url: String,
+    pub client_id: String,
+    device_fingerprint: Option<String>,
+    intent: Option<String>,
+    capabilities: Option<Vec<String>>,
+    metadata: Option<serde_json::Value>,
+    pub heartbeat_interval_ms: u64,
+    pub heartbeat_timeout_ms: u64,
+    handshake_timeout_ms: u64,
+    // v0.6.0 Security features
+    enable_ecdh_key_exchange: bool,
+    enable_metadata_encryption: bool,
+    secret_key: Option<String>,
+}
+
+/// A socket that completed the handshake.
+pub(crate) struct Connection {
+    pub ws: WsStream,
+    pub ack: HandshakeAck,
+    /// Session keys derived by ECDH for this connection, if enabled.
+    pub keys: Option<SessionKeys>,
+}
+
+pub(crate) struct SessionKeys {
+    encryption: String,
+    mac: String,
+}
+
+/// The client's half of an ECDH exchange, sent with `handshake_init` or
+/// `handshake_resume`.
+struct EcdhOffer {
+    public_key: String,
+    private_key: String,
+    signature: Option<String>,
+    timestamp: Option<i64>,
+}
+
+impl Dialer {
+    /// Open a socket and complete the handshake, resuming `thread_id` if
+    /// given. A rejected resume that suggests a new thread falls back to
+    /// `handshake_init` on the same socket.
+    ///
+    /// Fails with `HandshakeTimeout` if the whole exchange takes longer
+    /// than the handshake timeout.
+    pub(crate) async fn dial(&self, thread_id: Option<&str>) -> Result<Connection> {
+        let timeout = Duration::from_millis(self.handshake_timeout_ms);
+        tokio::time::timeout(timeout, self.handshake(thread_id))
+            .await
+            .map_err(|_| LtpError::HandshakeTimeout(timeout))?
+    }
+
+    async fn handshake(&self, thread_id: Option<&str>) -> Result<Connection> {
+        let url = url::Url::parse(&self.url)
+            .map_err(|e| LtpError::InvalidState(format!("Invalid URL: {}", e)))?;
+
+        let (mut ws, _) = connect_async(url).await?;
+
+        if let Some(thread_id) = thread_id {
+            // Every connection gets fresh ECDH keys, resumed or not.
+            let offer = self.ecdh_offer();
+            self.send_handshake_resume(&mut ws, thread_id, offer.as_ref())
+                .await?;
+            match wait_for_handshake_ack(&mut ws).await {
+                Ok(ack) => return self.connection(ws, ack, offer),
+                Err(LtpError::HandshakeRejected {
+                    suggest_new: true, ..
+                }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let offer = self.ecdh_offer();
+        self.send_handshake_init(&mut ws, offer.as_ref()).await?;
+        let ack = wait_for_handshake_ack(&mut ws).await?;
+        self.connection(ws, ack, offer)
     }
 
-    // Private helpers
+    fn connection(
+        &self,
+        ws: WsStream,
+        ack: HandshakeAck,
+        offer: Option<EcdhOffer>,
+    ) -> Result<Connection> {
+        // Handle ECDH key exchange (v0.6+)
+        let keys = match offer {
+            Some(offer) => Some(self.handle_ecdh_key_exchange(&ack, &offer.private_key)?),
+            None => None,
+        };
+        Ok(Connection { ws, ack, keys })
+    }
 
-    async fn send_handshake_init(&mut self) -> Result<()> {
-        // Generate ECDH key pair if enabled
-        let (ecdh_public_key, _ecdh_private_key) = if self.enable_ecdh_key_exchange {
-            let (pub_key, priv_key) = crypto::generate_ecdh_key_pair();
-            self.ecdh_public_key = Some(pub_key.clone());
-            self.ecdh_private_key = Some(priv_key.clone());
-            (Some(pub_key), Some(priv_key))
-        } else {
-            (None, None)
+    /// Generate an ECDH key pair if enabled, signed when a secret key is set
+    fn ecdh_offer(&self) -> Option<EcdhOffer> {
+        if !self.enable_ecdh_key_exchange {
+            return None;
+        }
+        let (public_key, private_key) = crypto::generate_ecdh_key_pair();
+
+        // Sign ECDH public key if secret_key is available (v0.6+ authenticated ECDH)
+        let (signature, timestamp) = match self.secret_key {
+            Some(ref secret_key) => {
+                let timestamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as i64;
+                let signature = crypto::sign_ecdh_public_key(
+                    &public_key,
+                    &self.client_id,
+                    timestamp,
+                    secret_key,
+                );
+                (Some(signature), Some(timestamp))
+            }
+            None => (None, None),
         };
 
-        let mut init = HandshakeInit {
+        Some(EcdhOffer {
+            public_key,
+            private_key,
+            signature,
+            timestamp,
+        })
+    }
+
+    async fn send_handshake_init(
+        &self,
+        ws: &mut WsStream,
+        offer: Option<&EcdhOffer>,
+    ) -> Result<()> {
+        let init = HandshakeInit {
             r#type: "handshake_init".to_string(),
             ltp_version: "0.6".to_string(),
             client_id: self.client_id.clone(),
@@ -253,93 +348,59 @@ impl LtpClient {
             intent: self.intent.clone(),
             capabilities: self.capabilities.clone(),
             metadata: self.metadata.clone(),
-            client_public_key: None,
-            client_ecdh_public_key: None,
-            client_ecdh_signature: None,
-            client_ecdh_timestamp: None,
-            key_agreement: None,
+            client_public_key: offer.map(|o| o.public_key.clone()), // Legacy field
+            client_ecdh_public_key: offer.map(|o| o.public_key.clone()),
+            client_ecdh_signature: offer.and_then(|o| o.signature.clone()),
+            client_ecdh_timestamp: offer.and_then(|o| o.timestamp),
+            key_agreement: offer.map(|_| key_agreement()),
         };
 
-        // Add ECDH public key if available
-        if let Some(ref pub_key) = ecdh_public_key {
-            init.client_ecdh_public_key = Some(pub_key.clone());
-            init.client_public_key = Some(pub_key.clone()); // Legacy field
-            init.key_agreement = Some(serde_json::json!({
-                "algorithm": "secp256r1",
-                "method": "ecdh",
-                "hkdf": "sha256"
-            }));
-
-            // Sign ECDH public key if secret_key is available (v0.6+ authenticated ECDH)
-            if let Some(ref secret_key) = self.secret_key {
-                let timestamp = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64;
-
-                let signature =
-                    crypto::sign_ecdh_public_key(pub_key, &self.client_id, timestamp, secret_key);
-
-                init.client_ecdh_signature = Some(signature);
-                init.client_ecdh_timestamp = Some(timestamp);
-            }
-        }
-
         let json = serde_json::to_string(&init)?;
-        self.send_text(json).await
+        ws.send(Message::Text(json)).await?;
+        Ok(())
     }
 
-    async fn send_handshake_resume(&mut self) -> Result<()> {
+    async fn send_handshake_resume(
+        &self,
+        ws: &mut WsStream,
+        thread_id: &str,
+        offer: Option<&EcdhOffer>,
+    ) -> Result<()> {
         let resume = HandshakeResume {
             r#type: "handshake_resume".to_string(),
             ltp_version: "0.6".to_string(),
             client_id: self.client_id.clone(),
-            thread_id: self.thread_id.clone().unwrap(),
+            thread_id: thread_id.to_string(),
             resume_reason: "automatic_reconnect".to_string(),
+            client_public_key: offer.map(|o| o.public_key.clone()), // Legacy field
+            client_ecdh_public_key: offer.map(|o| o.public_key.clone()),
+            client_ecdh_signature: offer.and_then(|o| o.signature.clone()),
+            client_ecdh_timestamp: offer.and_then(|o| o.timestamp),
+            key_agreement: offer.map(|_| key_agreement()),
         };
 
         let json = serde_json::to_string(&resume)?;
-        self.send_text(json).await
-    }
-
-    async fn wait_for_handshake_ack(
-        &mut self,
-        read: &mut futures_util::stream::SplitStream<WsStream>,
-    ) -> Result<HandshakeAck> {
-        loop {
-            if let Some(Ok(Message::Text(text))) = read.next().await {
-                if let Ok(ack) = serde_json::from_str::<HandshakeAck>(&text) {
-                    return Ok(ack);
-                }
-                if let Ok(reject) = serde_json::from_str::<HandshakeReject>(&text) {
-                    if self.thread_id.is_some() {
-                        // Resume was rejected, try init
-                        self.thread_id = None;
-                        return Err(LtpError::Handshake(format!(
-                            "Resume rejected: {}",
-                            reject.reason
-                        )));
-                    } else {
-                        return Err(LtpError::Handshake(reject.reason));
-                    }
-                }
-            }
-        }
+        ws.send(Message::Text(json)).await?;
+        Ok(())
     }
 
     /// Handle ECDH key exchange and derive session keys (v0.6+)
-    fn handle_ecdh_key_exchange(&mut self, ack: &HandshakeAck) -> Result<()> {
+    fn handle_ecdh_key_exchange(
+        &self,
+        ack: &HandshakeAck,
+        private_key: &str,
+    ) -> Result<SessionKeys> {
         // Get server's ECDH public key
         let server_ecdh_public_key = ack
             .server_ecdh_public_key
             .as_ref()
-            .or_else(|| ack.server_public_key.as_ref())
+            .or(ack.server_public_key.as_ref())
             .ok_or_else(|| {
                 LtpError::InvalidState("Server did not provide ECDH public key".to_string())
             })?;
 
         // Verify server's ECDH public key signature if available (v0.6+ authenticated ECDH)
-        if let (Some(ref signature), Some(timestamp)) = (
+        if let (Some(signature), Some(timestamp)) = (
             ack.server_ecdh_signature.as_ref(),
             ack.server_ecdh_timestamp,
         ) {
@@ -361,66 +422,136 @@ impl LtpClient {
         }
 
         // Derive shared secret
-        let private_key = self.ecdh_private_key.as_ref().ok_or_else(|| {
-            LtpError::InvalidState("Client ECDH private key not found".to_string())
-        })?;
-
         let shared_secret = crypto::derive_shared_secret(private_key, server_ecdh_public_key)
             .map_err(|e| {
                 LtpError::InvalidState(format!("Failed to derive shared secret: {}", e))
             })?;
 
         // Derive session keys using HKDF
-        let session_id = self
-            .session_id
-            .as_ref()
-            .ok_or_else(|| LtpError::InvalidState("Session ID not available".to_string()))?;
-
         let (encryption_key, mac_key, _iv_key) =
-            crypto::derive_session_keys(&shared_secret, session_id).map_err(|e| {
+            crypto::derive_session_keys(&shared_secret, &ack.session_id).map_err(|e| {
                 LtpError::InvalidState(format!("Failed to derive session keys: {}", e))
             })?;
 
-        // Store session keys
-        self.session_encryption_key = Some(encryption_key);
-        self.session_mac_key = Some(mac_key);
+        Ok(SessionKeys {
+            encryption: encryption_key,
+            mac: mac_key,
+        })
+    }
+}
 
-        Ok(())
+fn key_agreement() -> serde_json::Value {
+    serde_json::json!({
+        "algorithm": "secp256r1",
+        "method": "ecdh",
+        "hkdf": "sha256"
+    })
+}
+
+/// Wait for the next text frame of a handshake, skipping WebSocket control
+/// frames. The end of the stream and binary frames are errors.
+pub(crate) async fn next_handshake_text(ws: &mut WsStream) -> Result<String> {
+    loop {
+        return match ws.next().await {
+            Some(Ok(Message::Text(text))) => Ok(text),
+            Some(Ok(Message::Binary(_))) => Err(LtpError::ProtocolViolation(
+                "binary frame during handshake".to_string(),
+            )),
+            Some(Ok(Message::Close(frame))) => {
+                Err(LtpError::ConnectionClosed(frame.map(|f| CloseReason {
+                    code: f.code.into(),
+                    reason: f.reason.into_owned(),
+                })))
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => Err(e.into()),
+            None => Err(LtpError::ConnectionClosed(None)),
+        };
     }
+}
 
-    async fn send_text(&mut self, text: String) -> Result<()> {
-        if let Some(ref mut write) = self.write {
-            write.send(Message::Text(text)).await?;
-            Ok(())
-        } else {
-            Err(LtpError::NotConnected)
+/// Read the server's answer to a handshake message.
+///
+/// A `handshake_reject` or `error` answer, the end of the stream and any
+/// other message are all errors.
+async fn wait_for_handshake_ack(ws: &mut WsStream) -> Result<HandshakeAck> {
+    let text = next_handshake_text(ws).await?;
+    let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| {
+        LtpError::ProtocolViolation(format!("invalid JSON during handshake: {}", e))
+    })?;
+    let malformed = |e: serde_json::Error| {
+        LtpError::ProtocolViolation(format!("malformed {}: {}", value["type"], e))
+    };
+    match value["type"].as_str() {
+        Some("handshake_ack") => HandshakeAck::deserialize(&value).map_err(malformed),
+        Some("handshake_reject") => {
+            let reject = HandshakeReject::deserialize(&value).map_err(malformed)?;
+            Err(LtpError::HandshakeRejected {
+                reason: reject.reason,
+                suggest_new: reject.suggest_new,
+            })
+        }
+        Some("error") => {
+            let error = ErrorMessage::deserialize(&value).map_err(malformed)?;
+            Err(LtpError::Server(error.payload))
         }
+        other => Err(LtpError::ProtocolViolation(format!(
+            "expected handshake_ack, got {}",
+            other.unwrap_or("a message without a type")
+        ))),
     }
+}
 
-    async fn send_envelope(&mut self, envelope: LtpEnvelope) -> Result<()> {
-        let envelope = self.finalize_envelope(envelope)?;
+/// Ids, keys and outbound hash chain of the current session.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    pub thread_id: Option<String>,
+    pub session_id: Option<String>,
+    session_encryption_key: Option<String>,
+    session_mac_key: Option<String>,
+    last_sent_hash: Option<String>,
+}
 
-        let json = serde_json::to_string(&envelope)?;
-        self.send_text(json).await
+impl Session {
+    /// Adopt the ids and keys of a new connection.
+    pub(crate) fn start(&mut self, ack: &HandshakeAck, keys: Option<SessionKeys>) {
+        self.thread_id = Some(ack.thread_id.clone());
+        self.session_id = Some(ack.session_id.clone());
+        if let Some(keys) = keys {
+            self.session_encryption_key = Some(keys.encryption);
+            self.session_mac_key = Some(keys.mac);
+        }
+        // The hash chain starts over with each session.
+        self.last_sent_hash = None;
+    }
+
+    pub(crate) fn encryption_key(&self) -> Option<String> {
+        self.session_encryption_key.clone()
     }
 
-    fn finalize_envelope(&mut self, mut envelope: LtpEnvelope) -> Result<LtpEnvelope> {
+    /// Sign, chain and optionally encrypt `envelope` as the next message of
+    /// this session.
+    pub(crate) fn finalize(
+        &mut self,
+        mut envelope: LtpEnvelope,
+        dialer: &Dialer,
+    ) -> Result<LtpEnvelope> {
         // Generate nonce (HMAC-based if MAC key available, v0.6+)
-        let nonce = self.generate_nonce()?;
+        let nonce = self.generate_nonce();
         envelope.nonce = Some(nonce.clone());
 
         // Add prev_message_hash for hash chaining (v0.5+)
         envelope.prev_message_hash = self.last_sent_hash.clone();
 
         // Generate signature
-        if let Some(ref secret_key) = self.secret_key {
+        if let Some(ref secret_key) = dialer.secret_key {
             let signature = crypto::sign_message(&serde_json::to_value(&envelope)?, secret_key)?;
             envelope.signature = Some(signature);
         }
 
         // Encrypt metadata if enabled (v0.6+)
-        if self.enable_metadata_encryption {
-            if let (Some(ref encryption_key), Some(ref mac_key)) = (
+        if dialer.enable_metadata_encryption {
+            if let (Some(encryption_key), Some(mac_key)) = (
                 self.session_encryption_key.as_ref(),
                 self.session_mac_key.as_ref(),
             ) {
@@ -459,13 +590,75 @@ impl LtpClient {
     }
 
     /// Generate nonce (HMAC-based if MAC key available, v0.6+)
-    fn generate_nonce(&self) -> Result<String> {
+    fn generate_nonce(&self) -> String {
         if let Some(ref mac_key) = self.session_mac_key {
-            return Ok(crypto::generate_hmac_nonce(mac_key));
+            return crypto::generate_hmac_nonce(mac_key);
         }
 
         // Fallback to legacy format (backward compatibility)
-        Ok(uuid::Uuid::new_v4().to_string())
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// Checks applied to every inbound envelope of one connection before it is
+/// handed to the application.
+pub(crate) struct InboundVerifier {
+    session_encryption_key: Option<String>,
+    last_received_hash: Option<String>,
+    seen_nonces: HashSet<String>,
+    nonce_order: VecDeque<String>,
+}
+
+impl InboundVerifier {
+    pub(crate) fn new(session_encryption_key: Option<String>) -> Self {
+        Self {
+            session_encryption_key,
+            last_received_hash: None,
+            seen_nonces: HashSet::new(),
+            nonce_order: VecDeque::new(),
+        }
+    }
+
+    /// Decode one text frame into an event.
+    pub(crate) fn inbound_event(&mut self, text: &str) -> LtpEvent {
+        let value: serde_json::Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(e) => return LtpEvent::Error(e.into()),
+        };
+        let event = match value.get("type").and_then(|t| t.as_str()) {
+            Some("pong") => serde_json::from_value(value).map(LtpEvent::Pong),
+            Some("error") => serde_json::from_value(value).map(LtpEvent::ServerError),
+            _ => {
+                serde_json::from_value(value).map(|mut envelope| match self.check(&mut envelope) {
+                    Ok(()) => LtpEvent::Envelope(envelope),
+                    Err(e) => LtpEvent::Error(e),
+                })
+            }
+        };
+        event.unwrap_or_else(|e| LtpEvent::Error(e.into()))
+    }
+
+    fn check(&mut self, envelope: &mut LtpEnvelope) -> Result<()> {
+        self.check_nonce(envelope)?;
+        // The sender hashes the envelope as sent, so verify before decrypting.
+        self.verify_hash_chain(envelope)?;
+        self.decrypt_metadata_if_needed(envelope)
+    }
+
+    fn check_nonce(&mut self, envelope: &LtpEnvelope) -> Result<()> {
+        let Some(nonce) = &envelope.nonce else {
+            return Ok(());
+        };
+        if !self.seen_nonces.insert(nonce.clone()) {
+            return Err(IntegrityError::ReplayedNonce(nonce.clone()).into());
+        }
+        self.nonce_order.push_back(nonce.clone());
+        if self.nonce_order.len() > MAX_SEEN_NONCES {
+            if let Some(oldest) = self.nonce_order.pop_front() {
+                self.seen_nonces.remove(&oldest);
+            }
+        }
+        Ok(())
     }
 
     /// Decrypt metadata if encrypted (v0.6+)
@@ -473,9 +666,7 @@ impl LtpClient {
         if let Some(ref encrypted_metadata) = envelope.encrypted_metadata {
             if let Some(ref encryption_key) = self.session_encryption_key {
                 let metadata = crypto::decrypt_metadata(encrypted_metadata, encryption_key)
-                    .map_err(|e| {
-                        LtpError::InvalidState(format!("Failed to decrypt metadata: {}", e))
-                    })?;
+                    .map_err(IntegrityError::Decryption)?;
 
                 // Restore plaintext metadata
                 envelope.thread_id = metadata
@@ -498,15 +689,15 @@ impl LtpClient {
 
     /// Verify hash chaining (v0.5+)
     fn verify_hash_chain(&mut self, envelope: &LtpEnvelope) -> Result<()> {
-        if let (Some(prev_hash), Some(last_received)) = (
-            &envelope.prev_message_hash,
-            &self.last_received_hash,
-        ) {
+        if let (Some(prev_hash), Some(last_received)) =
+            (&envelope.prev_message_hash, &self.last_received_hash)
+        {
             if prev_hash != last_received {
-                return Err(LtpError::InvalidState(
-                    "Hash chain verification failed - message out of order or tampered"
-                        .to_string(),
-                ));
+                return Err(IntegrityError::HashChain {
+                    expected: last_received.clone(),
+                    actual: prev_hash.clone(),
+                }
+                .into());
             }
         }
 
@@ -520,70 +711,6 @@ impl LtpClient {
 
         Ok(())
     }
-
-    fn build_state_update_envelope<T: Serialize>(
-        &self,
-        kind: &str,
-        data: T,
-    ) -> Result<LtpEnvelope> {
-        let payload_data = serde_json::to_value(data)?;
-
-        let mut meta = serde_json::json!({
-            "client_id": self.client_id
-        });
-
-        if let Some(ref tag) = self.default_context_tag {
-            meta["context_tag"] = serde_json::Value::String(tag.clone());
-        }
-
-        Ok(LtpEnvelope {
-            r#type: "state_update".to_string(),
-            thread_id: self.thread_id.clone().unwrap_or_default(),
-            session_id: self.session_id.clone(),
-            timestamp: get_current_timestamp(),
-            content_encoding: ContentEncoding::Json,
-            payload: Payload {
-                kind: kind.to_string(),
-                data: payload_data,
-            },
-            meta: Some(meta),
-            nonce: None,              // Will be set in send_envelope
-            signature: None,          // Will be set in send_envelope
-            prev_message_hash: None,  // Will be set in send_envelope
-            encrypted_metadata: None, // Will be set in send_envelope if enabled
-            routing_tag: None,        // Will be set in send_envelope if enabled
-        })
-    }
-
-    fn build_event_envelope<T: Serialize>(&self, event_type: &str, data: T) -> Result<LtpEnvelope> {
-        let payload_data = serde_json::to_value(data)?;
-
-        let mut meta = serde_json::json!({
-            "client_id": self.client_id
-        });
-
-        if let Some(ref tag) = self.default_context_tag {
-            meta["context_tag"] = serde_json::Value::String(tag.clone());
-        }
-
-        Ok(LtpEnvelope {
-            r#type: "event".to_string(),
-            thread_id: self.thread_id.clone().unwrap_or_default(),
-            session_id: self.session_id.clone(),
-            timestamp: get_current_timestamp(),
-            content_encoding: ContentEncoding::Json,
-            payload: Payload {
-                kind: event_type.to_string(),
-                data: payload_data,
-            },
-            meta: Some(meta),
-            nonce: None,              // Will be set in send_envelope
-            signature: None,          // Will be set in send_envelope
-            prev_message_hash: None,  // Will be set in send_envelope
-            encrypted_metadata: None, // Will be set in send_envelope if enabled
-            routing_tag: None,        // Will be set in send_envelope if enabled
-        })
-    }
 }
 
 #[cfg(test)]
@@ -596,28 +723,55 @@ mod tests {
 
     #[test]
     fn hmac_nonce_contains_entropy_timestamp_and_mac() {
-        let client = LtpClient::new("ws://example.com", "client-123")
-            .with_session_mac_key("test-mac-key");
+        let client =
+            LtpClient::new("ws://example.com", "client-123").with_session_mac_key("test-mac-key");
 
-        let nonce = client.generate_nonce().expect("nonce should be generated");
+        let nonce = client.session.generate_nonce();
         let parts: Vec<&str> = nonce.split('-').collect();
 
-        assert_eq!(parts.get(0), Some(&"hmac"), "nonce must start with hmac prefix");
-        assert_eq!(parts.len(), 4, "nonce `{}` did not have four segments", nonce);
+        assert_eq!(
+            parts.first(),
+            Some(&"hmac"),
+            "nonce must start with hmac prefix"
+        );
+        assert_eq!(
+            parts.len(),
+            4,
+            "nonce `{}` did not have four segments",
+            nonce
+        );
 
         let random_hex = parts[1];
         let timestamp_part = parts[2];
         let hmac_prefix = parts[3];
 
-        assert_eq!(random_hex.len(), 32, "random hex `{}` must be 32 chars", random_hex);
-        assert!(is_hex(random_hex), "random hex `{}` must be hexadecimal", random_hex);
+        assert_eq!(
+            random_hex.len(),
+            32,
+            "random hex `{}` must be 32 chars",
+            random_hex
+        );
+        assert!(
+            is_hex(random_hex),
+            "random hex `{}` must be hexadecimal",
+            random_hex
+        );
 
         let timestamp: i64 = timestamp_part
             .parse()
             .expect("timestamp should be numeric milliseconds");
         assert!(timestamp > 0, "timestamp should be positive");
 
-        assert_eq!(hmac_prefix.len(), 32, "HMAC prefix `{}` must be 32 chars", hmac_prefix);
-        assert!(is_hex(hmac_prefix), "HMAC prefix `{}` must be hexadecimal", hmac_prefix);
+        assert_eq!(
+            hmac_prefix.len(),
+            32,
+            "HMAC prefix `{}` must be 32 chars",
+            hmac_prefix
+        );
+        assert!(
+            is_hex(hmac_prefix),
+            "HMAC prefix `{}` must be hexadecimal",
+            hmac_prefix
+        );
     }
 }
@@ -0,0 +1,432 @@
+//! The task behind every `LtpHandle`.
+//!
+//! One task per client owns the socket, the session keys, the outbound hash
+//! chain, the heartbeat, reconnect backoff, the outbox and the requests
+//! waiting for replies. Handles talk to it
+//! over a command channel, so sends are ordered by the channel and never
+//! contend for a lock.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::client::{Connection, Dialer, InboundVerifier, Session, WsStream};
+use crate::error::{LtpError, Result};
+use crate::events::{CloseReason, ConnectionState, LtpEvent};
+use crate::heartbeat::{self, Heartbeat};
+use crate::outbox::{DropReason, Outbox};
+use crate::pending::{Pending, Reply};
+use crate::reconnect::ReconnectPolicy;
+use crate::types::LtpEnvelope;
+
+pub(crate) enum Command {
+    Send {
+        envelope: Box<LtpEnvelope>,
+        done: oneshot::Sender<Result<()>>,
+    },
+    /// Send `envelope` past the outbox and wait for its reply.
+    Request {
+        envelope: Box<LtpEnvelope>,
+        reply: Reply<LtpEnvelope>,
+    },
+    Connect {
+        done: oneshot::Sender<Result<()>>,
+    },
+    Disconnect {
+        done: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// What handles can read without asking the task.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Status {
+    pub connected: bool,
+    pub thread_id: Option<String>,
+    pub session_id: Option<String>,
+    pub last_pong: Option<Instant>,
+    pub rtt: Option<Duration>,
+    pub outbox_len: Option<usize>,
+}
+
+struct Live {
+    ws: WsStream,
+    verifier: InboundVerifier,
+    heartbeat: Option<Heartbeat>,
+}
+
+enum State {
+    Connected(Box<Live>),
+    /// Waiting to make reconnect attempt `attempt`.
+    Backoff {
+        attempt: u32,
+        at: tokio::time::Instant,
+    },
+    /// Disconnected and not reconnecting until asked to.
+    Idle,
+}
+
+enum Step {
+    Command(Option<Command>),
+    Frame(Option<std::result::Result<Message, tungstenite::Error>>),
+    Heartbeat,
+    Reconnect,
+}
+
+pub(crate) struct ConnectionTask {
+    dialer: Dialer,
+    reconnect: Option<ReconnectPolicy>,
+    session: Session,
+    outbox: Option<Outbox>,
+    /// Requests waiting for the reply with their `correlation_id`.
+    requests: Pending<LtpEnvelope>,
+    events: mpsc::UnboundedSender<LtpEvent>,
+    status: watch::Sender<Status>,
+    state: State,
+}
+
+impl ConnectionTask {
+    pub(crate) fn new(
+        dialer: Dialer,
+        reconnect: Option<ReconnectPolicy>,
+        session: Session,
+        outbox: Option<Outbox>,
+        events: mpsc::UnboundedSender<LtpEvent>,
+    ) -> Self {
+        Self {
+            dialer,
+            reconnect,
+            session,
+            outbox,
+            requests: Pending::default(),
+            events,
+            status: watch::Sender::new(Status::default()),
+            state: State::Idle,
+        }
+    }
+
+    /// Start the task on an established connection. It runs until every
+    /// handle is dropped.
+    pub(crate) fn spawn(
+        mut self,
+        connection: Connection,
+    ) -> (mpsc::UnboundedSender<Command>, watch::Receiver<Status>) {
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        self.attach(connection);
+        let status = self.status.subscribe();
+        tokio::spawn(self.run(commands));
+        (commands_tx, status)
+    }
+
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<Command>) {
+        // Send whatever a file-backed outbox kept from a previous run.
+        if let Err(e) = self.flush().await {
+            self.emit(LtpEvent::Error(e));
+        }
+        loop {
+            let step = match &mut self.state {
+                State::Connected(live) => tokio::select! {
+                    command = commands.recv() => Step::Command(command),
+                    frame = live.ws.next() => Step::Frame(frame),
+                    _ = Heartbeat::tick(&mut live.heartbeat) => Step::Heartbeat,
+                },
+                State::Backoff { at, .. } => tokio::select! {
+                    command = commands.recv() => Step::Command(command),
+                    _ = tokio::time::sleep_until(*at) => Step::Reconnect,
+                },
+                State::Idle => Step::Command(commands.recv().await),
+            };
+            match step {
+                Step::Command(Some(command)) => self.command(command).await,
+                Step::Command(None) => break,
+                Step::Frame(frame) => self.frame(frame),
+                Step::Heartbeat => self.heartbeat().await,
+                Step::Reconnect => self.reconnect().await,
+            }
+        }
+
+        // Every handle is gone.
+        if let State::Connected(live) = &mut self.state {
+            let _ = live.ws.close(None).await;
+        }
+        self.status.send_modify(|s| s.connected = false);
+    }
+
+    async fn command(&mut self, command: Command) {
+        match command {
+            Command::Send { envelope, done } => {
+                let _ = done.send(self.send(*envelope).await);
+            }
+            Command::Request { envelope, reply } => {
+                let id = envelope.correlation_id.clone().unwrap_or_default();
+                match self.write(*envelope).await {
+                    Ok(()) => self.requests.insert(id, reply),
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+            }
+            Command::Connect { done } => {
+                let result = match self.state {
+                    State::Connected(_) => Ok(()),
+                    _ => self.connect().await,
+                };
+                let _ = done.send(result);
+            }
+            Command::Disconnect { done } => {
+                let result = match std::mem::replace(&mut self.state, State::Idle) {
+                    State::Connected(mut live) => live.ws.close(None).await.map_err(Into::into),
+                    _ => Ok(()),
+                };
+                self.status.send_modify(|s| s.connected = false);
+                self.requests.fail_all(|| LtpError::ConnectionClosed(None));
+                let _ = done.send(result);
+            }
+        }
+    }
+
+    async fn send(&mut self, envelope: LtpEnvelope) -> Result<()> {
+        let Some(outbox) = self.outbox.as_mut() else {
+            return self.write(envelope).await;
+        };
+        // Queue behind anything already waiting so the chain keeps send order.
+        if let Some(envelope) = outbox.push(envelope, SystemTime::now())? {
+            self.emit(LtpEvent::OutboxDropped {
+                envelope,
+                reason: DropReason::Full,
+            });
+        }
+        self.flush().await
+    }
+
+    /// Fill in the session ids, sign and chain `envelope`, and send it.
+    async fn write(&mut self, mut envelope: LtpEnvelope) -> Result<()> {
+        let State::Connected(live) = &mut self.state else {
+            return Err(LtpError::NotConnected);
+        };
+        envelope.thread_id = self.session.thread_id.clone().unwrap_or_default();
+        envelope.session_id = self.session.session_id.clone();
+        let envelope = self.session.finalize(envelope, &self.dialer)?;
+        let json = serde_json::to_string(&envelope)?;
+        live.ws.send(Message::Text(json)).await?;
+        Ok(())
+    }
+
+    /// Send queued messages in order. Does nothing while disconnected; a
+    /// message whose send fails stays queued.
+    async fn flush(&mut self) -> Result<()> {
+        let result = self.flush_queue().await;
+        if let Some(outbox) = &mut self.outbox {
+            let len = outbox.len();
+            self.status.send_modify(|s| s.outbox_len = Some(len));
+            outbox.persist()?;
+        }
+        result
+    }
+
+    async fn flush_queue(&mut self) -> Result<()> {
+        while matches!(self.state, State::Connected(_)) {
+            let Some(outbox) = self.outbox.as_mut() else {
+                break;
+            };
+            let mut expired = Vec::new();
+            let next = outbox.front(SystemTime::now(), &mut expired).cloned();
+            for envelope in expired {
+                self.emit(LtpEvent::OutboxDropped {
+                    envelope,
+                    reason: DropReason::Expired,
+                });
+            }
+            let Some(envelope) = next else {
+                break;
+            };
+            self.write(envelope).await?;
+            if let Some(outbox) = self.outbox.as_mut() {
+                outbox.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, frame: Option<std::result::Result<Message, tungstenite::Error>>) {
+        let State::Connected(live) = &mut self.state else {
+            return;
+        };
+        match frame {
+            Some(Ok(Message::Text(text))) => {
+                let event = live.verifier.inbound_event(&text);
+                if let (LtpEvent::Pong(_), Some(heartbeat)) = (&event, live.heartbeat.as_mut()) {
+                    heartbeat.pong_received();
+                    let (last_pong, rtt) = (heartbeat.last_pong(), heartbeat.last_rtt());
+                    self.status.send_modify(|s| {
+                        s.last_pong = Some(last_pong);
+                        s.rtt = rtt;
+                    });
+                }
+                self.deliver(event);
+            }
+            Some(Ok(Message::Close(frame))) => {
+                let reason = frame.map(|f| CloseReason {
+                    code: f.code.into(),
+                    reason: f.reason.into_owned(),
+                });
+                self.lost(reason);
+            }
+            Some(Err(e)) => {
+                self.emit(LtpEvent::Error(e.into()));
+                self.lost(None);
+            }
+            None => self.lost(None),
+            Some(Ok(_)) => {}
+        }
+    }
+
+    /// Hand a reply to the request waiting for it; emit anything else.
+    fn deliver(&mut self, event: LtpEvent) {
+        let id = match &event {
+            LtpEvent::Envelope(envelope) => envelope.correlation_id.as_deref(),
+            LtpEvent::ServerError(error) => error.correlation_id.as_deref(),
+            _ => None,
+        };
+        match (id.and_then(|id| self.requests.take(id)), event) {
+            (Some(reply), LtpEvent::Envelope(envelope)) => {
+                let _ = reply.send(Ok(envelope));
+            }
+            (Some(reply), LtpEvent::ServerError(error)) => {
+                let _ = reply.send(Err(LtpError::Server(error.payload)));
+            }
+            (_, event) => self.emit(event),
+        }
+    }
+
+    /// Ping, or drop the connection when the last pong is too old.
+    async fn heartbeat(&mut self) {
+        let State::Connected(live) = &mut self.state else {
+            return;
+        };
+        let Some(heartbeat) = live.heartbeat.as_mut() else {
+            return;
+        };
+        if let Some(silent_for) = heartbeat.timed_out() {
+            let _ = live.ws.close(None).await;
+            self.emit(LtpEvent::HeartbeatTimeout { silent_for });
+            self.lost(None);
+            return;
+        }
+
+        let ping = heartbeat::ping(
+            &self.dialer.client_id,
+            self.session.thread_id.as_deref().unwrap_or_default(),
+            self.session.session_id.as_deref().unwrap_or_default(),
+        );
+        let Ok(json) = serde_json::to_string(&ping) else {
+            return;
+        };
+        // A failed send surfaces on the read side.
+        if live.ws.send(Message::Text(json)).await.is_ok() {
+            heartbeat.ping_sent();
+        }
+    }
+
+    /// Report the lost connection and schedule the first reconnect attempt.
+    fn lost(&mut self, reason: Option<CloseReason>) {
+        self.state = State::Idle;
+        self.status.send_modify(|s| s.connected = false);
+        self.requests
+            .fail_all(|| LtpError::ConnectionClosed(reason.clone()));
+        self.emit(LtpEvent::Closed(reason));
+        self.backoff(1);
+    }
+
+    fn backoff(&mut self, attempt: u32) {
+        let Some(policy) = &self.reconnect else {
+            return;
+        };
+        if policy.max_retries.is_some_and(|max| attempt > max) {
+            // Stay down until `connect` is called again.
+            self.state = State::Idle;
+            self.emit(LtpEvent::StateChanged(ConnectionState::Disconnected));
+            return;
+        }
+        let delay = policy.delay(attempt);
+        self.state = State::Backoff {
+            attempt,
+            at: tokio::time::Instant::now() + delay,
+        };
+        self.emit(LtpEvent::StateChanged(ConnectionState::Reconnecting {
+            attempt,
+            delay,
+        }));
+    }
+
+    async fn reconnect(&mut self) {
+        let State::Backoff { attempt, .. } = self.state else {
+            return;
+        };
+        // Resume the thread of the connection that was lost.
+        match self.dialer.dial(self.session.thread_id.as_deref()).await {
+            Ok(connection) => {
+                self.emit(LtpEvent::StateChanged(ConnectionState::Connected {
+                    thread_id: connection.ack.thread_id.clone(),
+                    session_id: connection.ack.session_id.clone(),
+                    resumed: connection.ack.resumed,
+                }));
+                self.attach(connection);
+                if let Err(e) = self.flush().await {
+                    self.emit(LtpEvent::Error(e));
+                }
+            }
+            Err(e) => {
+                self.emit(LtpEvent::Error(e));
+                self.backoff(attempt + 1);
+            }
+        }
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        let connection = self.dialer.dial(self.session.thread_id.as_deref()).await?;
+        self.attach(connection);
+        self.flush().await
+    }
+
+    /// Make `connection` the current one.
+    fn attach(&mut self, connection: Connection) {
+        let Connection { ws, ack, keys } = connection;
+        self.session.start(&ack, keys);
+
+        // The server's interval from handshake_ack wins over the configured one.
+        let interval_ms = if ack.heartbeat_interval_ms > 0 {
+            ack.heartbeat_interval_ms
+        } else {
+            self.dialer.heartbeat_interval_ms
+        };
+        let heartbeat = (interval_ms > 0).then(|| {
+            Heartbeat::new(
+                Duration::from_millis(interval_ms),
+                Duration::from_millis(self.dialer.heartbeat_timeout_ms),
+            )
+        });
+        let last_pong = heartbeat
+            .as_ref()
+            .map_or_else(Instant::now, Heartbeat::last_pong);
+
+        self.state = State::Connected(Box::new(Live {
+            ws,
+            verifier: InboundVerifier::new(self.session.encryption_key()),
+            heartbeat,
+        }));
+        self.status.send_modify(|s| {
+            s.connected = true;
+            s.thread_id = Some(ack.thread_id);
+            s.session_id = Some(ack.session_id);
+            s.last_pong = Some(last_pong);
+            s.rtt = None;
+        });
+    }
+
+    /// Send errors only mean nobody is listening.
+    fn emit(&self, event: LtpEvent) {
+        let _ = self.events.send(event);
+    }
+}
@@ -10,6 +10,7 @@ use aes_gcm::{
 use hex;
 use hkdf::Hkdf;
 use hmac::{digest::KeyInit, Hmac, Mac};
+use ltp_protocol::canonical::{canonical_json_bytes, canonicalize_json};
 use rand::Rng;
 use p256::elliptic_curve::sec1::ToEncodedPoint;
 use p256::{EncodedPoint, SecretKey};
@@ -231,11 +232,11 @@ pub fn verify_ecdh_public_key(
 pub fn hash_envelope(message: &Value) -> Result<String, String> {
     // Canonicalize message for hashing
     let canonical = canonicalize_message(message)?;
-    let serialized = serde_json::to_string(&canonical)
+    let serialized = canonical_json_bytes(&canonical)
         .map_err(|e| format!("Failed to serialize message: {}", e))?;
 
     let mut hasher = Sha256::new();
-    hasher.update(serialized.as_bytes());
+    hasher.update(&serialized);
     Ok(hex::encode(hasher.finalize()))
 }
 
@@ -243,6 +244,7 @@ pub fn hash_envelope(message: &Value) -> Result<String, String> {
 ///
 /// Encrypts thread_id, session_id, and timestamp using AES-256-GCM.
 /// This prevents adversaries from tracking users across sessions.
+#[allow(clippy::needless_borrows_for_generic_args)]
 pub fn encrypt_metadata(metadata: &Value, encryption_key_hex: &str) -> Result<String, String> {
     // Serialize metadata to JSON
     let metadata_json = serde_json::to_string(metadata)
@@ -276,6 +278,7 @@ pub fn encrypt_metadata(metadata: &Value, encryption_key_hex: &str) -> Result<St
 }
 
 /// Decrypt metadata fields (v0.6+).
+#[allow(clippy::nonminimal_bool)]
 pub fn decrypt_metadata(
     encrypted_metadata: &str,
     encryption_key_hex: &str,
@@ -360,7 +363,7 @@ pub fn generate_routing_tag(
 /// Sign a message using HMAC-SHA256.
 pub fn sign_message(message: &Value, secret_key: &str) -> Result<String, String> {
     let canonical = canonicalize_message(message)?;
-    let serialized = serde_json::to_string(&canonical)
+    let serialized = serde_json::to_string(&canonicalize_json(&canonical))
         .map_err(|e| format!("Failed to serialize message: {}", e))?;
 
     Ok(hmac_sha256(&serialized, secret_key))
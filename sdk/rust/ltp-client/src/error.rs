@@ -1,17 +1,53 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
+use crate::events::CloseReason;
+use crate::node::ErrorCode;
+use crate::types::ErrorPayload;
+
 pub type Result<T> = std::result::Result<T, LtpError>;
 
 #[derive(Error, Debug)]
 pub enum LtpError {
     #[error("websocket error: {0}")]
-    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
 
     #[error("serde json error: {0}")]
     SerdeJson(#[from] serde_json::Error),
 
-    #[error("handshake failed: {0}")]
-    Handshake(String),
+    #[error("handshake timed out after {0:?}")]
+    HandshakeTimeout(Duration),
+
+    /// No reply to a request arrived in time.
+    #[error("request timed out after {0:?}")]
+    RequestTimeout(Duration),
+
+    /// The server answered the handshake with `handshake_reject`.
+    #[error("handshake rejected: {reason}")]
+    HandshakeRejected { reason: String, suggest_new: bool },
+
+    /// The server answered with an `error` message.
+    #[error("server error {}: {}", .0.error_code, .0.error_message)]
+    Server(ErrorPayload),
+
+    /// `ltp-rust-node` answered with an `error` message.
+    #[error("node error {code}: {}", message.as_deref().unwrap_or("no message"))]
+    Node {
+        code: ErrorCode,
+        message: Option<String>,
+        /// When a per-key rate limit allows the next attempt.
+        retry_after: Option<Duration>,
+    },
+
+    /// The connection ended before the exchange completed; `None` when it
+    /// dropped without a close frame.
+    #[error("connection closed")]
+    ConnectionClosed(Option<CloseReason>),
+
+    /// The server sent something the protocol does not allow at this point.
+    #[error("protocol violation: {0}")]
+    ProtocolViolation(String),
 
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
@@ -19,8 +55,49 @@ pub enum LtpError {
     #[error("not connected")]
     NotConnected,
 
+    #[error("outbox is full")]
+    OutboxFull,
+
     #[error("invalid state: {0}")]
     InvalidState(String),
+
+    #[error("integrity check failed: {0}")]
+    Integrity(#[from] IntegrityError),
+
+    #[error("toon: {0}")]
+    Toon(#[from] ToonError),
+}
+
+/// Why an inbound envelope was rejected before reaching the application.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityError {
+    #[error("hash chain broken: expected prev_message_hash {expected}, got {actual}")]
+    HashChain { expected: String, actual: String },
+
+    #[error("nonce {0} was already seen")]
+    ReplayedNonce(String),
+
+    #[error("failed to decrypt metadata: {0}")]
+    Decryption(String),
+}
+
+/// Why a TOON table could not be written or read.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ToonError {
+    #[error("only a non-empty array of flat objects with the same keys is a table")]
+    NotTabular,
+
+    #[error("content_encoding is toon but payload.data is not a string")]
+    NotText,
+
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for LtpError {
+    fn from(value: tokio_tungstenite::tungstenite::Error) -> Self {
+        LtpError::WebSocket(Box::new(value))
+    }
 }
 
 impl From<String> for LtpError {
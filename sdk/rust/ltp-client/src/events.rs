@@ -0,0 +1,92 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+use crate::error::LtpError;
+use crate::outbox::DropReason;
+use crate::types::{ErrorMessage, LtpEnvelope, PongMessage};
+
+/// Something received from the server after the handshake.
+#[derive(Debug)]
+pub enum LtpEvent {
+    /// An envelope that passed replay, hash-chain and decryption checks.
+    Envelope(LtpEnvelope),
+    Pong(PongMessage),
+    /// An `error` message sent by the server.
+    ServerError(ErrorMessage),
+    /// A frame that could not be decoded or failed an integrity check, or a
+    /// transport error. Integrity failures carry `LtpError::Integrity`.
+    Error(LtpError),
+    /// No pong arrived within the heartbeat timeout; the connection was
+    /// dropped and `Closed(None)` follows.
+    HeartbeatTimeout {
+        silent_for: Duration,
+    },
+    /// The connection ended; `None` when it dropped without a close frame.
+    Closed(Option<CloseReason>),
+    /// Progress of the reconnect supervisor, if reconnecting is enabled.
+    StateChanged(ConnectionState),
+    /// A queued message was discarded by the outbox limits instead of sent.
+    OutboxDropped {
+        envelope: LtpEnvelope,
+        reason: DropReason,
+    },
+}
+
+/// Connection states reported by the reconnect supervisor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Waiting `delay` before reconnect attempt `attempt` (counted from 1).
+    Reconnecting { attempt: u32, delay: Duration },
+    /// A reconnect completed its handshake. `resumed` is false when the
+    /// server started a new thread instead of resuming the old one.
+    Connected {
+        thread_id: String,
+        session_id: String,
+        resumed: bool,
+    },
+    /// Reconnecting gave up after the policy's `max_retries`.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// Inbound events of an `LtpClient` (or a `NodeClient`), as a `Stream` or
+/// via `recv`.
+///
+/// The channel is unbounded so a slow consumer never stalls the socket or
+/// the heartbeat. Nothing is dropped to make room: every event not yet
+/// received stays in memory, so a stream that is held but not drained grows
+/// for as long as the server keeps sending. Drop the stream when the events
+/// are no longer wanted; later events are then discarded as they arrive,
+/// as they are when the stream was never taken.
+#[derive(Debug)]
+pub struct LtpEvents<E = LtpEvent> {
+    rx: mpsc::UnboundedReceiver<E>,
+}
+
+impl<E> LtpEvents<E> {
+    pub(crate) fn new(rx: mpsc::UnboundedReceiver<E>) -> Self {
+        Self { rx }
+    }
+
+    /// Wait for the next event; `None` once the client is dropped.
+    pub async fn recv(&mut self) -> Option<E> {
+        self.rx.recv().await
+    }
+}
+
+impl<E> Stream for LtpEvents<E> {
+    type Item = E;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
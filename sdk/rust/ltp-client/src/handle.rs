@@ -0,0 +1,264 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::connection::{Command, Status};
+use crate::error::{LtpError, Result};
+use crate::pending;
+use crate::toon;
+use crate::types::*;
+
+/// A cheap, cloneable handle to a connected client.
+///
+/// Every clone talks to the same connection task, which owns the socket and
+/// the nonce and hash-chain state. Messages are sent in the order the send
+/// methods are called, across all clones. The task stops once every handle
+/// is dropped.
+#[derive(Debug, Clone)]
+pub struct LtpHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    status: watch::Receiver<Status>,
+    client_id: Arc<str>,
+    default_context_tag: Option<Arc<str>>,
+    content_encoding: ContentEncoding,
+    request_timeout: Duration,
+}
+
+impl LtpHandle {
+    pub(crate) fn new(
+        commands: mpsc::UnboundedSender<Command>,
+        status: watch::Receiver<Status>,
+        client_id: String,
+        default_context_tag: Option<String>,
+        content_encoding: ContentEncoding,
+        request_timeout: Duration,
+    ) -> Self {
+        Self {
+            commands,
+            status,
+            client_id: client_id.into(),
+            default_context_tag: default_context_tag.map(Into::into),
+            content_encoding,
+            request_timeout,
+        }
+    }
+
+    /// Send a state update, as TOON if the client prefers it and `data` is
+    /// tabular
+    pub fn send_state_update<T: Serialize>(&self, kind: &str, data: T) -> Completion {
+        self.send("state_update", kind, data, self.content_encoding)
+    }
+
+    /// Send an event
+    pub fn send_event<T: Serialize>(&self, event_type: &str, data: T) -> Completion {
+        self.send("event", event_type, data, ContentEncoding::Json)
+    }
+
+    /// Send a message tagged with a fresh `correlation_id` and wait for the
+    /// reply carrying the same id: an envelope, or the server's `error` as
+    /// `LtpError::Server`. Fails with `RequestTimeout` after the client's
+    /// request timeout.
+    ///
+    /// Requests skip the outbox: while disconnected they fail with
+    /// `NotConnected`, and a lost connection fails them with
+    /// `ConnectionClosed`. Dropping the future cancels the request; a reply
+    /// that arrives afterwards goes to the event stream.
+    pub async fn request<T: Serialize>(
+        &self,
+        r#type: &str,
+        kind: &str,
+        data: T,
+    ) -> Result<LtpEnvelope> {
+        self.request_with_timeout(r#type, kind, data, self.request_timeout)
+            .await
+    }
+
+    /// Like `request`, with its own timeout.
+    pub async fn request_with_timeout<T: Serialize>(
+        &self,
+        r#type: &str,
+        kind: &str,
+        data: T,
+        timeout: Duration,
+    ) -> Result<LtpEnvelope> {
+        let mut envelope = self.build_envelope(r#type, kind, data, ContentEncoding::Json)?;
+        envelope.correlation_id = Some(uuid::Uuid::new_v4().to_string());
+        let (reply, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Request {
+                envelope: Box::new(envelope),
+                reply,
+            })
+            .map_err(|_| LtpError::NotConnected)?;
+        pending::wait(rx, timeout).await
+    }
+
+    /// Reconnect after `disconnect`, or after the reconnect policy gave up,
+    /// resuming the current thread.
+    pub async fn connect(&self) -> Result<()> {
+        self.call(|done| Command::Connect { done }).await
+    }
+
+    /// Close the connection without reconnecting. Sends fail with
+    /// `NotConnected`, or are queued in the outbox, until `connect`.
+    pub async fn disconnect(&self) -> Result<()> {
+        self.call(|done| Command::Disconnect { done }).await
+    }
+
+    /// Whether the handshake completed and the connection has not been lost
+    /// since (closed by either side or timed out by the heartbeat).
+    pub fn is_connected(&self) -> bool {
+        self.status.borrow().connected
+    }
+
+    /// Get current thread ID
+    pub fn thread_id(&self) -> Option<String> {
+        self.status.borrow().thread_id.clone()
+    }
+
+    /// Get current session ID
+    pub fn session_id(&self) -> Option<String> {
+        self.status.borrow().session_id.clone()
+    }
+
+    /// Round-trip time of the last answered heartbeat ping.
+    pub fn heartbeat_rtt(&self) -> Option<Duration> {
+        self.status.borrow().rtt
+    }
+
+    /// When the last pong arrived (or the connection was established).
+    pub fn last_pong_time(&self) -> Option<Instant> {
+        self.status.borrow().last_pong
+    }
+
+    /// Messages waiting in the outbox, if one is configured.
+    pub fn outbox_len(&self) -> Option<usize> {
+        self.status.borrow().outbox_len
+    }
+
+    fn send<T: Serialize>(
+        &self,
+        r#type: &str,
+        kind: &str,
+        data: T,
+        encoding: ContentEncoding,
+    ) -> Completion {
+        let envelope = match self.build_envelope(r#type, kind, data, encoding) {
+            Ok(envelope) => envelope,
+            Err(e) => return Completion::failed(e),
+        };
+        let (done, rx) = oneshot::channel();
+        match self.commands.send(Command::Send {
+            envelope: Box::new(envelope),
+            done,
+        }) {
+            Ok(()) => Completion::pending(rx),
+            Err(_) => Completion::failed(LtpError::NotConnected),
+        }
+    }
+
+    async fn call(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<()>>) -> Command,
+    ) -> Result<()> {
+        let (done, rx) = oneshot::channel();
+        self.commands
+            .send(command(done))
+            .map_err(|_| LtpError::NotConnected)?;
+        Completion::pending(rx).await
+    }
+
+    /// The connection task fills in the thread and session ids when it sends.
+    /// With `ContentEncoding::Toon`, data that is not tabular stays JSON.
+    fn build_envelope<T: Serialize>(
+        &self,
+        r#type: &str,
+        kind: &str,
+        data: T,
+        encoding: ContentEncoding,
+    ) -> Result<LtpEnvelope> {
+        let payload_data = serde_json::to_value(data)?;
+        let (content_encoding, payload_data) = match encoding {
+            ContentEncoding::Json => (ContentEncoding::Json, payload_data),
+            ContentEncoding::Toon => toon::encode_payload(kind, payload_data),
+        };
+
+        let mut meta = serde_json::json!({
+            "client_id": &*self.client_id
+        });
+
+        if let Some(ref tag) = self.default_context_tag {
+            meta["context_tag"] = serde_json::Value::String(tag.to_string());
+        }
+
+        Ok(LtpEnvelope {
+            r#type: r#type.to_string(),
+            thread_id: String::new(),
+            session_id: None,
+            timestamp: get_current_timestamp(),
+            content_encoding,
+            payload: Payload {
+                kind: kind.to_string(),
+                data: payload_data,
+            },
+            meta: Some(meta),
+            nonce: None,              // Will be set by the connection task
+            signature: None,          // Will be set by the connection task
+            prev_message_hash: None,  // Will be set by the connection task
+            encrypted_metadata: None, // Will be set by the connection task if enabled
+            routing_tag: None,        // Will be set by the connection task if enabled
+            correlation_id: None,
+        })
+    }
+}
+
+/// Outcome of one send: resolves once the message was written to the
+/// socket, or queued in the outbox while disconnected.
+///
+/// The message's place in the send order is fixed when the send method is
+/// called; dropping the `Completion` does not cancel it.
+#[derive(Debug)]
+pub struct Completion {
+    state: CompletionState,
+}
+
+#[derive(Debug)]
+enum CompletionState {
+    Pending(oneshot::Receiver<Result<()>>),
+    Failed(Option<LtpError>),
+}
+
+impl Completion {
+    pub(crate) fn pending(rx: oneshot::Receiver<Result<()>>) -> Self {
+        Self {
+            state: CompletionState::Pending(rx),
+        }
+    }
+
+    pub(crate) fn failed(error: LtpError) -> Self {
+        Self {
+            state: CompletionState::Failed(Some(error)),
+        }
+    }
+}
+
+impl Future for Completion {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.state {
+            // The task only drops a reply when it is shutting down.
+            CompletionState::Pending(rx) => Pin::new(rx)
+                .poll(cx)
+                .map(|reply| reply.unwrap_or(Err(LtpError::NotConnected))),
+            CompletionState::Failed(error) => Poll::Ready(Err(error
+                .take()
+                .expect("Completion polled after it resolved"))),
+        }
+    }
+}
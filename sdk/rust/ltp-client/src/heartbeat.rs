@@ -0,0 +1,89 @@
+use std::time::{Duration, Instant};
+
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::types::{get_current_timestamp, PingMessage};
+
+/// Ping schedule and pong bookkeeping of one connection.
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    ticker: Interval,
+    timeout: Duration,
+    last_pong: Instant,
+    ping_sent: Option<Instant>,
+    last_rtt: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(interval: Duration, timeout: Duration) -> Self {
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            ticker,
+            timeout,
+            last_pong: Instant::now(),
+            ping_sent: None,
+            last_rtt: None,
+        }
+    }
+
+    /// Wait for the next ping, or for the oldest unanswered ping to time out
+    /// if that comes first; never completes without a heartbeat.
+    pub(crate) async fn tick(heartbeat: &mut Option<Heartbeat>) {
+        match heartbeat {
+            Some(heartbeat) => match heartbeat.ping_sent {
+                Some(sent) => {
+                    let deadline = tokio::time::Instant::from_std(sent + heartbeat.timeout);
+                    tokio::select! {
+                        _ = heartbeat.ticker.tick() => {}
+                        _ = tokio::time::sleep_until(deadline) => {}
+                    }
+                }
+                None => {
+                    heartbeat.ticker.tick().await;
+                }
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    /// How long the oldest ping has gone unanswered, if that reaches the
+    /// timeout. Measured from the ping rather than the last pong, so an
+    /// interval longer than the timeout does not fail a healthy peer.
+    pub(crate) fn timed_out(&self) -> Option<Duration> {
+        let silent_for = self.ping_sent?.elapsed();
+        (silent_for >= self.timeout).then_some(silent_for)
+    }
+
+    pub(crate) fn last_pong(&self) -> Instant {
+        self.last_pong
+    }
+
+    pub(crate) fn last_rtt(&self) -> Option<Duration> {
+        self.last_rtt
+    }
+
+    pub(crate) fn ping_sent(&mut self) {
+        // Keep the oldest unanswered ping so a slow pong is not under-measured.
+        self.ping_sent.get_or_insert_with(Instant::now);
+    }
+
+    pub(crate) fn pong_received(&mut self) {
+        let now = Instant::now();
+        self.last_pong = now;
+        if let Some(sent) = self.ping_sent.take() {
+            self.last_rtt = Some(now - sent);
+        }
+    }
+}
+
+pub(crate) fn ping(client_id: &str, thread_id: &str, session_id: &str) -> PingMessage {
+    PingMessage {
+        r#type: "ping".to_string(),
+        thread_id: thread_id.to_string(),
+        session_id: Some(session_id.to_string()),
+        timestamp: get_current_timestamp(),
+        payload: serde_json::json!({}),
+        meta: Some(serde_json::json!({ "client_id": client_id })),
+    }
+}
@@ -1,11 +1,25 @@
 pub mod client;
+mod connection;
 pub mod crypto;
 pub mod error;
+pub mod events;
+pub mod handle;
+mod heartbeat;
+pub mod node;
+pub mod outbox;
+mod pending;
+pub mod reconnect;
+pub mod toon;
 pub mod types;
 
 pub use client::LtpClient;
 pub use crypto::*;
-pub use error::{LtpError, Result};
+pub use error::{IntegrityError, LtpError, Result, ToonError};
+pub use events::{CloseReason, ConnectionState, LtpEvent, LtpEvents};
+pub use handle::{Completion, LtpHandle};
+pub use node::{NodeClient, NodeEvent, NodeEvents, NodeHandle};
+pub use outbox::{DropPolicy, DropReason, Outbox, OutboxConfig};
+pub use reconnect::ReconnectPolicy;
 pub use types::*;
 
 /// SDK version
@@ -41,6 +55,7 @@ mod tests {
             prev_message_hash: None,
             encrypted_metadata: None,
             routing_tag: None,
+            correlation_id: None,
         };
 
         assert_eq!(envelope.r#type, "state_update");
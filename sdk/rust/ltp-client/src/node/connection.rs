@@ -0,0 +1,263 @@
+//! The task behind every `NodeHandle`.
+
+use std::ops::ControlFlow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use super::{
+    node_error, Correlated, LtpIncomingMessage, LtpOutgoingMessage, NodeEvent, RouteSuggestion,
+};
+use crate::client::WsStream;
+use crate::error::{LtpError, Result};
+use crate::events::CloseReason;
+use crate::heartbeat::Heartbeat;
+use crate::pending::{Pending, Reply};
+
+pub(crate) enum Command {
+    Send {
+        message: Correlated<LtpIncomingMessage>,
+        done: oneshot::Sender<Result<()>>,
+    },
+    Route {
+        message: Correlated<LtpIncomingMessage>,
+        reply: Reply<RouteSuggestion>,
+    },
+    Close {
+        done: oneshot::Sender<Result<()>>,
+    },
+}
+
+impl Command {
+    fn fail(self, error: LtpError) {
+        match self {
+            Command::Send { done, .. } | Command::Close { done } => {
+                let _ = done.send(Err(error));
+            }
+            Command::Route { reply, .. } => {
+                let _ = reply.send(Err(error));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Status {
+    pub connected: bool,
+    pub rtt: Option<Duration>,
+}
+
+/// `Break` once the session is over, with the close frame if there was one.
+type Flow = ControlFlow<Option<CloseReason>>;
+
+pub(crate) struct NodeTask {
+    ws: WsStream,
+    session_id: String,
+    heartbeat: Option<Heartbeat>,
+    /// Route requests waiting for a reply.
+    routes: Pending<RouteSuggestion>,
+    /// The node has echoed a `correlation_id`, so replies without one
+    /// answer no request.
+    echoes_ids: bool,
+    events: mpsc::UnboundedSender<NodeEvent>,
+    status: watch::Sender<Status>,
+}
+
+impl NodeTask {
+    /// `heartbeat` is the interval and timeout, if heartbeats are enabled.
+    pub(crate) fn new(
+        ws: WsStream,
+        session_id: String,
+        heartbeat: Option<(Duration, Duration)>,
+        events: mpsc::UnboundedSender<NodeEvent>,
+    ) -> Self {
+        Self {
+            ws,
+            session_id,
+            heartbeat: heartbeat.map(|(interval, timeout)| Heartbeat::new(interval, timeout)),
+            routes: Pending::default(),
+            echoes_ids: false,
+            events,
+            status: watch::Sender::new(Status {
+                connected: true,
+                rtt: None,
+            }),
+        }
+    }
+
+    pub(crate) fn spawn(self) -> (mpsc::UnboundedSender<Command>, watch::Receiver<Status>) {
+        let (commands_tx, commands) = mpsc::unbounded_channel();
+        let status = self.status.subscribe();
+        tokio::spawn(self.run(commands));
+        (commands_tx, status)
+    }
+
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<Command>) {
+        let reason = loop {
+            let flow = tokio::select! {
+                command = commands.recv() => match command {
+                    Some(command) => self.command(command).await,
+                    None => {
+                        // Every handle is gone.
+                        let _ = self.ws.close(None).await;
+                        self.status.send_modify(|s| s.connected = false);
+                        return;
+                    }
+                },
+                frame = self.ws.next() => self.frame(frame),
+                _ = Heartbeat::tick(&mut self.heartbeat) => self.heartbeat().await,
+            };
+            if let ControlFlow::Break(reason) = flow {
+                break reason;
+            }
+        };
+
+        self.status.send_modify(|s| s.connected = false);
+        self.routes
+            .fail_all(|| LtpError::ConnectionClosed(reason.clone()));
+        self.emit(NodeEvent::Closed(reason));
+        while let Some(command) = commands.recv().await {
+            command.fail(LtpError::NotConnected);
+        }
+    }
+
+    async fn command(&mut self, command: Command) -> Flow {
+        match command {
+            Command::Send { message, done } => {
+                let _ = done.send(self.send(&message).await);
+            }
+            Command::Route { message, reply } => match self.send(&message).await {
+                Ok(()) => self
+                    .routes
+                    .insert(message.correlation_id.unwrap_or_default(), reply),
+                Err(e) => {
+                    let _ = reply.send(Err(e));
+                }
+            },
+            Command::Close { done } => {
+                let _ = done.send(self.ws.close(None).await.map_err(Into::into));
+                return ControlFlow::Break(None);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    async fn send(&mut self, message: &impl Serialize) -> Result<()> {
+        let json = serde_json::to_string(message)?;
+        self.ws.send(Message::Text(json)).await?;
+        Ok(())
+    }
+
+    fn frame(&mut self, frame: Option<std::result::Result<Message, tungstenite::Error>>) -> Flow {
+        match frame {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                Ok(message) => self.message(message),
+                Err(e) => self.emit(NodeEvent::Error(LtpError::ProtocolViolation(format!(
+                    "malformed message from node: {}",
+                    e
+                )))),
+            },
+            Some(Ok(Message::Binary(_))) => self.emit(NodeEvent::Error(
+                LtpError::ProtocolViolation("binary frame from node".to_string()),
+            )),
+            Some(Ok(Message::Close(frame))) => {
+                return ControlFlow::Break(frame.map(|f| CloseReason {
+                    code: f.code.into(),
+                    reason: f.reason.into_owned(),
+                }))
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                self.emit(NodeEvent::Error(e.into()));
+                return ControlFlow::Break(None);
+            }
+            None => return ControlFlow::Break(None),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn message(&mut self, reply: Correlated<LtpOutgoingMessage>) {
+        self.echoes_ids |= reply.correlation_id.is_some();
+        match reply.message {
+            LtpOutgoingMessage::HeartbeatAck { .. } => {
+                if let Some(heartbeat) = self.heartbeat.as_mut() {
+                    heartbeat.pong_received();
+                    let rtt = heartbeat.last_rtt();
+                    self.status.send_modify(|s| s.rtt = rtt);
+                }
+            }
+            LtpOutgoingMessage::RouteSuggestion(suggestion) => {
+                match self.route(reply.correlation_id) {
+                    Some(route) => {
+                        let _ = route.send(Ok(suggestion));
+                    }
+                    None => self.emit(NodeEvent::RouteSuggestion(suggestion)),
+                }
+            }
+            LtpOutgoingMessage::Error {
+                code,
+                message,
+                retry_after_ms,
+            } => {
+                let error = node_error(code, message, retry_after_ms);
+                // Errors without an id come from the connection (rate
+                // limits, unparseable frames), not from a route request.
+                match reply.correlation_id.and_then(|id| self.routes.take(&id)) {
+                    Some(route) => {
+                        let _ = route.send(Err(error));
+                    }
+                    None => self.emit(NodeEvent::Error(error)),
+                }
+            }
+            LtpOutgoingMessage::HelloAck { .. } => self.emit(NodeEvent::Error(
+                LtpError::ProtocolViolation("hello_ack after the handshake".to_string()),
+            )),
+        }
+    }
+
+    /// The route request a suggestion answers. Nodes that have never echoed
+    /// `correlation_id` answer in request order.
+    fn route(&mut self, correlation_id: Option<String>) -> Option<Reply<RouteSuggestion>> {
+        match correlation_id {
+            Some(id) => self.routes.take(&id),
+            None if self.echoes_ids => None,
+            None => self.routes.take_oldest(),
+        }
+    }
+
+    /// Send a heartbeat, or end the session when one has gone unacked too long.
+    async fn heartbeat(&mut self) -> Flow {
+        let Some(heartbeat) = self.heartbeat.as_mut() else {
+            return ControlFlow::Continue(());
+        };
+        if let Some(silent_for) = heartbeat.timed_out() {
+            let _ = self.ws.close(None).await;
+            self.emit(NodeEvent::HeartbeatTimeout { silent_for });
+            return ControlFlow::Break(None);
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        let message = LtpIncomingMessage::Heartbeat {
+            session_id: self.session_id.clone(),
+            timestamp_ms,
+        };
+        // A failed send surfaces on the read side.
+        if self.send(&message).await.is_ok() {
+            if let Some(heartbeat) = self.heartbeat.as_mut() {
+                heartbeat.ping_sent();
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Send errors only mean nobody is listening.
+    fn emit(&self, event: NodeEvent) {
+        let _ = self.events.send(event);
+    }
+}
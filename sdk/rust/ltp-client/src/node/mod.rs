@@ -0,0 +1,272 @@
+//! Client for the `ltp-rust-node` dialect.
+//!
+//! The node does not speak the envelope protocol of `LtpClient`: a session
+//! opens with `hello`, is kept alive with `heartbeat`, takes `orientation`
+//! updates and answers `route_request` with a `route_suggestion`.
+
+mod connection;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::SinkExt;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::client::{next_handshake_text, WsStream};
+use crate::error::{LtpError, Result};
+use crate::events::{CloseReason, LtpEvents};
+use crate::handle::Completion;
+use crate::pending;
+
+use connection::{Command, NodeTask, Status};
+/// Wire types, shared with the node through `ltp-protocol`.
+pub use ltp_protocol::node as protocol;
+pub use protocol::{
+    Correlated, ErrorCode, LtpIncomingMessage, LtpOutgoingMessage, RouteDebugInfo, RouteSuggestion,
+    Sector, TimeOrientationBoostPayload, TimeOrientationDirectionPayload,
+};
+
+/// Something received from the node outside of a request.
+#[derive(Debug)]
+pub enum NodeEvent {
+    /// An `error` from the node that no route request was waiting for, as
+    /// `LtpError::Node`, a frame that could not be decoded, or a transport
+    /// error.
+    Error(LtpError),
+    /// A `route_suggestion` that no route request was waiting for.
+    RouteSuggestion(RouteSuggestion),
+    /// No `heartbeat_ack` arrived within the heartbeat timeout; the
+    /// connection was dropped and `Closed(None)` follows.
+    HeartbeatTimeout { silent_for: Duration },
+    /// The session ended; `None` when it dropped without a close frame.
+    Closed(Option<CloseReason>),
+}
+
+/// Inbound events of a `NodeClient`.
+pub type NodeEvents = LtpEvents<NodeEvent>;
+
+/// Builder for a session with an `ltp-rust-node`.
+///
+/// Configure it, take its `events()`, then `hello` to get a `NodeHandle`.
+pub struct NodeClient {
+    url: String,
+    client_label: Option<String>,
+    heartbeat_interval_ms: u64,
+    heartbeat_timeout_ms: u64,
+    handshake_timeout_ms: u64,
+    request_timeout_ms: u64,
+    events_tx: mpsc::UnboundedSender<NodeEvent>,
+    events_rx: Option<mpsc::UnboundedReceiver<NodeEvent>>,
+}
+
+impl NodeClient {
+    pub fn new(url: impl Into<String>) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        Self {
+            url: url.into(),
+            client_label: None,
+            heartbeat_interval_ms: 15_000,
+            heartbeat_timeout_ms: 45_000,
+            handshake_timeout_ms: 10_000,
+            request_timeout_ms: 30_000,
+            events_tx,
+            events_rx: Some(events_rx),
+        }
+    }
+
+    /// Label recorded by the node in the session trace
+    pub fn with_client_label(mut self, label: impl Into<String>) -> Self {
+        self.client_label = Some(label.into());
+        self
+    }
+
+    /// Set heartbeat interval; `0` disables heartbeats
+    pub fn with_heartbeat_interval(mut self, interval_ms: u64) -> Self {
+        self.heartbeat_interval_ms = interval_ms;
+        self
+    }
+
+    /// Set heartbeat timeout
+    pub fn with_heartbeat_timeout(mut self, timeout_ms: u64) -> Self {
+        self.heartbeat_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Set how long opening the socket and the `hello` exchange may take
+    pub fn with_handshake_timeout(mut self, timeout_ms: u64) -> Self {
+        self.handshake_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Set how long `request_route` waits for the node's answer
+    pub fn with_request_timeout(mut self, timeout_ms: u64) -> Self {
+        self.request_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Take the stream of inbound events. Returns `None` after the first call.
+    /// Events are buffered until received; see `LtpEvents`.
+    pub fn events(&mut self) -> Option<NodeEvents> {
+        self.events_rx.take().map(LtpEvents::new)
+    }
+
+    /// Open a session with `api_key` and start heartbeating.
+    ///
+    /// An unknown key or a full node fails with `LtpError::Node`.
+    pub async fn hello(self, api_key: impl Into<String>) -> Result<NodeHandle> {
+        let timeout = Duration::from_millis(self.handshake_timeout_ms);
+        let (ws, node_id, session_id) = tokio::time::timeout(timeout, self.open(api_key.into()))
+            .await
+            .map_err(|_| LtpError::HandshakeTimeout(timeout))??;
+
+        let heartbeat = (self.heartbeat_interval_ms > 0).then(|| {
+            (
+                Duration::from_millis(self.heartbeat_interval_ms),
+                Duration::from_millis(self.heartbeat_timeout_ms),
+            )
+        });
+        let task = NodeTask::new(ws, session_id.clone(), heartbeat, self.events_tx);
+        let (commands, status) = task.spawn();
+        Ok(NodeHandle {
+            commands,
+            status,
+            node_id: node_id.into(),
+            session_id: session_id.into(),
+            request_timeout: Duration::from_millis(self.request_timeout_ms),
+        })
+    }
+
+    async fn open(&self, api_key: String) -> Result<(WsStream, String, String)> {
+        let url = url::Url::parse(&self.url)
+            .map_err(|e| LtpError::InvalidState(format!("Invalid URL: {}", e)))?;
+        let (mut ws, _) = connect_async(url).await?;
+
+        let hello = LtpIncomingMessage::Hello {
+            api_key,
+            client_label: self.client_label.clone(),
+        };
+        ws.send(Message::Text(serde_json::to_string(&hello)?))
+            .await?;
+
+        let text = next_handshake_text(&mut ws).await?;
+        let reply = serde_json::from_str(&text)
+            .map_err(|e| LtpError::ProtocolViolation(format!("malformed reply to hello: {}", e)))?;
+        match reply {
+            LtpOutgoingMessage::HelloAck {
+                node_id,
+                accepted: true,
+                session_id,
+            } => Ok((ws, node_id, session_id)),
+            LtpOutgoingMessage::HelloAck { .. } => Err(LtpError::HandshakeRejected {
+                reason: "hello not accepted".to_string(),
+                suggest_new: false,
+            }),
+            LtpOutgoingMessage::Error {
+                code,
+                message,
+                retry_after_ms,
+            } => Err(node_error(code, message, retry_after_ms)),
+            other => Err(LtpError::ProtocolViolation(format!(
+                "expected hello_ack, got {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// A cheap, cloneable handle to a node session.
+///
+/// Every clone talks to the same task, which owns the socket and sends the
+/// heartbeats. Route requests and orientation updates carry a
+/// `correlation_id` that the node echoes, so suggestions and errors reach
+/// the request they answer; replies to nothing waiting go to the event
+/// stream. Route suggestions without an id, from nodes that have never
+/// echoed one, are matched to route requests in the order the requests were
+/// sent; errors without an id always go to the event stream.
+#[derive(Debug, Clone)]
+pub struct NodeHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    status: watch::Receiver<Status>,
+    node_id: Arc<str>,
+    session_id: Arc<str>,
+    request_timeout: Duration,
+}
+
+impl NodeHandle {
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Whether the session is still open.
+    pub fn is_connected(&self) -> bool {
+        self.status.borrow().connected
+    }
+
+    /// Round-trip time of the last acknowledged heartbeat.
+    pub fn heartbeat_rtt(&self) -> Option<Duration> {
+        self.status.borrow().rtt
+    }
+
+    /// Report the client's focus momentum and time orientation. The node
+    /// does not acknowledge them; the `Completion` resolves once written.
+    pub fn send_orientation(
+        &self,
+        focus_momentum: Option<f64>,
+        time_orientation: Option<TimeOrientationBoostPayload>,
+    ) -> Completion {
+        let (done, rx) = oneshot::channel();
+        let message = correlated(LtpIncomingMessage::Orientation {
+            session_id: self.session_id.to_string(),
+            focus_momentum,
+            time_orientation,
+        });
+        match self.commands.send(Command::Send { message, done }) {
+            Ok(()) => Completion::pending(rx),
+            Err(_) => Completion::failed(LtpError::NotConnected),
+        }
+    }
+
+    /// Ask the node which sector to route to.
+    ///
+    /// Fails with `RequestTimeout` after the client's request timeout, and
+    /// with `ConnectionClosed` if the session ends first. Dropping the future
+    /// cancels the request; a late suggestion goes to the event stream.
+    pub async fn request_route(&self, hint_sector: Option<Sector>) -> Result<RouteSuggestion> {
+        let (reply, rx) = oneshot::channel();
+        let message = correlated(LtpIncomingMessage::RouteRequest {
+            session_id: self.session_id.to_string(),
+            hint_sector,
+        });
+        self.commands
+            .send(Command::Route { message, reply })
+            .map_err(|_| LtpError::NotConnected)?;
+        pending::wait(rx, self.request_timeout).await
+    }
+
+    /// Close the session. Later requests fail with `NotConnected`.
+    pub async fn close(&self) -> Result<()> {
+        let (done, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Close { done })
+            .map_err(|_| LtpError::NotConnected)?;
+        Completion::pending(rx).await
+    }
+}
+
+/// Tag `message` with a fresh correlation id.
+fn correlated(message: LtpIncomingMessage) -> Correlated<LtpIncomingMessage> {
+    Correlated::new(Some(uuid::Uuid::new_v4().to_string()), message)
+}
+
+fn node_error(code: ErrorCode, message: Option<String>, retry_after_ms: Option<u64>) -> LtpError {
+    LtpError::Node {
+        code,
+        message,
+        retry_after: retry_after_ms.map(Duration::from_millis),
+    }
+}
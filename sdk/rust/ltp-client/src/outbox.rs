@@ -0,0 +1,355 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{LtpError, Result};
+use crate::types::LtpEnvelope;
+
+/// What to do with a message sent while the outbox is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Evict the oldest queued message to make room.
+    #[default]
+    DropOldest,
+    /// Discard the message being sent.
+    DropNewest,
+    /// Fail the send with `LtpError::OutboxFull`.
+    Reject,
+}
+
+/// Why a queued message was discarded without being sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    Full,
+    Expired,
+}
+
+/// Limits of an `Outbox`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxConfig {
+    pub max_messages: usize,
+    /// Messages queued for longer are discarded instead of sent; `None`
+    /// keeps them until they are sent or evicted.
+    pub max_age: Option<Duration>,
+    pub on_full: DropPolicy,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 1_000,
+            max_age: None,
+            on_full: DropPolicy::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Queued {
+    queued_at_ms: u64,
+    envelope: LtpEnvelope,
+}
+
+/// Messages sent while disconnected, oldest first.
+///
+/// Envelopes are queued before they are signed and chained, and finalized
+/// one by one when the client reconnects, so `prev_message_hash` follows the
+/// order of the original sends within the new session.
+///
+/// A file-backed outbox keeps one JSON line per message and survives a
+/// restart of the process. Delivery is at least once: messages sent just
+/// before a crash may be sent again.
+#[derive(Debug)]
+pub struct Outbox {
+    config: OutboxConfig,
+    queue: VecDeque<Queued>,
+    path: Option<PathBuf>,
+    /// Messages were removed since the file was last rewritten.
+    dirty: bool,
+}
+
+impl Outbox {
+    /// An outbox kept in memory only.
+    pub fn memory(config: OutboxConfig) -> Self {
+        Self {
+            config,
+            queue: VecDeque::new(),
+            path: None,
+            dirty: false,
+        }
+    }
+
+    /// An outbox persisted to `path`, loading any messages left there.
+    ///
+    /// A final line cut short by a crash mid-append is dropped and the file
+    /// rewritten without it.
+    pub fn open(path: impl AsRef<Path>, config: OutboxConfig) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let torn = !content.is_empty() && !content.ends_with('\n');
+        let mut queue = VecDeque::new();
+        let mut lines = content.lines().peekable();
+        while let Some(line) = lines.next() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(queued) => queue.push_back(queued),
+                Err(_) if torn && lines.peek().is_none() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let mut outbox = Self {
+            config,
+            queue,
+            path: Some(path),
+            dirty: torn,
+        };
+        // Appends must not land on the end of the torn line.
+        outbox.persist()?;
+        Ok(outbox)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queue `envelope`, returning any message dropped to respect the
+    /// size limit.
+    pub(crate) fn push(
+        &mut self,
+        envelope: LtpEnvelope,
+        now: SystemTime,
+    ) -> Result<Option<LtpEnvelope>> {
+        let mut dropped = None;
+        if self.queue.len() >= self.config.max_messages {
+            match self.config.on_full {
+                DropPolicy::Reject => return Err(LtpError::OutboxFull),
+                DropPolicy::DropNewest => return Ok(Some(envelope)),
+                DropPolicy::DropOldest => {
+                    dropped = self.queue.pop_front().map(|q| q.envelope);
+                }
+            }
+        }
+        let queued = Queued {
+            queued_at_ms: unix_ms(now),
+            envelope,
+        };
+        if dropped.is_some() {
+            self.queue.push_back(queued);
+            self.dirty = true;
+            self.persist()?;
+        } else {
+            self.append(&queued)?;
+            self.queue.push_back(queued);
+        }
+        Ok(dropped)
+    }
+
+    /// The oldest message still within `max_age`, after discarding the
+    /// expired ones before it.
+    pub(crate) fn front(
+        &mut self,
+        now: SystemTime,
+        expired: &mut Vec<LtpEnvelope>,
+    ) -> Option<&LtpEnvelope> {
+        if let Some(max_age) = self.config.max_age {
+            let cutoff = unix_ms(now).saturating_sub(max_age.as_millis() as u64);
+            while self.queue.front().is_some_and(|q| q.queued_at_ms < cutoff) {
+                expired.extend(self.queue.pop_front().map(|q| q.envelope));
+                self.dirty = true;
+            }
+        }
+        self.queue.front().map(|q| &q.envelope)
+    }
+
+    /// Remove the message returned by `front` once it has been sent.
+    pub(crate) fn pop_front(&mut self) {
+        self.queue.pop_front();
+        self.dirty = true;
+    }
+
+    /// Rewrite the backing file to match the queue, if messages were
+    /// removed since it was last written. Pushes are appended as they come.
+    pub(crate) fn persist(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let tmp = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            for queued in &self.queue {
+                serde_json::to_writer(&mut writer, queued)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&tmp, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn append(&self, queued: &Queued) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_vec(queued)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ContentEncoding, Payload};
+
+    fn envelope(kind: &str) -> LtpEnvelope {
+        LtpEnvelope {
+            r#type: "event".to_string(),
+            thread_id: String::new(),
+            session_id: None,
+            timestamp: 0,
+            content_encoding: ContentEncoding::Json,
+            payload: Payload {
+                kind: kind.to_string(),
+                data: serde_json::json!({}),
+            },
+            meta: None,
+            nonce: None,
+            signature: None,
+            prev_message_hash: None,
+            encrypted_metadata: None,
+            routing_tag: None,
+            correlation_id: None,
+        }
+    }
+
+    fn kinds(outbox: &Outbox) -> Vec<&str> {
+        outbox
+            .queue
+            .iter()
+            .map(|q| q.envelope.payload.kind.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn applies_drop_policies_when_full() {
+        let now = SystemTime::now();
+        let config = |on_full| OutboxConfig {
+            max_messages: 2,
+            on_full,
+            ..OutboxConfig::default()
+        };
+
+        let mut oldest = Outbox::memory(config(DropPolicy::DropOldest));
+        for kind in ["a", "b"] {
+            assert!(oldest.push(envelope(kind), now).unwrap().is_none());
+        }
+        let dropped = oldest.push(envelope("c"), now).unwrap().unwrap();
+        assert_eq!(dropped.payload.kind, "a");
+        assert_eq!(kinds(&oldest), ["b", "c"]);
+
+        let mut newest = Outbox::memory(config(DropPolicy::DropNewest));
+        for kind in ["a", "b"] {
+            newest.push(envelope(kind), now).unwrap();
+        }
+        let dropped = newest.push(envelope("c"), now).unwrap().unwrap();
+        assert_eq!(dropped.payload.kind, "c");
+        assert_eq!(kinds(&newest), ["a", "b"]);
+
+        let mut reject = Outbox::memory(config(DropPolicy::Reject));
+        for kind in ["a", "b"] {
+            reject.push(envelope(kind), now).unwrap();
+        }
+        assert!(matches!(
+            reject.push(envelope("c"), now),
+            Err(LtpError::OutboxFull)
+        ));
+    }
+
+    #[test]
+    fn skips_expired_messages() {
+        let mut outbox = Outbox::memory(OutboxConfig {
+            max_age: Some(Duration::from_secs(60)),
+            ..OutboxConfig::default()
+        });
+        let start = SystemTime::now();
+        outbox.push(envelope("stale"), start).unwrap();
+        outbox
+            .push(envelope("fresh"), start + Duration::from_secs(45))
+            .unwrap();
+
+        let mut expired = Vec::new();
+        let front = outbox.front(start + Duration::from_secs(90), &mut expired);
+        assert_eq!(front.unwrap().payload.kind, "fresh");
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].payload.kind, "stale");
+    }
+
+    #[test]
+    fn file_backed_outbox_survives_reopening() {
+        let path = std::env::temp_dir().join(format!("ltp-outbox-{}.jsonl", uuid::Uuid::new_v4()));
+        let now = SystemTime::now();
+        {
+            let mut outbox = Outbox::open(&path, OutboxConfig::default()).unwrap();
+            for kind in ["a", "b", "c"] {
+                outbox.push(envelope(kind), now).unwrap();
+            }
+            let mut expired = Vec::new();
+            assert!(outbox.front(now, &mut expired).is_some());
+            outbox.pop_front();
+            outbox.persist().unwrap();
+        }
+        let reopened = Outbox::open(&path, OutboxConfig::default()).unwrap();
+        assert_eq!(kinds(&reopened), ["b", "c"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_a_final_line_torn_by_a_crash() {
+        let path = std::env::temp_dir().join(format!("ltp-outbox-{}.jsonl", uuid::Uuid::new_v4()));
+        let now = SystemTime::now();
+        {
+            let mut outbox = Outbox::open(&path, OutboxConfig::default()).unwrap();
+            for kind in ["a", "b"] {
+                outbox.push(envelope(kind), now).unwrap();
+            }
+        }
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 10]).unwrap();
+
+        let mut reopened = Outbox::open(&path, OutboxConfig::default()).unwrap();
+        assert_eq!(kinds(&reopened), ["a"]);
+        reopened.push(envelope("c"), now).unwrap();
+        let reopened = Outbox::open(&path, OutboxConfig::default()).unwrap();
+        assert_eq!(kinds(&reopened), ["a", "c"]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+//! Requests waiting for the reply that carries their correlation id.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::error::{LtpError, Result};
+
+pub(crate) type Reply<T> = oneshot::Sender<Result<T>>;
+
+/// Requests sent and not yet answered, oldest first.
+pub(crate) struct Pending<T> {
+    waiting: VecDeque<(String, Reply<T>)>,
+}
+
+impl<T> Default for Pending<T> {
+    fn default() -> Self {
+        Self {
+            waiting: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> Pending<T> {
+    /// Wait for the reply to `id`, forgetting requests whose caller stopped
+    /// waiting (timed out or dropped the future).
+    pub(crate) fn insert(&mut self, id: String, reply: Reply<T>) {
+        self.waiting.retain(|(_, reply)| !reply.is_closed());
+        self.waiting.push_back((id, reply));
+    }
+
+    /// The caller still waiting for the reply to `id`. `None` means the
+    /// reply belongs on the event stream.
+    pub(crate) fn take(&mut self, id: &str) -> Option<Reply<T>> {
+        let index = self.waiting.iter().position(|(waiting, _)| waiting == id)?;
+        let (_, reply) = self.waiting.remove(index)?;
+        (!reply.is_closed()).then_some(reply)
+    }
+
+    /// The oldest caller still waiting, for replies that carry no id.
+    pub(crate) fn take_oldest(&mut self) -> Option<Reply<T>> {
+        self.waiting.retain(|(_, reply)| !reply.is_closed());
+        self.waiting.pop_front().map(|(_, reply)| reply)
+    }
+
+    /// Fail every waiting request.
+    pub(crate) fn fail_all(&mut self, error: impl Fn() -> LtpError) {
+        for (_, reply) in self.waiting.drain(..) {
+            let _ = reply.send(Err(error()));
+        }
+    }
+}
+
+/// Wait at most `timeout` for the task to answer. Dropping the future
+/// cancels the request: its reply then goes to the event stream.
+pub(crate) async fn wait<T>(rx: oneshot::Receiver<Result<T>>, timeout: Duration) -> Result<T> {
+    match tokio::time::timeout(timeout, rx).await {
+        // The task only drops a reply when it is shutting down.
+        Ok(reply) => reply.unwrap_or(Err(LtpError::NotConnected)),
+        Err(_) => Err(LtpError::RequestTimeout(timeout)),
+    }
+}
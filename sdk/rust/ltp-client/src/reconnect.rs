@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// When and how often a lost connection is re-established.
+///
+/// Attempt `n` waits `initial_delay * multiplier^(n - 1)`, capped at
+/// `max_delay`, then scaled by a random factor in `1 ± jitter` so that many
+/// clients dropped by one server restart do not reconnect in lockstep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Fraction of the delay to randomise, between 0 and 1.
+    pub jitter: f64,
+    /// Attempts before giving up; `None` retries forever.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_retries: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before reconnect attempt `attempt`, counted from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let max = self.max_delay.as_secs_f64();
+        let base = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent)).min(max);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+        Duration::from_secs_f64((base * factor).min(max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_backs_off_exponentially_up_to_the_cap() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+            ..ReconnectPolicy::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+
+        let jittered = ReconnectPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = jittered.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(300));
+        }
+    }
+}
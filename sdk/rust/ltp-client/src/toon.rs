@@ -0,0 +1,377 @@
+//! TOON tables for `ContentEncoding::Toon` payloads (`specs/LTP-toon.md`).
+//!
+//! Only the tabular form is supported: a non-empty array of objects that
+//! share their keys and hold primitive values.
+//!
+//! ```text
+//! affect_log[2]{arousal,t,valence}:
+//!   -0.1,1,0.2
+//!   -0.2,2,0.3
+//! ```
+//!
+//! Columns come out in `serde_json` key order, which is sorted. Strings are
+//! bare unless they would read back as something else or contain a
+//! delimiter; then they are quoted with JSON escapes. Anything that is not
+//! tabular stays JSON, which is what `encode_payload` falls back to.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+use crate::error::{Result, ToonError};
+use crate::types::{ContentEncoding, LtpEnvelope};
+
+const INDENT: &str = "  ";
+
+/// Write `value` as a TOON table named `name`. Fails with
+/// `ToonError::NotTabular` unless it serializes to a non-empty array of
+/// flat objects with the same keys.
+pub fn to_string<T: Serialize + ?Sized>(name: &str, value: &T) -> Result<String> {
+    let value = serde_json::to_value(value)?;
+    Ok(encode_table(name, &value).ok_or(ToonError::NotTabular)?)
+}
+
+/// Read a TOON table into `T`, typically a `Vec` of row structs. The table
+/// name is not checked.
+pub fn from_str<T: DeserializeOwned>(toon: &str) -> Result<T> {
+    let (_, rows) = decode(toon)?;
+    Ok(serde_json::from_value(rows)?)
+}
+
+/// Read a TOON table into its name and a JSON array of row objects.
+pub fn decode(toon: &str) -> Result<(String, Value)> {
+    Ok(decode_table(toon)?)
+}
+
+/// `payload.data` for a payload of `kind`: a TOON table named after the
+/// kind when `data` is tabular, otherwise `data` itself as JSON.
+pub fn encode_payload(kind: &str, data: Value) -> (ContentEncoding, Value) {
+    match encode_table(kind, &data) {
+        Some(toon) => (ContentEncoding::Toon, Value::String(toon)),
+        None => (ContentEncoding::Json, data),
+    }
+}
+
+/// `payload.data` of `envelope` as JSON, decoded if it was sent as TOON.
+pub fn decode_payload(envelope: &LtpEnvelope) -> Result<Value> {
+    match (envelope.content_encoding, &envelope.payload.data) {
+        (ContentEncoding::Json, data) => Ok(data.clone()),
+        (ContentEncoding::Toon, Value::String(toon)) => Ok(decode_table(toon)?.1),
+        (ContentEncoding::Toon, _) => Err(ToonError::NotText.into()),
+    }
+}
+
+fn encode_table(name: &str, value: &Value) -> Option<String> {
+    let rows = value
+        .as_array()?
+        .iter()
+        .map(Value::as_object)
+        .collect::<Option<Vec<&Map<String, Value>>>>()?;
+    let first = rows.first().filter(|first| !first.is_empty())?;
+    let tabular = rows
+        .iter()
+        .all(|row| row.keys().eq(first.keys()) && row.values().all(is_primitive));
+    if !tabular {
+        return None;
+    }
+
+    let fields: Vec<String> = first.keys().map(|field| key(field)).collect();
+    let mut out = format!("{}[{}]{{{}}}:\n", key(name), rows.len(), fields.join(","));
+    for row in rows {
+        let cells: Vec<String> = row.values().map(cell).collect();
+        out.push_str(INDENT);
+        out.push_str(&cells.join(","));
+        out.push('\n');
+    }
+    Some(out)
+}
+
+fn is_primitive(value: &Value) -> bool {
+    !matches!(value, Value::Array(_) | Value::Object(_))
+}
+
+/// A table or field name, quoted unless it is a plain identifier.
+fn key(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if plain {
+        name.to_string()
+    } else {
+        quote(name)
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::String(s) if needs_quotes(s) => quote(s),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Whether `s` written bare would read back as something else.
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.trim() != s
+        || matches!(s, "null" | "true" | "false")
+        || s.parse::<Number>().is_ok()
+        || s.contains(|c: char| {
+            matches!(c, ',' | '"' | '\\' | ':' | '[' | ']' | '{' | '}') || c.is_control()
+        })
+}
+
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).expect("strings always serialize")
+}
+
+fn decode_table(toon: &str) -> std::result::Result<(String, Value), ToonError> {
+    let mut lines = toon
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (header_line, header) = lines.next().ok_or_else(|| syntax(1, "empty table"))?;
+    let (name, count, fields) = parse_header(header).map_err(|m| syntax(header_line, m))?;
+
+    let mut rows = Vec::with_capacity(count);
+    for (line, text) in lines {
+        if rows.len() == count {
+            return Err(syntax(
+                line,
+                format!("more than the {} declared rows", count),
+            ));
+        }
+        let cells = split(text.trim()).map_err(|m| syntax(line, m))?;
+        if cells.len() != fields.len() {
+            return Err(syntax(
+                line,
+                format!("expected {} values, got {}", fields.len(), cells.len()),
+            ));
+        }
+        let row = fields
+            .iter()
+            .cloned()
+            .zip(cells)
+            .map(|(field, raw)| Ok((field, parse_cell(raw)?)))
+            .collect::<std::result::Result<Map<_, _>, String>>()
+            .map_err(|m| syntax(line, m))?;
+        rows.push(Value::Object(row));
+    }
+    if rows.len() != count {
+        return Err(syntax(
+            header_line,
+            format!("declares {} rows, found {}", count, rows.len()),
+        ));
+    }
+    Ok((name, Value::Array(rows)))
+}
+
+/// `name[N]{a,b}:` into the name, `N` and the field names.
+fn parse_header(line: &str) -> std::result::Result<(String, usize, Vec<String>), String> {
+    let expected = || "expected a header like `name[N]{a,b}:`".to_string();
+    let body = line.trim().strip_suffix(':').ok_or_else(expected)?;
+    let name_len = if body.starts_with('"') {
+        quoted_len(body).ok_or("unterminated string")?
+    } else {
+        body.find('[').ok_or_else(expected)?
+    };
+    let (name, rest) = body.split_at(name_len);
+    let (count, fields) = rest
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .ok_or_else(expected)?;
+    let count = count
+        .parse()
+        .map_err(|_| format!("invalid row count `{}`", count))?;
+    let fields = fields
+        .strip_prefix('{')
+        .and_then(|fields| fields.strip_suffix('}'))
+        .ok_or_else(expected)?;
+    let fields = split(fields)?
+        .into_iter()
+        .map(parse_key)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if let Some(i) = (1..fields.len()).find(|&i| fields[..i].contains(&fields[i])) {
+        return Err(format!("duplicate field `{}`", fields[i]));
+    }
+    Ok((parse_key(name)?, count, fields))
+}
+
+/// Byte length of the quoted string `s` starts with, quotes included.
+fn quoted_len(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split on the commas outside quoted strings.
+fn split(text: &str) -> std::result::Result<Vec<&str>, String> {
+    let mut cells = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                cells.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return Err("unterminated string".to_string());
+    }
+    cells.push(text[start..].trim());
+    Ok(cells)
+}
+
+fn parse_key(raw: &str) -> std::result::Result<String, String> {
+    let raw = raw.trim();
+    if raw.starts_with('"') {
+        serde_json::from_str(raw).map_err(|e| format!("invalid name {}: {}", raw, e))
+    } else if raw.is_empty() {
+        Err("empty name".to_string())
+    } else {
+        Ok(raw.to_string())
+    }
+}
+
+/// An empty cell is `null`, as written by the JS example codec.
+fn parse_cell(raw: &str) -> std::result::Result<Value, String> {
+    Ok(match raw {
+        "" | "null" => Value::Null,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ if raw.starts_with('"') => serde_json::from_str(raw)
+            .map(Value::String)
+            .map_err(|e| format!("invalid string {}: {}", raw, e))?,
+        _ => match raw.parse::<Number>() {
+            Ok(number) => Value::Number(number),
+            Err(_) => Value::String(raw.to_string()),
+        },
+    })
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> ToonError {
+    ToonError::Syntax {
+        line,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[test]
+    fn reads_the_spec_example() {
+        let toon = "affect_log[2]{t,valence,arousal}:\n  1,0.2,-0.1\n  2,0.3,-0.2\n";
+        let (name, rows) = decode(toon).unwrap();
+        assert_eq!(name, "affect_log");
+        assert_eq!(
+            rows,
+            json!([
+                {"t": 1, "valence": 0.2, "arousal": -0.1},
+                {"t": 2, "valence": 0.3, "arousal": -0.2}
+            ])
+        );
+
+        let (encoding, data) = encode_payload("affect_log", rows.clone());
+        assert!(matches!(encoding, ContentEncoding::Toon));
+        assert_eq!(
+            data,
+            json!("affect_log[2]{arousal,t,valence}:\n  -0.1,1,0.2\n  -0.2,2,0.3\n")
+        );
+        assert_eq!(decode(data.as_str().unwrap()).unwrap().1, rows);
+    }
+
+    #[test]
+    fn quotes_strings_that_would_read_back_differently() {
+        let rows = json!([
+            {"note": "plain words", "tag": "a,b", "odd key": true},
+            {"note": "", "tag": "42", "odd key": null},
+            {"note": " padded ", "tag": "say \"hi\"\nbye", "odd key": false},
+            {"note": "null", "tag": "x:y", "odd key": 1.5}
+        ]);
+        let toon = to_string("notes", &rows).unwrap();
+        assert!(toon.starts_with("notes[4]{note,\"odd key\",tag}:\n"));
+        assert!(toon.contains("  plain words,true,\"a,b\"\n"));
+        assert!(toon.contains("  \"\",null,\"42\"\n"));
+        assert_eq!(decode(&toon).unwrap().1, rows);
+    }
+
+    #[test]
+    fn round_trips_rows_through_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Sample {
+            t: u32,
+            label: String,
+            score: Option<f64>,
+        }
+        let samples = vec![
+            Sample {
+                t: 1,
+                label: "calm".to_string(),
+                score: Some(0.25),
+            },
+            Sample {
+                t: 2,
+                label: "alert".to_string(),
+                score: None,
+            },
+        ];
+        let toon = to_string("samples", &samples).unwrap();
+        assert_eq!(from_str::<Vec<Sample>>(&toon).unwrap(), samples);
+    }
+
+    #[test]
+    fn leaves_non_tabular_data_as_json() {
+        for data in [
+            json!({"t": 1}),
+            json!([]),
+            json!([1, 2]),
+            json!([{}]),
+            json!([{"t": 1}, {"u": 2}]),
+            json!([{"t": 1}, {"t": 2, "u": 3}]),
+            json!([{"t": [1, 2]}]),
+        ] {
+            let (encoding, encoded) = encode_payload("log", data.clone());
+            assert!(matches!(encoding, ContentEncoding::Json), "{}", data);
+            assert_eq!(encoded, data);
+            assert!(matches!(
+                to_string("log", &data),
+                Err(crate::LtpError::Toon(ToonError::NotTabular))
+            ));
+        }
+    }
+
+    #[test]
+    fn reports_malformed_tables_with_their_line() {
+        let line_of = |toon: &str| match decode(toon) {
+            Err(crate::LtpError::Toon(ToonError::Syntax { line, .. })) => line,
+            other => panic!("expected a syntax error for {:?}, got {:?}", toon, other),
+        };
+        assert_eq!(line_of(""), 1);
+        assert_eq!(line_of("log{t}:\n  1\n"), 1);
+        assert_eq!(line_of("log[x]{t}:\n  1\n"), 1);
+        assert_eq!(line_of("log[1]{t,t}:\n  1,2\n"), 1);
+        assert_eq!(line_of("log[2]{t,u}:\n  1,2\n  3\n"), 3);
+        assert_eq!(line_of("log[1]{t}:\n  1\n  2\n"), 3);
+        assert_eq!(line_of("log[3]{t}:\n  1\n  2\n"), 1);
+        assert_eq!(line_of("log[1]{t}:\n  \"open\n"), 2);
+    }
+}
@@ -1,8 +1,21 @@
+#![allow(clippy::bool_assert_comparison)]
+
+use futures_util::{SinkExt, StreamExt};
 use ltp_client::crypto::{generate_hmac_nonce, hmac_sha256};
+use ltp_client::node::{
+    ErrorCode, Sector, TimeOrientationBoostPayload, TimeOrientationDirectionPayload,
+};
 use ltp_client::types::*;
-use ltp_client::LtpClient;
+use ltp_client::{
+    toon, ConnectionState, IntegrityError, LtpClient, LtpError, LtpEvent, NodeClient, NodeEvent,
+    Outbox, OutboxConfig, ReconnectPolicy,
+};
+#[allow(unused_imports)]
 use regex::Regex;
 use serde_json::json;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
 
 fn is_hex(s: &str) -> bool {
     !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit())
@@ -32,6 +45,7 @@ fn test_envelope_serialization() {
         prev_message_hash: None,
         encrypted_metadata: None,
         routing_tag: None,
+        correlation_id: None,
     };
 
     let json_str = serde_json::to_string(&envelope).unwrap();
@@ -102,3 +116,911 @@ fn test_hmac_nonce_format_with_session_mac_key() {
     );
 }
 
+#[tokio::test]
+async fn delivers_inbound_frames_as_typed_events() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 15000
+        });
+        let envelope = json!({
+            "type": "state_update",
+            "thread_id": "thread-1",
+            "timestamp": 1,
+            "payload": {"kind": "focus", "data": {"level": 3}},
+            "nonce": "n-1"
+        });
+        let frames = [
+            ack,
+            envelope.clone(),
+            json!({"type": "pong", "thread_id": "thread-1", "timestamp": 2}),
+            // Same nonce again: a replay.
+            envelope,
+            json!({
+                "type": "state_update",
+                "thread_id": "thread-1",
+                "timestamp": 3,
+                "payload": {"kind": "focus", "data": {}},
+                "nonce": "n-2",
+                "prev_message_hash": "not-the-previous-hash"
+            }),
+            json!({
+                "type": "error",
+                "timestamp": 4,
+                "payload": {"error_code": "INVALID_STATE_UPDATE", "error_message": "bad kind"}
+            }),
+        ];
+        for frame in frames {
+            ws.send(Message::Text(frame.to_string())).await.unwrap();
+        }
+        ws.close(None).await.unwrap();
+    });
+
+    let mut client = LtpClient::new(format!("ws://{}", addr), "client-1");
+    let mut events = client.events().expect("events are available once");
+    assert!(client.events().is_none());
+    let _client = client.connect().await.unwrap();
+
+    match events.next().await.unwrap() {
+        LtpEvent::Envelope(envelope) => {
+            assert_eq!(envelope.payload.kind, "focus");
+            assert_eq!(envelope.payload.data["level"], 3);
+        }
+        other => panic!("expected envelope, got {:?}", other),
+    }
+    assert!(matches!(events.next().await, Some(LtpEvent::Pong(_))));
+    assert!(matches!(
+        events.next().await,
+        Some(LtpEvent::Error(LtpError::Integrity(IntegrityError::ReplayedNonce(n)))) if n == "n-1"
+    ));
+    assert!(matches!(
+        events.next().await,
+        Some(LtpEvent::Error(LtpError::Integrity(
+            IntegrityError::HashChain { .. }
+        )))
+    ));
+    match events.next().await.unwrap() {
+        LtpEvent::ServerError(err) => assert_eq!(err.payload.error_code, "INVALID_STATE_UPDATE"),
+        other => panic!("expected server error, got {:?}", other),
+    }
+    assert!(matches!(events.next().await, Some(LtpEvent::Closed(_))));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn heartbeat_times_out_a_silent_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 50
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        // Answer two pings, then go quiet while still reading.
+        let mut answered = 0;
+        while let Some(Ok(Message::Text(text))) = ws.next().await {
+            let ping: serde_json::Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(ping["type"], "ping");
+            assert_eq!(ping["session_id"], "session-1");
+            assert_eq!(ping["meta"]["client_id"], "client-1");
+            if answered < 2 {
+                let pong = json!({"type": "pong", "thread_id": "thread-1", "timestamp": 0});
+                ws.send(Message::Text(pong.to_string())).await.unwrap();
+                answered += 1;
+            }
+        }
+    });
+
+    let mut client =
+        LtpClient::new(format!("ws://{}", addr), "client-1").with_heartbeat_timeout(200);
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+    assert!(client.is_connected());
+
+    assert!(matches!(events.next().await, Some(LtpEvent::Pong(_))));
+    assert!(matches!(events.next().await, Some(LtpEvent::Pong(_))));
+    assert!(client.heartbeat_rtt().is_some());
+    match events.next().await {
+        Some(LtpEvent::HeartbeatTimeout { silent_for }) => {
+            assert!(silent_for >= std::time::Duration::from_millis(200))
+        }
+        other => panic!("expected heartbeat timeout, got {:?}", other),
+    }
+    assert!(matches!(events.next().await, Some(LtpEvent::Closed(None))));
+    assert!(!client.is_connected());
+    assert!(matches!(
+        client.send_event("late", json!({})).await,
+        Err(LtpError::NotConnected)
+    ));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn heartbeat_timeout_counts_from_the_unanswered_ping() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        // The negotiated interval is longer than the client's timeout.
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 400
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let mut answered = 0;
+        while let Some(Ok(Message::Text(_))) = ws.next().await {
+            if answered < 2 {
+                let pong = json!({"type": "pong", "thread_id": "thread-1", "timestamp": 0});
+                ws.send(Message::Text(pong.to_string())).await.unwrap();
+                answered += 1;
+            }
+        }
+    });
+
+    let mut client =
+        LtpClient::new(format!("ws://{}", addr), "client-1").with_heartbeat_timeout(100);
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+
+    // Prompt pongs keep the connection up although every tick comes after the timeout.
+    assert!(matches!(events.next().await, Some(LtpEvent::Pong(_))));
+    assert!(matches!(events.next().await, Some(LtpEvent::Pong(_))));
+    assert!(client.is_connected());
+    // The third ping times out after the timeout, not at the next tick.
+    match events.next().await {
+        Some(LtpEvent::HeartbeatTimeout { silent_for }) => {
+            assert!(silent_for >= std::time::Duration::from_millis(100));
+            assert!(silent_for < std::time::Duration::from_millis(400));
+        }
+        other => panic!("expected heartbeat timeout, got {:?}", other),
+    }
+    assert!(matches!(events.next().await, Some(LtpEvent::Closed(None))));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn reconnects_and_falls_back_to_init_when_resume_is_rejected() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        // First connection: accept the init, then drop the client.
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let init = ws.next().await.unwrap().unwrap();
+        let init: serde_json::Value = serde_json::from_str(init.to_text().unwrap()).unwrap();
+        assert_eq!(init["type"], "handshake_init");
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 0
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+        ws.close(None).await.unwrap();
+
+        // Second connection: the server "restarted" and forgot the thread.
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let resume = ws.next().await.unwrap().unwrap();
+        let resume: serde_json::Value = serde_json::from_str(resume.to_text().unwrap()).unwrap();
+        assert_eq!(resume["type"], "handshake_resume");
+        assert_eq!(resume["thread_id"], "thread-1");
+        let reject = json!({
+            "type": "handshake_reject",
+            "ltp_version": "0.6",
+            "reason": "thread_not_found",
+            "suggest_new": true
+        });
+        ws.send(Message::Text(reject.to_string())).await.unwrap();
+        let init = ws.next().await.unwrap().unwrap();
+        let init: serde_json::Value = serde_json::from_str(init.to_text().unwrap()).unwrap();
+        assert_eq!(init["type"], "handshake_init");
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-2",
+            "session_id": "session-2",
+            "heartbeat_interval_ms": 0
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let event = ws.next().await.unwrap().unwrap();
+        let event: serde_json::Value = serde_json::from_str(event.to_text().unwrap()).unwrap();
+        assert_eq!(event["thread_id"], "thread-2");
+        assert_eq!(event["session_id"], "session-2");
+        assert!(event.get("prev_message_hash").is_none());
+    });
+
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        ..ReconnectPolicy::default()
+    };
+    let mut client = LtpClient::new(format!("ws://{}", addr), "client-1").with_reconnect(policy);
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+    assert_eq!(client.thread_id().as_deref(), Some("thread-1"));
+
+    assert!(matches!(events.next().await, Some(LtpEvent::Closed(_))));
+    assert!(matches!(
+        events.next().await,
+        Some(LtpEvent::StateChanged(ConnectionState::Reconnecting {
+            attempt: 1,
+            ..
+        }))
+    ));
+    match events.next().await {
+        Some(LtpEvent::StateChanged(ConnectionState::Connected {
+            thread_id, resumed, ..
+        })) => {
+            assert_eq!(thread_id, "thread-2");
+            assert!(!resumed);
+        }
+        other => panic!("expected reconnect, got {:?}", other),
+    }
+    assert!(client.is_connected());
+    assert_eq!(client.session_id().as_deref(), Some("session-2"));
+    client.send_event("after_restart", json!({})).await.unwrap();
+    server.await.unwrap();
+    client.disconnect().await.unwrap();
+    assert!(!client.is_connected());
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        // Nothing listens on the port once this connection is closed.
+        drop(listener);
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        ws.next().await.unwrap().unwrap();
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 0
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+        ws.close(None).await.unwrap();
+    });
+
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_retries: Some(1),
+        ..ReconnectPolicy::default()
+    };
+    let mut client = LtpClient::new(format!("ws://{}", addr), "client-1").with_reconnect(policy);
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+    server.await.unwrap();
+
+    assert!(matches!(events.next().await, Some(LtpEvent::Closed(_))));
+    assert!(matches!(
+        events.next().await,
+        Some(LtpEvent::StateChanged(ConnectionState::Reconnecting {
+            attempt: 1,
+            ..
+        }))
+    ));
+    assert!(matches!(events.next().await, Some(LtpEvent::Error(_))));
+    assert!(matches!(
+        events.next().await,
+        Some(LtpEvent::StateChanged(ConnectionState::Disconnected))
+    ));
+    // No further dial attempts, each of which would report an error.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), events.next())
+            .await
+            .is_err()
+    );
+    assert!(!client.is_connected());
+}
+
+#[tokio::test]
+async fn handshake_failures_are_reported_instead_of_hanging() {
+    use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        for answer in ["silence", "reject", "error", "close", "pong"] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let _init = ws.next().await.unwrap().unwrap();
+            let reply = match answer {
+                "silence" => {
+                    // Hold the socket open until the client gives up.
+                    while let Some(Ok(_)) = ws.next().await {}
+                    continue;
+                }
+                "close" => {
+                    let frame = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: "banned".into(),
+                    };
+                    let _ = ws.close(Some(frame)).await;
+                    continue;
+                }
+                "reject" => json!({
+                    "type": "handshake_reject",
+                    "ltp_version": "0.6",
+                    "reason": "unsupported_version"
+                }),
+                "error" => json!({
+                    "type": "error",
+                    "payload": {"error_code": "RATE_LIMITED", "error_message": "slow down"}
+                }),
+                _ => json!({"type": "pong", "thread_id": "thread-1", "timestamp": 0}),
+            };
+            ws.send(Message::Text(reply.to_string())).await.unwrap();
+        }
+    });
+
+    let connect = || {
+        LtpClient::new(format!("ws://{}", addr), "client-1")
+            .with_handshake_timeout(200)
+            .connect()
+    };
+    assert!(matches!(
+        connect().await,
+        Err(LtpError::HandshakeTimeout(timeout)) if timeout == Duration::from_millis(200)
+    ));
+    assert!(matches!(
+        connect().await,
+        Err(LtpError::HandshakeRejected { reason, suggest_new: false }) if reason == "unsupported_version"
+    ));
+    assert!(matches!(
+        connect().await,
+        Err(LtpError::Server(payload)) if payload.error_code == "RATE_LIMITED"
+    ));
+    assert!(matches!(
+        connect().await,
+        Err(LtpError::ConnectionClosed(Some(reason))) if reason.code == 1008 && reason.reason == "banned"
+    ));
+    assert!(matches!(
+        connect().await,
+        Err(LtpError::ProtocolViolation(_))
+    ));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn outbox_replays_messages_sent_while_disconnected() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (offline_tx, offline_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        let ack = |resumed| {
+            json!({
+                "type": "handshake_ack",
+                "ltp_version": "0.6",
+                "thread_id": "thread-1",
+                "session_id": if resumed { "session-2" } else { "session-1" },
+                "resumed": resumed,
+                "heartbeat_interval_ms": 0
+            })
+            .to_string()
+        };
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        ws.send(Message::Text(ack(false))).await.unwrap();
+        ws.close(None).await.unwrap();
+        drop(ws);
+
+        // Stay down until the client has queued its messages.
+        offline_rx.await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let resume = ws.next().await.unwrap().unwrap();
+        let resume: serde_json::Value = serde_json::from_str(resume.to_text().unwrap()).unwrap();
+        assert_eq!(resume["type"], "handshake_resume");
+        ws.send(Message::Text(ack(true))).await.unwrap();
+
+        let mut previous_hash = None;
+        for kind in ["first", "second", "third"] {
+            let frame = ws.next().await.unwrap().unwrap();
+            let envelope: serde_json::Value =
+                serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            assert_eq!(envelope["payload"]["kind"], kind);
+            assert_eq!(envelope["session_id"], "session-2");
+            assert_eq!(
+                envelope.get("prev_message_hash").and_then(|h| h.as_str()),
+                previous_hash.as_deref()
+            );
+            previous_hash = Some(ltp_client::crypto::hash_envelope(&envelope).unwrap());
+        }
+    });
+
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        jitter: 0.0,
+        ..ReconnectPolicy::default()
+    };
+    let mut client = LtpClient::new(format!("ws://{}", addr), "client-1")
+        .with_reconnect(policy)
+        .with_outbox(Outbox::memory(OutboxConfig::default()));
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+
+    assert!(matches!(events.next().await, Some(LtpEvent::Closed(_))));
+    assert!(!client.is_connected());
+    client.send_event("first", json!({})).await.unwrap();
+    client.send_event("second", json!({})).await.unwrap();
+    assert_eq!(client.outbox_len(), Some(2));
+    offline_tx.send(()).unwrap();
+
+    loop {
+        match events.next().await {
+            Some(LtpEvent::StateChanged(ConnectionState::Connected { resumed, .. })) => {
+                assert!(resumed);
+                break;
+            }
+            Some(_) => {}
+            None => panic!("event stream ended"),
+        }
+    }
+    client.send_event("third", json!({})).await.unwrap();
+    server.await.unwrap();
+    assert_eq!(client.outbox_len(), Some(0));
+}
+
+#[tokio::test]
+async fn cloned_handles_share_one_hash_chain() {
+    const PRODUCERS: usize = 4;
+    const PER_PRODUCER: usize = 25;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 0
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let mut previous_hash = None;
+        for _ in 0..PRODUCERS * PER_PRODUCER {
+            let frame = ws.next().await.unwrap().unwrap();
+            let envelope: serde_json::Value =
+                serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            assert_eq!(
+                envelope.get("prev_message_hash").and_then(|h| h.as_str()),
+                previous_hash.as_deref()
+            );
+            previous_hash = Some(ltp_client::crypto::hash_envelope(&envelope).unwrap());
+        }
+    });
+
+    let client = LtpClient::new(format!("ws://{}", addr), "client-1")
+        .connect()
+        .await
+        .unwrap();
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|producer| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                for i in 0..PER_PRODUCER {
+                    client
+                        .send_state_update("sample", json!({"producer": producer, "i": i}))
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.await.unwrap();
+    }
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn node_client_speaks_the_node_dialect() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let hello = ws.next().await.unwrap().unwrap();
+        let hello: serde_json::Value = serde_json::from_str(hello.to_text().unwrap()).unwrap();
+        assert_eq!(
+            hello,
+            json!({"type": "hello", "api_key": "key-1", "client_label": "tests"})
+        );
+        let ack = json!({"type": "hello_ack", "node_id": "node-1", "accepted": true, "session_id": "s-1"});
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let mut routes = 0;
+        while let Some(Ok(Message::Text(text))) = ws.next().await {
+            let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(message["session_id"], "s-1");
+            let reply = match message["type"].as_str().unwrap() {
+                "heartbeat" => json!({
+                    "type": "heartbeat_ack",
+                    "session_id": "s-1",
+                    "timestamp_ms": message["timestamp_ms"]
+                }),
+                "orientation" => {
+                    assert_eq!(message["focus_momentum"], 0.8);
+                    assert_eq!(message["time_orientation"]["direction"], "present");
+                    continue;
+                }
+                "route_request" if routes == 0 => {
+                    routes += 1;
+                    assert_eq!(message["hint_sector"], "present_focus");
+                    json!({
+                        "type": "route_suggestion",
+                        "session_id": "s-1",
+                        "suggested_sector": "present_focus",
+                        "reason": "client is present-oriented"
+                    })
+                }
+                // Errors only answer a request when they carry its id.
+                "route_request" => json!({
+                    "type": "error",
+                    "correlation_id": message["correlation_id"],
+                    "code": "RATE_LIMIT",
+                    "message": "key rate limit",
+                    "retry_after_ms": 250
+                }),
+                other => panic!("unexpected {}", other),
+            };
+            ws.send(Message::Text(reply.to_string())).await.unwrap();
+        }
+    });
+
+    let node = NodeClient::new(format!("ws://{}", addr))
+        .with_client_label("tests")
+        .with_heartbeat_interval(20)
+        .hello("key-1")
+        .await
+        .unwrap();
+    assert_eq!(node.node_id(), "node-1");
+    assert_eq!(node.session_id(), "s-1");
+
+    node.send_orientation(
+        Some(0.8),
+        Some(TimeOrientationBoostPayload {
+            direction: TimeOrientationDirectionPayload::Present,
+            strength: 0.5,
+        }),
+    )
+    .await
+    .unwrap();
+    let route = node
+        .request_route(Some(Sector::PresentFocus))
+        .await
+        .unwrap();
+    assert_eq!(route.suggested_sector, Sector::PresentFocus);
+    match node.request_route(None).await {
+        Err(LtpError::Node {
+            code: ErrorCode::RateLimit,
+            retry_after,
+            ..
+        }) => assert_eq!(retry_after, Some(Duration::from_millis(250))),
+        other => panic!("expected rate limit, got {:?}", other),
+    }
+
+    while node.heartbeat_rtt().is_none() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    node.close().await.unwrap();
+    assert!(!node.is_connected());
+    assert!(matches!(
+        node.request_route(None).await,
+        Err(LtpError::NotConnected)
+    ));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn node_client_reports_an_unauthorized_key() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _hello = ws.next().await.unwrap().unwrap();
+        let error = json!({"type": "error", "code": "UNAUTHORIZED", "message": "unauthorized"});
+        ws.send(Message::Text(error.to_string())).await.unwrap();
+        let _ = ws.close(None).await;
+    });
+
+    match NodeClient::new(format!("ws://{}", addr))
+        .hello("bad-key")
+        .await
+    {
+        Err(LtpError::Node { code, message, .. }) => {
+            assert_eq!(code, ErrorCode::Unauthorized);
+            assert_eq!(message.as_deref(), Some("unauthorized"));
+        }
+        other => panic!("expected unauthorized, got {:?}", other.map(|_| ())),
+    }
+    server.await.unwrap();
+}
+
+async fn next_json(
+    ws: &mut tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
+) -> serde_json::Value {
+    let text = ws.next().await.unwrap().unwrap().into_text().unwrap();
+    serde_json::from_str(&text).unwrap()
+}
+
+#[tokio::test]
+async fn requests_resolve_with_the_reply_carrying_their_correlation_id() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 0
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let reply = |request: &serde_json::Value| {
+            json!({
+                "type": "query_result",
+                "thread_id": "thread-1",
+                "timestamp": 1,
+                "payload": {"kind": request["payload"]["kind"], "data": {}},
+                "correlation_id": request["correlation_id"]
+            })
+            .to_string()
+        };
+
+        // Answer two concurrent requests in reverse order.
+        let (a, b) = (next_json(&mut ws).await, next_json(&mut ws).await);
+        assert_ne!(a["correlation_id"], b["correlation_id"]);
+        ws.send(Message::Text(reply(&b))).await.unwrap();
+        ws.send(Message::Text(reply(&a))).await.unwrap();
+
+        let failing = next_json(&mut ws).await;
+        let error = json!({
+            "type": "error",
+            "timestamp": 2,
+            "payload": {"error_code": "UNKNOWN_QUERY", "error_message": "no such query"},
+            "correlation_id": failing["correlation_id"]
+        });
+        ws.send(Message::Text(error.to_string())).await.unwrap();
+
+        // Answer the slow request only after its caller gave up.
+        let slow = next_json(&mut ws).await;
+        let done = next_json(&mut ws).await;
+        assert_eq!(done["type"], "event");
+        ws.send(Message::Text(reply(&slow))).await.unwrap();
+
+        let _unanswered = next_json(&mut ws).await;
+        ws.close(None).await.unwrap();
+    });
+
+    let mut client = LtpClient::new(format!("ws://{}", addr), "client-1");
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+
+    let (a, b) = tokio::join!(
+        client.request("query", "a", json!({})),
+        client.request("query", "b", json!({}))
+    );
+    assert_eq!(a.unwrap().payload.kind, "a");
+    assert_eq!(b.unwrap().payload.kind, "b");
+
+    match client.request("query", "missing", json!({})).await {
+        Err(LtpError::Server(payload)) => assert_eq!(payload.error_code, "UNKNOWN_QUERY"),
+        other => panic!("expected server error, got {:?}", other),
+    }
+
+    let timeout = Duration::from_millis(50);
+    assert!(matches!(
+        client
+            .request_with_timeout("query", "slow", json!({}), timeout)
+            .await,
+        Err(LtpError::RequestTimeout(t)) if t == timeout
+    ));
+    client.send_event("done", json!({})).await.unwrap();
+    match events.next().await.unwrap() {
+        LtpEvent::Envelope(late) => {
+            assert_eq!(late.payload.kind, "slow");
+            assert!(late.correlation_id.is_some());
+        }
+        other => panic!("expected the late reply, got {:?}", other),
+    }
+
+    assert!(matches!(
+        client.request("query", "unanswered", json!({})).await,
+        Err(LtpError::ConnectionClosed(_))
+    ));
+    assert!(matches!(
+        client.request("query", "offline", json!({})).await,
+        Err(LtpError::NotConnected)
+    ));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn node_client_matches_replies_by_correlation_id() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _hello = ws.next().await.unwrap().unwrap();
+        let ack = json!({"type": "hello_ack", "node_id": "node-1", "accepted": true, "session_id": "s-1"});
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let message = next_json(&mut ws).await;
+            assert!(message["correlation_id"].is_string());
+            received.push(message);
+        }
+        // An error without an id answers no request.
+        let limited = json!({
+            "type": "error",
+            "code": "RATE_LIMIT",
+            "message": "too many messages"
+        });
+        ws.send(Message::Text(limited.to_string())).await.unwrap();
+        // Reject the orientation update, then answer the routes in reverse order.
+        received.sort_by_key(|m| m["type"] != "orientation");
+        let error = json!({
+            "type": "error",
+            "correlation_id": received[0]["correlation_id"],
+            "code": "FORBIDDEN",
+            "message": "orientation not allowed"
+        });
+        ws.send(Message::Text(error.to_string())).await.unwrap();
+        // The node echoes ids, so a suggestion without one is unsolicited.
+        let unsolicited = json!({
+            "type": "route_suggestion",
+            "session_id": "s-1",
+            "suggested_sector": "multi_bridge"
+        });
+        ws.send(Message::Text(unsolicited.to_string()))
+            .await
+            .unwrap();
+        for route in received[1..].iter().rev() {
+            let suggestion = json!({
+                "type": "route_suggestion",
+                "correlation_id": route["correlation_id"],
+                "session_id": "s-1",
+                "suggested_sector": route["hint_sector"]
+            });
+            ws.send(Message::Text(suggestion.to_string()))
+                .await
+                .unwrap();
+        }
+        while ws.next().await.is_some() {}
+    });
+
+    let mut client = NodeClient::new(format!("ws://{}", addr)).with_heartbeat_interval(0);
+    let mut events = client.events().unwrap();
+    let node = client.hello("key-1").await.unwrap();
+
+    let (orientation, past, future) = tokio::join!(
+        node.send_orientation(Some(0.2), None),
+        node.request_route(Some(Sector::RetrospectiveSafe)),
+        node.request_route(Some(Sector::FuturePlanning))
+    );
+    orientation.unwrap();
+    assert_eq!(past.unwrap().suggested_sector, Sector::RetrospectiveSafe);
+    assert_eq!(future.unwrap().suggested_sector, Sector::FuturePlanning);
+    match events.next().await.unwrap() {
+        NodeEvent::Error(LtpError::Node { code, .. }) => assert_eq!(code, ErrorCode::RateLimit),
+        other => panic!("expected the rate limit error, got {:?}", other),
+    }
+    match events.next().await.unwrap() {
+        NodeEvent::Error(LtpError::Node { code, .. }) => assert_eq!(code, ErrorCode::Forbidden),
+        other => panic!("expected the orientation error, got {:?}", other),
+    }
+    match events.next().await.unwrap() {
+        NodeEvent::RouteSuggestion(suggestion) => {
+            assert_eq!(suggestion.suggested_sector, Sector::MultiBridge)
+        }
+        other => panic!("expected the unsolicited suggestion, got {:?}", other),
+    }
+    node.close().await.unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn state_updates_are_sent_as_toon_when_tabular() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _init = ws.next().await.unwrap().unwrap();
+        let ack = json!({
+            "type": "handshake_ack",
+            "ltp_version": "0.6",
+            "thread_id": "thread-1",
+            "session_id": "session-1",
+            "heartbeat_interval_ms": 0
+        });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+
+        let table = next_json(&mut ws).await;
+        assert_eq!(table["content_encoding"], "toon");
+        assert_eq!(
+            table["payload"]["data"],
+            "affect_log[2]{arousal,t,valence}:\n  -0.1,1,0.2\n  -0.2,2,0.3\n"
+        );
+        let nested = next_json(&mut ws).await;
+        assert_eq!(nested["content_encoding"], "json");
+        assert_eq!(nested["payload"]["data"]["focus"]["level"], 3);
+        let event = next_json(&mut ws).await;
+        assert_eq!(event["content_encoding"], "json");
+        assert!(event["payload"]["data"].is_array());
+
+        // Echo the table back.
+        ws.send(Message::Text(table.to_string())).await.unwrap();
+        while ws.next().await.is_some() {}
+    });
+
+    let mut client = LtpClient::new(format!("ws://{}", addr), "client-1")
+        .with_content_encoding(ContentEncoding::Toon);
+    let mut events = client.events().unwrap();
+    let client = client.connect().await.unwrap();
+
+    let affect_log = json!([
+        {"t": 1, "valence": 0.2, "arousal": -0.1},
+        {"t": 2, "valence": 0.3, "arousal": -0.2}
+    ]);
+    client
+        .send_state_update("affect_log", &affect_log)
+        .await
+        .unwrap();
+    client
+        .send_state_update("snapshot", json!({"focus": {"level": 3}}))
+        .await
+        .unwrap();
+    client.send_event("batch", &affect_log).await.unwrap();
+
+    match events.next().await.unwrap() {
+        LtpEvent::Envelope(envelope) => {
+            assert!(matches!(envelope.content_encoding, ContentEncoding::Toon));
+            assert_eq!(toon::decode_payload(&envelope).unwrap(), affect_log);
+        }
+        other => panic!("expected the echoed table, got {:?}", other),
+    }
+    client.disconnect().await.unwrap();
+    server.await.unwrap();
+}
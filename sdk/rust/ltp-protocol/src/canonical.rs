@@ -0,0 +1,50 @@
+//! Canonical JSON: recursively sorted keys, no whitespace. Hashes and
+//! signatures are computed over these bytes so every implementation agrees.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Recursively sort object keys so equal frames always serialize identically.
+pub fn canonicalize_json(v: &Value) -> Value {
+    match v {
+        Value::Object(map) => {
+            let mut sorted = BTreeMap::new();
+            for (k, val) in map.iter() {
+                sorted.insert(k.clone(), canonicalize_json(val));
+            }
+            let mut new_map = serde_json::Map::new();
+            for (k, val) in sorted {
+                new_map.insert(k, val);
+            }
+            Value::Object(new_map)
+        }
+        Value::Array(arr) => Value::Array(arr.iter().map(canonicalize_json).collect()),
+        _ => v.clone(),
+    }
+}
+
+/// Canonical bytes of a frame: sorted keys, no whitespace.
+pub fn canonical_json_bytes(frame: &Value) -> serde_json::Result<Vec<u8>> {
+    let canon = canonicalize_json(frame);
+    serde_json::to_vec(&canon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn key_order_does_not_change_bytes() {
+        let a = json!({"b": 2, "a": {"d": [1, {"z": 0, "y": 1}], "c": null}});
+        let b = json!({"a": {"c": null, "d": [1, {"y": 1, "z": 0}]}, "b": 2});
+        assert_eq!(
+            canonical_json_bytes(&a).unwrap(),
+            canonical_json_bytes(&b).unwrap()
+        );
+        assert_eq!(
+            String::from_utf8(canonical_json_bytes(&a).unwrap()).unwrap(),
+            r#"{"a":{"c":null,"d":[1,{"y":1,"z":0}]},"b":2}"#
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! LTP frames v0.1 (`specs/LTP-Frames-v0.1.md`).
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Value of `v` in every v0.1 frame.
+pub const FRAME_VERSION: &str = "0.1";
+
+/// One frame. `payload` is frame-type specific and kept as raw JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub v: String,
+    pub id: String,
+    /// Unix timestamp in milliseconds.
+    pub ts: i64,
+    #[serde(rename = "type")]
+    pub frame_type: FrameType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    pub payload: serde_json::Value,
+}
+
+/// Frame discriminator. Frame types are additive, so unknown ones are kept
+/// as `Other` for the receiver to ignore rather than rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum FrameType {
+    Hello,
+    Heartbeat,
+    Orientation,
+    RouteRequest,
+    RouteResponse,
+    FocusSnapshot,
+    Other(String),
+}
+
+impl FrameType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FrameType::Hello => "hello",
+            FrameType::Heartbeat => "heartbeat",
+            FrameType::Orientation => "orientation",
+            FrameType::RouteRequest => "route_request",
+            FrameType::RouteResponse => "route_response",
+            FrameType::FocusSnapshot => "focus_snapshot",
+            FrameType::Other(other) => other,
+        }
+    }
+}
+
+impl From<String> for FrameType {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "hello" => FrameType::Hello,
+            "heartbeat" => FrameType::Heartbeat,
+            "orientation" => FrameType::Orientation,
+            "route_request" => FrameType::RouteRequest,
+            "route_response" => FrameType::RouteResponse,
+            "focus_snapshot" => FrameType::FocusSnapshot,
+            _ => FrameType::Other(value),
+        }
+    }
+}
+
+impl From<FrameType> for String {
+    fn from(value: FrameType) -> Self {
+        match value {
+            FrameType::Other(other) => other,
+            known => known.as_str().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FrameType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonical::canonical_json_bytes;
+    use serde_json::Value;
+
+    /// Parse `raw` as a frame and check it serializes back to the same
+    /// canonical bytes.
+    fn round_trip(raw: &Value) -> Frame {
+        let frame: Frame = serde_json::from_value(raw.clone()).unwrap();
+        let back = serde_json::to_value(&frame).unwrap();
+        assert_eq!(
+            canonical_json_bytes(&back).unwrap(),
+            canonical_json_bytes(raw).unwrap(),
+            "frame {} changed in a round trip",
+            frame.id
+        );
+        frame
+    }
+
+    #[test]
+    fn self_test_vector_round_trips() {
+        let vector: Value = serde_json::from_str(include_str!(
+            "../../../../specs/vectors/self-test-canonical.v0.1.json"
+        ))
+        .unwrap();
+        let frames: Vec<Frame> = vector["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(round_trip)
+            .collect();
+
+        assert!(frames.iter().all(|f| f.v == FRAME_VERSION));
+        let unknown = frames.iter().find(|f| f.id == "f-unknown").unwrap();
+        assert_eq!(
+            unknown.frame_type,
+            FrameType::Other("unknown_frame".to_string())
+        );
+    }
+
+    #[test]
+    fn golden_transcript_round_trips() {
+        let transcript = include_str!("../../../../specs/flow/golden-transcript.v0.1.jsonl");
+        let types: Vec<FrameType> = transcript
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| round_trip(&serde_json::from_str(line).unwrap()).frame_type)
+            .collect();
+
+        assert_eq!(
+            types,
+            [
+                FrameType::Hello,
+                FrameType::Hello,
+                FrameType::Heartbeat,
+                FrameType::Heartbeat,
+                FrameType::Heartbeat,
+                FrameType::Orientation,
+                FrameType::RouteRequest,
+                FrameType::RouteResponse,
+                FrameType::FocusSnapshot,
+            ]
+        );
+    }
+}
@@ -0,0 +1,15 @@
+//! LTP wire types shared by `ltp-rust-node` and the Rust SDK.
+//!
+//! - `frame`: the v0.1 frame contract of `specs/LTP-Frames-v0.1.md`
+//! - `envelope`: envelopes and handshake messages of the thread protocol
+//! - `node`: the `hello`/`route_request` dialect of `ltp-rust-node`
+//! - `canonical`: the JSON encoding hashes and signatures are computed over
+
+pub mod canonical;
+pub mod envelope;
+pub mod frame;
+pub mod node;
+
+pub use canonical::{canonical_json_bytes, canonicalize_json};
+pub use frame::{Frame, FrameType, FRAME_VERSION};
+pub use node::{ErrorCode, Sector};
@@ -0,0 +1,345 @@
+//! Wire types of the `ltp-rust-node` dialect, named from the node's side:
+//! `LtpIncomingMessage` is what clients send.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LtpIncomingMessage {
+    Hello {
+        api_key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_label: Option<String>,
+    },
+    Heartbeat {
+        session_id: String,
+        timestamp_ms: i64,
+    },
+    Orientation {
+        session_id: String,
+        focus_momentum: Option<f64>,
+        #[serde(default)]
+        time_orientation: Option<TimeOrientationBoostPayload>,
+    },
+    RouteRequest {
+        session_id: String,
+        #[serde(default)]
+        hint_sector: Option<Sector>,
+    },
+}
+
+/// A message with the `correlation_id` a client may attach to a request.
+///
+/// The id sits next to `type` on the wire. The node copies it onto every
+/// reply to that message, so clients can match replies without relying on
+/// their order; messages without one get replies without one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Correlated<M> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    #[serde(flatten)]
+    pub message: M,
+}
+
+impl<M> Correlated<M> {
+    pub fn new(correlation_id: Option<String>, message: M) -> Self {
+        Self {
+            correlation_id,
+            message,
+        }
+    }
+}
+
+/// `type` tags of `LtpIncomingMessage`.
+pub const INCOMING_TYPES: &[&str] = &["hello", "heartbeat", "orientation", "route_request"];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TimeOrientationBoostPayload {
+    pub direction: TimeOrientationDirectionPayload,
+    pub strength: f64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeOrientationDirectionPayload {
+    Past,
+    Present,
+    Future,
+    Multi,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LtpOutgoingMessage {
+    HelloAck {
+        node_id: String,
+        accepted: bool,
+        session_id: String,
+    },
+    HeartbeatAck {
+        session_id: String,
+        timestamp_ms: i64,
+    },
+    RouteSuggestion(RouteSuggestion),
+    Error {
+        code: ErrorCode,
+        #[serde(default)]
+        message: Option<String>,
+        /// For `RATE_LIMIT`: how long the client should wait before retrying.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_after_ms: Option<u64>,
+    },
+}
+
+/// The node's answer to a `route_request`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RouteSuggestion {
+    pub session_id: String,
+    pub suggested_sector: Sector,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub debug: Option<RouteDebugInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RouteDebugInfo {
+    #[serde(default)]
+    pub focus_momentum: Option<f64>,
+    #[serde(default)]
+    pub time_orientation: Option<TimeOrientationBoostPayload>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Unknown or expired API key; the node closes the connection.
+    Unauthorized,
+    /// Message not allowed for this key or session; the node closes the
+    /// connection.
+    Forbidden,
+    /// Too many messages or sessions. Closes the connection unless it came
+    /// from a per-key limit, in which case `retry_after_ms` says when to try
+    /// again.
+    RateLimit,
+    /// The message could not be parsed.
+    Invalid,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::RateLimit => "RATE_LIMIT",
+            ErrorCode::Invalid => "INVALID",
+        })
+    }
+}
+
+/// Routing sector, on the wire as its `Display` form (`"present_focus"`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sector {
+    BaseNeutral,
+    RetrospectiveSafe,
+    PresentFocus,
+    FuturePlanning,
+    MultiBridge,
+    Custom(String),
+}
+
+impl Sector {
+    pub fn base_neutral() -> Self {
+        Self::BaseNeutral
+    }
+
+    pub fn with_momentum(self, _momentum: Option<f64>) -> Self {
+        // Simple implementation for now, just returning self as momentum logic isn't fully defined
+        self
+    }
+}
+
+impl fmt::Display for Sector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sector::BaseNeutral => write!(f, "base_neutral"),
+            Sector::RetrospectiveSafe => write!(f, "retrospective_safe"),
+            Sector::PresentFocus => write!(f, "present_focus"),
+            Sector::FuturePlanning => write!(f, "future_planning"),
+            Sector::MultiBridge => write!(f, "multi_bridge"),
+            Sector::Custom(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Inverse of `Display`; anything unknown is `Custom`.
+impl FromStr for Sector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "base_neutral" => Sector::BaseNeutral,
+            "retrospective_safe" => Sector::RetrospectiveSafe,
+            "present_focus" => Sector::PresentFocus,
+            "future_planning" => Sector::FuturePlanning,
+            "multi_bridge" => Sector::MultiBridge,
+            other => Sector::Custom(other.to_string()),
+        })
+    }
+}
+
+impl Serialize for Sector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Also accepts the derived serde forms (`"PresentFocus"`,
+/// `{"Custom": ".."}`) that `hint_sector` used before it shared the
+/// `Display` form with `suggested_sector`.
+impl<'de> Deserialize<'de> for Sector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Name(String),
+            Custom {
+                #[serde(rename = "Custom")]
+                custom: String,
+            },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Name(name) => match name.as_str() {
+                "BaseNeutral" => Sector::BaseNeutral,
+                "RetrospectiveSafe" => Sector::RetrospectiveSafe,
+                "PresentFocus" => Sector::PresentFocus,
+                "FuturePlanning" => Sector::FuturePlanning,
+                "MultiBridge" => Sector::MultiBridge,
+                _ => match name.parse() {
+                    Ok(sector) => sector,
+                    Err(never) => match never {},
+                },
+            },
+            Repr::Custom { custom } => Sector::Custom(custom),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn messages_match_the_node_wire_format() {
+        let request = LtpIncomingMessage::RouteRequest {
+            session_id: "s-1".to_string(),
+            hint_sector: Some(Sector::PresentFocus),
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({"type": "route_request", "session_id": "s-1", "hint_sector": "present_focus"})
+        );
+
+        let error: LtpOutgoingMessage = serde_json::from_value(json!({
+            "type": "error",
+            "code": "RATE_LIMIT",
+            "message": "slow down",
+            "retry_after_ms": 250
+        }))
+        .unwrap();
+        assert_eq!(
+            error,
+            LtpOutgoingMessage::Error {
+                code: ErrorCode::RateLimit,
+                message: Some("slow down".to_string()),
+                retry_after_ms: Some(250),
+            }
+        );
+
+        let suggestion = json!({
+            "type": "route_suggestion",
+            "session_id": "s-1",
+            "suggested_sector": "future_planning",
+            "reason": "client oriented to future"
+        });
+        let parsed: LtpOutgoingMessage = serde_json::from_value(suggestion.clone()).unwrap();
+        match &parsed {
+            LtpOutgoingMessage::RouteSuggestion(s) => {
+                assert_eq!(s.suggested_sector, Sector::FuturePlanning)
+            }
+            other => panic!("expected route_suggestion, got {:?}", other),
+        }
+        let mut back = serde_json::to_value(&parsed).unwrap();
+        back.as_object_mut().unwrap().remove("debug");
+        assert_eq!(back, suggestion);
+    }
+
+    #[test]
+    fn correlation_id_sits_next_to_the_type() {
+        let request = Correlated::new(
+            Some("r-1".to_string()),
+            LtpIncomingMessage::RouteRequest {
+                session_id: "s-1".to_string(),
+                hint_sector: None,
+            },
+        );
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            json,
+            json!({"type": "route_request", "correlation_id": "r-1", "session_id": "s-1", "hint_sector": null})
+        );
+        assert_eq!(
+            serde_json::from_value::<Correlated<LtpIncomingMessage>>(json).unwrap(),
+            request
+        );
+
+        // Untagged messages still parse, and both forms parse as the bare message.
+        let bare = json!({"type": "heartbeat_ack", "session_id": "s-1", "timestamp_ms": 5});
+        let reply: Correlated<LtpOutgoingMessage> = serde_json::from_value(bare.clone()).unwrap();
+        assert_eq!(reply.correlation_id, None);
+        assert_eq!(serde_json::to_value(&reply).unwrap(), bare);
+        let mut tagged = bare;
+        tagged["correlation_id"] = json!("r-2");
+        assert!(serde_json::from_value::<LtpOutgoingMessage>(tagged).is_ok());
+    }
+
+    #[test]
+    fn sector_round_trips_and_accepts_the_derived_form() {
+        for sector in [
+            Sector::BaseNeutral,
+            Sector::RetrospectiveSafe,
+            Sector::PresentFocus,
+            Sector::FuturePlanning,
+            Sector::MultiBridge,
+            Sector::Custom("night_watch".to_string()),
+        ] {
+            assert_eq!(sector.to_string().parse::<Sector>().unwrap(), sector);
+            let json = serde_json::to_value(&sector).unwrap();
+            assert_eq!(json, json!(sector.to_string()));
+            assert_eq!(serde_json::from_value::<Sector>(json).unwrap(), sector);
+        }
+
+        let legacy: LtpIncomingMessage = serde_json::from_value(json!({
+            "type": "route_request",
+            "session_id": "s-1",
+            "hint_sector": "RetrospectiveSafe"
+        }))
+        .unwrap();
+        assert_eq!(
+            legacy,
+            LtpIncomingMessage::RouteRequest {
+                session_id: "s-1".to_string(),
+                hint_sector: Some(Sector::RetrospectiveSafe),
+            }
+        );
+        assert_eq!(
+            serde_json::from_value::<Sector>(json!({"Custom": "night_watch"})).unwrap(),
+            Sector::Custom("night_watch".to_string())
+        );
+    }
+}
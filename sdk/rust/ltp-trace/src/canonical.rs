@@ -0,0 +1,3 @@
+//! Canonical JSON, shared with the node and the SDK through `ltp-protocol`.
+
+pub use ltp_protocol::canonical::{canonical_json_bytes, canonicalize_json};
@@ -0,0 +1,531 @@
+//! Fintech compliance report (`schemas/ltp-fintech-compliance-report.v0.1.json`).
+//!
+//! One pass over a trace checks the hash chain, tests every signature against
+//! a keyring, and checks that each session was opened by a `session_open`
+//! record or an accepted `hello_ack` (i.e. passed authentication), on a node
+//! whose `identity_source` is not `off`, and that its `session_open` names a
+//! readable `auth_id` that the node knows. Sessions without a readable
+//! `auth_id` leave the binding `unknown`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::BufRead;
+use std::time::SystemTime;
+
+use ed25519_dalek::VerifyingKey;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::calendar::rfc3339_utc;
+use crate::entry::{TraceEntry, SIGNATURE_ALG_ED25519};
+use crate::reader::TraceReader;
+use crate::redact::REDACTED_PREFIX;
+use crate::session::SessionRecord;
+use crate::verify::{check_signature, TraceVerifier};
+
+pub const REPORT_VERSION: &str = "0.1";
+pub const SPEC_VERSION: &str = "0.1";
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ComplianceReport {
+    pub report_version: String,
+    pub spec_version: String,
+    pub node_version: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sdk_versions: Vec<String>,
+    pub generated_at: String,
+    pub trace_integrity: TraceIntegrity,
+    pub trace_signature: TraceSignature,
+    pub identity_binding: IdentityBinding,
+    pub replay_determinism: ReplayDeterminism,
+}
+
+impl ComplianceReport {
+    /// Intact chain, signed entries (verified or at least present) and every
+    /// session bound to an authenticated identity.
+    pub fn passed(&self) -> bool {
+        self.trace_integrity.status == IntegrityStatus::Verified
+            && matches!(
+                self.trace_signature.status,
+                SignatureStatus::Verified | SignatureStatus::Present
+            )
+            && self.identity_binding.status == BindingStatus::Verified
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrityStatus {
+    Verified,
+    Broken,
+    Unchecked,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TraceIntegrity {
+    pub status: IntegrityStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_root: Option<String>,
+    /// 0-based position of the first entry that failed verification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation_index: Option<u64>,
+    /// Human-readable reason for `broken`; not part of the schema's required set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureStatus {
+    Verified,
+    Present,
+    Missing,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TraceSignature {
+    pub status: SignatureStatus,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub key_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BindingStatus {
+    Verified,
+    Violated,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct IdentityBinding {
+    pub status: BindingStatus,
+    /// Set when every session is bound to the same identity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Sessions that failed the check, with the reason.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub violations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayStatus {
+    Verified,
+    Failed,
+    Unchecked,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ReplayDeterminism {
+    pub status: ReplayStatus,
+}
+
+/// Inputs for a compliance run beyond the trace itself.
+#[derive(Debug, Clone)]
+pub struct ComplianceCheck {
+    node_version: String,
+    keyring: BTreeMap<String, VerifyingKey>,
+    known_identities: Option<BTreeSet<String>>,
+}
+
+impl ComplianceCheck {
+    pub fn new(node_version: impl Into<String>) -> Self {
+        Self {
+            node_version: node_version.into(),
+            keyring: BTreeMap::new(),
+            known_identities: None,
+        }
+    }
+
+    /// Node verifying keys by key id; a trace is `verified` when one of them
+    /// verifies every entry.
+    pub fn with_keyring(mut self, keyring: BTreeMap<String, VerifyingKey>) -> Self {
+        self.keyring = keyring;
+        self
+    }
+
+    /// Identities (auth ids) the node accepts, e.g. the ids of `AUTH_KEYS_FILE`.
+    pub fn with_known_identities(mut self, identities: impl IntoIterator<Item = String>) -> Self {
+        self.known_identities = Some(identities.into_iter().collect());
+        self
+    }
+
+    pub fn run<R: BufRead>(&self, reader: TraceReader<R>) -> ComplianceReport {
+        let mut verifier = TraceVerifier::new();
+        let mut integrity_error = None;
+        let mut entries = 0u64;
+        let mut signed = 0u64;
+        let mut wrong_alg = false;
+        let mut candidate_keys: Vec<&String> = self.keyring.keys().collect();
+        let mut sessions: HashMap<String, SessionBinding> = HashMap::new();
+
+        for record in reader {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    integrity_error.get_or_insert((entries, err.to_string()));
+                    break;
+                }
+            };
+            let entry = &record.entry;
+            if integrity_error.is_none() {
+                if let Err(err) = verifier.verify_entry(record.line, entry) {
+                    integrity_error = Some((entries, err.to_string()));
+                }
+            }
+            entries += 1;
+
+            if entry.signature.is_some() {
+                signed += 1;
+                wrong_alg |= entry.alg.as_deref() != Some(SIGNATURE_ALG_ED25519);
+            }
+            candidate_keys
+                .retain(|id| check_signature(&self.keyring[*id], record.line, entry).is_ok());
+
+            if !entry.session_id.is_empty() {
+                sessions
+                    .entry(entry.session_id.clone())
+                    .or_insert_with(|| SessionBinding::opened_by(entry))
+                    .observe(entry);
+            }
+        }
+
+        let trace_integrity = match integrity_error {
+            Some((index, reason)) => TraceIntegrity {
+                status: IntegrityStatus::Broken,
+                hash_root: None,
+                violation_index: Some(index),
+                violation: Some(reason),
+            },
+            None if entries == 0 => TraceIntegrity {
+                status: IntegrityStatus::Unchecked,
+                hash_root: None,
+                violation_index: None,
+                violation: None,
+            },
+            None => TraceIntegrity {
+                status: IntegrityStatus::Verified,
+                hash_root: Some(verifier.report().last_hash),
+                violation_index: None,
+                violation: None,
+            },
+        };
+
+        let trace_signature = TraceSignature {
+            status: if signed == 0 {
+                SignatureStatus::Missing
+            } else if signed < entries || wrong_alg {
+                SignatureStatus::Invalid
+            } else if self.keyring.is_empty() {
+                SignatureStatus::Present
+            } else if candidate_keys.is_empty() {
+                SignatureStatus::Invalid
+            } else {
+                SignatureStatus::Verified
+            },
+            key_ids: if signed > 0 && signed == entries {
+                candidate_keys.into_iter().cloned().collect()
+            } else {
+                Vec::new()
+            },
+            algorithm: (signed > 0).then(|| SIGNATURE_ALG_ED25519.to_string()),
+        };
+
+        ComplianceReport {
+            report_version: REPORT_VERSION.to_string(),
+            spec_version: SPEC_VERSION.to_string(),
+            node_version: self.node_version.clone(),
+            sdk_versions: Vec::new(),
+            generated_at: rfc3339_utc(SystemTime::now()),
+            trace_integrity,
+            trace_signature,
+            identity_binding: self.bind_identities(sessions),
+            replay_determinism: ReplayDeterminism {
+                status: ReplayStatus::Unchecked,
+            },
+        }
+    }
+
+    fn bind_identities(&self, sessions: HashMap<String, SessionBinding>) -> IdentityBinding {
+        let mut violations = BTreeMap::new();
+        let mut identities = BTreeSet::new();
+        let mut unbound = false;
+        for (session_id, binding) in &sessions {
+            if !binding.authenticated {
+                violations.insert(
+                    session_id.clone(),
+                    "session does not start with session_open or an accepted hello_ack".to_string(),
+                );
+                continue;
+            }
+            if binding.identity_source.as_deref() == Some("off") {
+                violations.insert(
+                    session_id.clone(),
+                    "node does not authenticate clients (identity_source off)".to_string(),
+                );
+                continue;
+            }
+            match (&binding.identity, &self.known_identities) {
+                (Some(identity), Some(known)) if !known.contains(identity) => {
+                    violations.insert(
+                        session_id.clone(),
+                        format!("identity {} is not a known auth id", identity),
+                    );
+                }
+                (Some(identity), _) => {
+                    identities.insert(identity.clone());
+                }
+                // No auth_id, or only a hashed one: nothing to bind to.
+                (None, _) => unbound = true,
+            }
+        }
+
+        let status = if !violations.is_empty() {
+            BindingStatus::Violated
+        } else if sessions.is_empty() || unbound {
+            BindingStatus::Unknown
+        } else {
+            BindingStatus::Verified
+        };
+        let identity = match (identities.len(), status) {
+            (1, BindingStatus::Verified) => identities.into_iter().next(),
+            _ => None,
+        };
+        IdentityBinding {
+            status,
+            identity,
+            violations,
+        }
+    }
+}
+
+/// How one session was opened and which identity the trace names for it.
+struct SessionBinding {
+    authenticated: bool,
+    /// From the `session_open` record; traces opened by `hello_ack` have none.
+    identity_source: Option<String>,
+    identity: Option<String>,
+}
+
+impl SessionBinding {
+    fn opened_by(first: &TraceEntry) -> Self {
+        let identity_source = match SessionRecord::from_entry(first) {
+            Some(SessionRecord::SessionOpen(open)) => Some(open.identity_source),
+            _ => None,
+        };
+        let accepted = first.direction == "out"
+            && first.frame_type() == Some("hello_ack")
+            && first.frame.get("accepted").and_then(Value::as_bool) == Some(true);
+        Self {
+            authenticated: identity_source.is_some() || accepted,
+            identity_source,
+            identity: None,
+        }
+    }
+
+    // Only the node's session_open names the identity; an auth_id in a client
+    // frame is whatever the client claimed. A hashed auth_id says nothing
+    // about which key was used, so it is skipped.
+    fn observe(&mut self, entry: &TraceEntry) {
+        if self.identity.is_some() {
+            return;
+        }
+        if let Some(SessionRecord::SessionOpen(open)) = SessionRecord::from_entry(entry) {
+            self.identity = Some(open.auth_id).filter(|id| !id.starts_with(REDACTED_PREFIX));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::TraceLogger;
+    use ed25519_dalek::SigningKey;
+    use serde_json::json;
+    use tempfile::NamedTempFile;
+
+    async fn write_trace(signing_key: Option<SigningKey>, unbound: bool) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let logger = TraceLogger::open(file.path(), signing_key).await.unwrap();
+        let open = json!({
+            "type": "session_open",
+            "auth_id": "key-1",
+            "identity_source": "api_key",
+            "client_ip": "203.0.113.7",
+            "node_id": "node-a",
+        });
+        logger.log("node", "s1", &open).await.unwrap();
+        logger
+            .log(
+                "out",
+                "s1",
+                &json!({"type": "hello_ack", "accepted": true, "session_id": "s1"}),
+            )
+            .await
+            .unwrap();
+        logger
+            .log(
+                "in",
+                "s1",
+                &json!({"type": "heartbeat", "session_id": "s1"}),
+            )
+            .await
+            .unwrap();
+        if unbound {
+            logger
+                .log(
+                    "in",
+                    "s2",
+                    &json!({"type": "heartbeat", "session_id": "s2"}),
+                )
+                .await
+                .unwrap();
+        }
+        file
+    }
+
+    fn run(file: &NamedTempFile, check: ComplianceCheck) -> ComplianceReport {
+        check.run(TraceReader::open(file.path()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn signed_bound_trace_passes() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let file = write_trace(Some(key.clone()), false).await;
+        let keyring = BTreeMap::from([
+            (
+                "other".to_string(),
+                SigningKey::from_bytes(&[4u8; 32]).verifying_key(),
+            ),
+            ("node-1".to_string(), key.verifying_key()),
+        ]);
+
+        let report = run(
+            &file,
+            ComplianceCheck::new("ltp-rust-node@0.1.0").with_keyring(keyring),
+        );
+        assert!(report.passed());
+        assert_eq!(report.trace_integrity.status, IntegrityStatus::Verified);
+        assert_eq!(report.trace_signature.status, SignatureStatus::Verified);
+        assert_eq!(report.trace_signature.key_ids, vec!["node-1".to_string()]);
+        assert_eq!(report.identity_binding.status, BindingStatus::Verified);
+
+        // Required fields and enum values match the published schema.
+        let schema: Value = serde_json::from_str(include_str!(
+            "../../../../schemas/ltp-fintech-compliance-report.v0.1.json"
+        ))
+        .unwrap();
+        let value = serde_json::to_value(&report).unwrap();
+        for field in schema["required"].as_array().unwrap() {
+            assert!(
+                value.get(field.as_str().unwrap()).is_some(),
+                "missing {}",
+                field
+            );
+        }
+        for section in [
+            "trace_integrity",
+            "trace_signature",
+            "identity_binding",
+            "replay_determinism",
+        ] {
+            let allowed = &schema["properties"][section]["properties"]["status"]["enum"];
+            assert!(allowed
+                .as_array()
+                .unwrap()
+                .contains(&value[section]["status"]));
+        }
+        assert_eq!(
+            value["trace_integrity"]["hash_root"]
+                .as_str()
+                .unwrap()
+                .len(),
+            64
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_unbound_sessions_unsigned_and_broken_traces() {
+        let file = write_trace(None, true).await;
+        let report = run(&file, ComplianceCheck::new("n"));
+        assert!(!report.passed());
+        assert_eq!(report.trace_signature.status, SignatureStatus::Missing);
+        assert_eq!(report.identity_binding.status, BindingStatus::Violated);
+        assert!(report.identity_binding.violations.contains_key("s2"));
+
+        let content = std::fs::read_to_string(file.path()).unwrap();
+        std::fs::write(file.path(), content.replace("heartbeat", "hijacked")).unwrap();
+        let report = run(&file, ComplianceCheck::new("n"));
+        assert_eq!(report.trace_integrity.status, IntegrityStatus::Broken);
+        assert_eq!(report.trace_integrity.violation_index, Some(2));
+    }
+
+    #[tokio::test]
+    async fn binds_sessions_through_session_open() {
+        let file = NamedTempFile::new().unwrap();
+        let logger = TraceLogger::open(file.path(), None).await.unwrap();
+        for (session_id, auth_id) in [("s1", "key-1"), ("s2", "key-9")] {
+            let open = json!({
+                "type": "session_open",
+                "auth_id": auth_id,
+                "identity_source": "api_key",
+                "client_ip": "203.0.113.7",
+                "node_id": "node-a",
+            });
+            logger.log("node", session_id, &open).await.unwrap();
+            logger
+                .log("in", session_id, &json!({"type": "heartbeat"}))
+                .await
+                .unwrap();
+        }
+
+        let report = run(
+            &file,
+            ComplianceCheck::new("n").with_known_identities(["key-1".to_string()]),
+        );
+        assert_eq!(report.identity_binding.status, BindingStatus::Violated);
+        assert!(!report.identity_binding.violations.contains_key("s1"));
+        assert_eq!(
+            report.identity_binding.violations["s2"],
+            "identity key-9 is not a known auth id"
+        );
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_or_anonymous_sessions_are_not_verified() {
+        let file = NamedTempFile::new().unwrap();
+        let logger = TraceLogger::open(file.path(), None).await.unwrap();
+        // AUTH_MODE=none: the node records whatever the client claimed.
+        let open = json!({
+            "type": "session_open",
+            "auth_id": "key-1",
+            "identity_source": "off",
+            "client_ip": "203.0.113.7",
+            "node_id": "node-a",
+        });
+        logger.log("node", "s1", &open).await.unwrap();
+        let report = run(&file, ComplianceCheck::new("n"));
+        assert_eq!(report.identity_binding.status, BindingStatus::Violated);
+        assert_eq!(
+            report.identity_binding.violations["s1"],
+            "node does not authenticate clients (identity_source off)"
+        );
+        assert!(!report.passed());
+
+        // An accepted hello_ack without a session_open binds nothing, even
+        // when a client frame claims an auth_id.
+        let file = NamedTempFile::new().unwrap();
+        let logger = TraceLogger::open(file.path(), None).await.unwrap();
+        let ack = json!({"type": "hello_ack", "accepted": true, "session_id": "s1"});
+        logger.log("out", "s1", &ack).await.unwrap();
+        let claim = json!({"type": "heartbeat", "auth_id": "key-1"});
+        logger.log("in", "s1", &claim).await.unwrap();
+        let report = run(&file, ComplianceCheck::new("n"));
+        assert_eq!(report.identity_binding.status, BindingStatus::Unknown);
+        assert_eq!(report.identity_binding.identity, None);
+        assert!(!report.passed());
+    }
+}
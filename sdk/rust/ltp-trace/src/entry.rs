@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::canonical::canonical_json_bytes;
+
+/// `prev_hash` of the first entry in a trace.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Value of `alg` for entries signed with the node's ed25519 key.
+pub const SIGNATURE_ALG_ED25519: &str = "ed25519";
+
+/// One line of the JSONL trace log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub i: u64,
+    pub timestamp_ms: u64,
+    pub direction: String,
+    pub session_id: String,
+    pub frame: Value,
+    /// JSON pointer -> commitment for every value redacted out of `frame`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redactions: BTreeMap<String, String>,
+    pub prev_hash: String,
+    /// Position of this entry within its session's chain (0-based).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_seq: Option<u64>,
+    /// `hash` of the previous entry of the same session, or `GENESIS_HASH`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_prev_hash: Option<String>,
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+}
+
+impl TraceEntry {
+    /// Frame `type` tag, if the frame carries one.
+    pub fn frame_type(&self) -> Option<&str> {
+        self.frame.get("type").and_then(Value::as_str)
+    }
+
+    /// The `hash` this entry should carry, recomputed from its other fields.
+    pub fn chain_hash(&self) -> serde_json::Result<String> {
+        let session_link = match (self.session_seq, &self.session_prev_hash) {
+            (Some(seq), Some(prev)) => Some((seq, prev.as_str())),
+            _ => None,
+        };
+        compute_entry_hash(&self.prev_hash, &self.frame, &self.redactions, session_link)
+    }
+}
+
+/// `SHA256(prev_hash_hex || canonical_json_bytes(frame))`, hex encoded.
+pub fn compute_hash(prev_hash: &str, frame: &Value) -> serde_json::Result<String> {
+    let frame_bytes = canonical_json_bytes(frame)?;
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(&frame_bytes);
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Chain hash of an entry. Without redactions or a session link this is
+/// `compute_hash`. Non-empty `redactions` append their canonical map, so
+/// commitments cannot be swapped; a session link appends the canonical
+/// `{"session_prev_hash", "session_seq"}` object, so the per-session chain is
+/// covered by the global hash (and its signature).
+pub fn compute_entry_hash(
+    prev_hash: &str,
+    frame: &Value,
+    redactions: &BTreeMap<String, String>,
+    session_link: Option<(u64, &str)>,
+) -> serde_json::Result<String> {
+    if redactions.is_empty() && session_link.is_none() {
+        return compute_hash(prev_hash, frame);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(canonical_json_bytes(frame)?);
+    if !redactions.is_empty() {
+        hasher.update(canonical_json_bytes(&serde_json::to_value(redactions)?)?);
+    }
+    if let Some((seq, session_prev_hash)) = session_link {
+        hasher.update(canonical_json_bytes(&serde_json::json!({
+            "session_prev_hash": session_prev_hash,
+            "session_seq": seq,
+        }))?);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,111 @@
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, TraceError>;
+
+/// Failure to read a trace line, before any chain checks run.
+#[derive(Error, Debug)]
+pub enum ReadError {
+    #[error("io error at line {line}: {source}")]
+    Io {
+        line: usize,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse line {line}: {source}")]
+    Parse {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+impl ReadError {
+    /// 1-based line number the error refers to.
+    pub fn line(&self) -> usize {
+        match self {
+            ReadError::Io { line, .. } | ReadError::Parse { line, .. } => *line,
+        }
+    }
+}
+
+/// A trace that does not hold up. `line` is 1-based, `index` is the entry's `i`.
+#[derive(Error, Debug)]
+pub enum VerificationError {
+    #[error(transparent)]
+    Read(#[from] ReadError),
+
+    #[error("sequence break at line {line}: expected i={expected}, got i={found}")]
+    SequenceBreak {
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+
+    #[error("hash chain broken at line {line} (i={index}): prev_hash mismatch, expected {expected}, got {found}")]
+    ChainBreak {
+        line: usize,
+        index: u64,
+        expected: String,
+        found: String,
+    },
+
+    #[error("integrity check failed at line {line} (i={index}): expected hash {expected}, actual hash {found}")]
+    HashMismatch {
+        line: usize,
+        index: u64,
+        expected: String,
+        found: String,
+    },
+
+    #[error("bad signature at line {line} (i={index}): {reason}")]
+    BadSignature {
+        line: usize,
+        index: u64,
+        reason: String,
+    },
+
+    #[error("entry at line {line} (i={index}) has no session chain fields")]
+    MissingSessionChain { line: usize, index: u64 },
+}
+
+impl VerificationError {
+    /// 1-based line number the error refers to.
+    pub fn line(&self) -> usize {
+        match self {
+            VerificationError::Read(err) => err.line(),
+            VerificationError::SequenceBreak { line, .. }
+            | VerificationError::ChainBreak { line, .. }
+            | VerificationError::HashMismatch { line, .. }
+            | VerificationError::BadSignature { line, .. }
+            | VerificationError::MissingSessionChain { line, .. } => *line,
+        }
+    }
+}
+
+/// Errors from writing or recovering a trace log.
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("serde json error: {0}")]
+    SerdeJson(#[from] serde_json::Error),
+
+    #[error("system clock error: {0}")]
+    Clock(#[from] std::time::SystemTimeError),
+
+    #[error("failed to parse last line of trace log during recovery: {0}")]
+    Recovery(serde_json::Error),
+
+    #[error("trace chain is corrupt before its tail (i={index}): {reason}; refusing to truncate")]
+    CorruptChain { index: u64, reason: String },
+
+    /// The entry is chained and written, but syncing it to disk failed, so
+    /// it may not survive a crash. Later entries chain onto it.
+    #[error("entry written but not synced to disk: {0}")]
+    NotDurable(std::io::Error),
+
+    #[error("trace writer has shut down")]
+    WriterClosed,
+}
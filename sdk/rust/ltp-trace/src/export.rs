@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entry::TraceEntry;
+
+/// A session rendered in the `canonical/trace.v0.1.json` shape.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanonicalTrace {
+    pub protocol: String,
+    pub version: String,
+    pub thread_id: String,
+    pub identity: CanonicalIdentity,
+    pub timeline: Vec<CanonicalTimelinePoint>,
+    pub futures: Vec<CanonicalFuture>,
+    pub constraints: CanonicalConstraints,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanonicalIdentity {
+    pub id: String,
+    pub stability: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanonicalTimelinePoint {
+    pub t: u64,
+    pub event: String,
+    pub orientation: CanonicalOrientation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanonicalOrientation {
+    pub focus_momentum: f64,
+    pub drift: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanonicalFuture {
+    pub id: String,
+    pub confidence: f64,
+    pub admissible: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CanonicalConstraints {
+    pub safety: bool,
+    pub resources: String,
+}
+
+/// Build the canonical view of one session from its trace entries.
+///
+/// Inbound `orientation` frames form the timeline (the first is `init`, later
+/// ones `transition`, with `drift` the change in focus momentum). Outbound
+/// `route_suggestion` frames become futures weighted by how often each sector
+/// was suggested. `FORBIDDEN` errors clear `safety`; `RATE_LIMIT` errors mark
+/// resources as `limited`.
+pub fn export_session<'a>(
+    session_id: &str,
+    entries: impl IntoIterator<Item = &'a TraceEntry>,
+) -> CanonicalTrace {
+    let mut timeline = Vec::new();
+    let mut last_momentum: Option<f64> = None;
+    let mut suggestions: BTreeMap<String, u64> = BTreeMap::new();
+    let mut total_suggestions = 0u64;
+    let mut safety = true;
+    let mut resources = "ok";
+
+    for entry in entries.into_iter().filter(|e| e.session_id == session_id) {
+        match (entry.direction.as_str(), entry.frame_type()) {
+            ("in", Some("orientation")) => {
+                let momentum = entry
+                    .frame
+                    .get("focus_momentum")
+                    .and_then(|v| v.as_f64())
+                    .or(last_momentum)
+                    .unwrap_or(0.0);
+                let drift = last_momentum.map_or(0.0, |prev| (momentum - prev).abs());
+                timeline.push(CanonicalTimelinePoint {
+                    t: timeline.len() as u64,
+                    event: if timeline.is_empty() {
+                        "init".to_string()
+                    } else {
+                        "transition".to_string()
+                    },
+                    orientation: CanonicalOrientation {
+                        focus_momentum: momentum,
+                        drift,
+                    },
+                });
+                last_momentum = Some(momentum);
+            }
+            ("out", Some("route_suggestion")) => {
+                if let Some(sector) = entry.frame.get("suggested_sector").and_then(|v| v.as_str()) {
+                    *suggestions.entry(sector.to_string()).or_default() += 1;
+                    total_suggestions += 1;
+                }
+            }
+            ("out", Some("error")) => match entry.frame.get("code").and_then(|v| v.as_str()) {
+                Some("FORBIDDEN") => safety = false,
+                Some("RATE_LIMIT") => resources = "limited",
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    let futures = suggestions
+        .into_iter()
+        .map(|(id, count)| CanonicalFuture {
+            id,
+            confidence: count as f64 / total_suggestions as f64,
+            admissible: true,
+        })
+        .collect();
+
+    CanonicalTrace {
+        protocol: "LTP".to_string(),
+        version: "0.1".to_string(),
+        thread_id: session_id.to_string(),
+        identity: CanonicalIdentity {
+            id: session_id.to_string(),
+            stability: 1.0,
+        },
+        timeline,
+        futures,
+        constraints: CanonicalConstraints {
+            safety,
+            resources: resources.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(i: u64, direction: &str, frame: serde_json::Value) -> TraceEntry {
+        TraceEntry {
+            i,
+            timestamp_ms: i,
+            direction: direction.to_string(),
+            session_id: "s1".to_string(),
+            frame,
+            redactions: Default::default(),
+            prev_hash: String::new(),
+            session_seq: None,
+            session_prev_hash: None,
+            hash: String::new(),
+            signature: None,
+            alg: None,
+        }
+    }
+
+    #[test]
+    fn exports_orientation_timeline_and_futures() {
+        let entries = vec![
+            entry(
+                0,
+                "in",
+                json!({"type": "orientation", "session_id": "s1", "focus_momentum": 0.72}),
+            ),
+            entry(
+                1,
+                "in",
+                json!({"type": "orientation", "session_id": "s1", "focus_momentum": 0.68}),
+            ),
+            entry(
+                2,
+                "out",
+                json!({"type": "route_suggestion", "session_id": "s1", "suggested_sector": "future_planning"}),
+            ),
+        ];
+
+        let trace = export_session("s1", &entries);
+        assert_eq!(trace.timeline.len(), 2);
+        assert_eq!(trace.timeline[0].event, "init");
+        assert_eq!(trace.timeline[1].event, "transition");
+        assert!((trace.timeline[1].orientation.drift - 0.04).abs() < 1e-9);
+        assert_eq!(trace.futures[0].id, "future_planning");
+        assert_eq!(trace.futures[0].confidence, 1.0);
+        assert!(trace.constraints.safety);
+
+        let value = serde_json::to_value(&trace).unwrap();
+        let canonical: serde_json::Value =
+            serde_json::from_str(include_str!("../../../../canonical/trace.v0.1.json")).unwrap();
+        let keys = |v: &serde_json::Value| -> Vec<String> {
+            v.as_object().unwrap().keys().cloned().collect()
+        };
+        assert_eq!(keys(&value), keys(&canonical));
+        assert_eq!(keys(&value["timeline"][0]), keys(&canonical["timeline"][0]));
+        assert_eq!(keys(&value["futures"][0]), keys(&canonical["futures"][0]));
+    }
+}
@@ -0,0 +1,40 @@
+//! Hash-chained LTP trace log.
+//!
+//! The LTP node records every inbound and outbound frame as one JSON line
+//! (`TraceEntry`), chained with `hash_i = SHA256(hash_{i-1} || canonical(frame_i))`
+//! and optionally signed with ed25519. This crate holds the entry format, the
+//! canonical JSON encoding, a streaming reader, the chain verifier, the
+//! group-commit writer, field redaction, session lifecycle records and query/export helpers so other
+//! Rust programs can produce, check and inspect traces in-process.
+
+pub mod calendar;
+pub mod canonical;
+pub mod compliance;
+pub mod entry;
+pub mod error;
+pub mod export;
+pub mod query;
+pub mod reader;
+pub mod recovery;
+pub mod redact;
+pub mod session;
+pub mod verify;
+pub mod writer;
+
+pub use canonical::{canonical_json_bytes, canonicalize_json};
+pub use compliance::{ComplianceCheck, ComplianceReport};
+pub use entry::{
+    compute_entry_hash, compute_hash, TraceEntry, GENESIS_HASH, SIGNATURE_ALG_ED25519,
+};
+pub use error::{ReadError, Result, TraceError, VerificationError};
+pub use export::{export_session, CanonicalTrace};
+pub use query::{SessionTimelines, TimelineEvent, TraceFilter, TraceStats};
+pub use reader::{TraceReader, TraceRecord};
+pub use recovery::{repair_tail, TailRepair, DEFAULT_VERIFY_DEPTH};
+pub use redact::{verify_disclosure, RedactionPolicy, Redactor};
+pub use session::{SessionClose, SessionOpen, SessionRecord, NODE_DIRECTION};
+pub use verify::{SessionVerifier, TraceVerifier, VerificationReport};
+pub use writer::{
+    recover_state, BatchStats, Durability, TraceLogger, TraceWriterConfig, WriterObserver,
+    DEFAULT_MAX_BATCH, DEFAULT_QUEUE_CAPACITY,
+};
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::entry::TraceEntry;
+
+/// Frame type reported for frames without a `type` tag.
+pub const UNTYPED_FRAME: &str = "<untyped>";
+
+/// Selects trace entries by session, direction, time range and frame type.
+/// Unset criteria match everything; time bounds are inclusive, in epoch ms.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub session_id: Option<String>,
+    pub direction: Option<String>,
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+    pub frame_type: Option<String>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        if let Some(session_id) = &self.session_id {
+            if &entry.session_id != session_id {
+                return false;
+            }
+        }
+        if let Some(direction) = &self.direction {
+            if &entry.direction != direction {
+                return false;
+            }
+        }
+        if let Some(since) = self.since_ms {
+            if entry.timestamp_ms < since {
+                return false;
+            }
+        }
+        if let Some(until) = self.until_ms {
+            if entry.timestamp_ms > until {
+                return false;
+            }
+        }
+        if let Some(frame_type) = &self.frame_type {
+            if entry.frame_type() != Some(frame_type.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Aggregate counters over a set of trace entries.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TraceStats {
+    pub entries: u64,
+    pub frames_by_type: BTreeMap<String, u64>,
+    pub frames_by_direction: BTreeMap<String, u64>,
+    pub error_frames: u64,
+    pub errors_by_code: BTreeMap<String, u64>,
+    pub first_timestamp_ms: Option<u64>,
+    pub last_timestamp_ms: Option<u64>,
+    #[serde(skip)]
+    sessions: BTreeSet<String>,
+}
+
+impl TraceStats {
+    pub fn record(&mut self, entry: &TraceEntry) {
+        self.entries += 1;
+        let frame_type = entry.frame_type().unwrap_or(UNTYPED_FRAME);
+        *self
+            .frames_by_type
+            .entry(frame_type.to_string())
+            .or_default() += 1;
+        *self
+            .frames_by_direction
+            .entry(entry.direction.clone())
+            .or_default() += 1;
+        if frame_type == "error" {
+            self.error_frames += 1;
+            let code = entry
+                .frame
+                .get("code")
+                .and_then(|c| c.as_str())
+                .unwrap_or("UNKNOWN");
+            *self.errors_by_code.entry(code.to_string()).or_default() += 1;
+        }
+        self.first_timestamp_ms = Some(
+            self.first_timestamp_ms
+                .map_or(entry.timestamp_ms, |t| t.min(entry.timestamp_ms)),
+        );
+        self.last_timestamp_ms = Some(
+            self.last_timestamp_ms
+                .map_or(entry.timestamp_ms, |t| t.max(entry.timestamp_ms)),
+        );
+        if !self.sessions.contains(&entry.session_id) {
+            self.sessions.insert(entry.session_id.clone());
+        }
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = &str> {
+        self.sessions.iter().map(String::as_str)
+    }
+}
+
+/// One row of a session timeline.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEvent {
+    pub i: u64,
+    pub timestamp_ms: u64,
+    /// Milliseconds since the first entry of the session.
+    pub offset_ms: u64,
+    pub direction: String,
+    pub frame_type: String,
+}
+
+/// Entries grouped per session, in trace order.
+#[derive(Debug, Clone, Default)]
+pub struct SessionTimelines {
+    sessions: BTreeMap<String, Vec<TimelineEvent>>,
+}
+
+impl SessionTimelines {
+    pub fn record(&mut self, entry: &TraceEntry) {
+        let events = self.sessions.entry(entry.session_id.clone()).or_default();
+        let start = events
+            .first()
+            .map(|e| e.timestamp_ms)
+            .unwrap_or(entry.timestamp_ms);
+        events.push(TimelineEvent {
+            i: entry.i,
+            timestamp_ms: entry.timestamp_ms,
+            offset_ms: entry.timestamp_ms.saturating_sub(start),
+            direction: entry.direction.clone(),
+            frame_type: entry.frame_type().unwrap_or(UNTYPED_FRAME).to_string(),
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[TimelineEvent])> {
+        self.sessions
+            .iter()
+            .map(|(id, events)| (id.as_str(), events.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(
+        i: u64,
+        ts: u64,
+        direction: &str,
+        session: &str,
+        frame: serde_json::Value,
+    ) -> TraceEntry {
+        TraceEntry {
+            i,
+            timestamp_ms: ts,
+            direction: direction.to_string(),
+            session_id: session.to_string(),
+            frame,
+            redactions: Default::default(),
+            prev_hash: String::new(),
+            session_seq: None,
+            session_prev_hash: None,
+            hash: String::new(),
+            signature: None,
+            alg: None,
+        }
+    }
+
+    #[test]
+    fn filter_and_stats() {
+        let entries = vec![
+            entry(0, 100, "out", "a", json!({"type": "hello_ack"})),
+            entry(1, 150, "in", "a", json!({"type": "heartbeat"})),
+            entry(
+                2,
+                200,
+                "out",
+                "b",
+                json!({"type": "error", "code": "FORBIDDEN"}),
+            ),
+            entry(3, 300, "in", "a", json!({"type": "route_request"})),
+        ];
+
+        let filter = TraceFilter {
+            session_id: Some("a".to_string()),
+            direction: Some("in".to_string()),
+            since_ms: Some(120),
+            ..Default::default()
+        };
+        let selected: Vec<u64> = entries
+            .iter()
+            .filter(|e| filter.matches(e))
+            .map(|e| e.i)
+            .collect();
+        assert_eq!(selected, vec![1, 3]);
+
+        let mut stats = TraceStats::default();
+        let mut timelines = SessionTimelines::default();
+        for e in &entries {
+            stats.record(e);
+            timelines.record(e);
+        }
+        assert_eq!(stats.entries, 4);
+        assert_eq!(stats.session_count(), 2);
+        assert_eq!(stats.error_frames, 1);
+        assert_eq!(stats.errors_by_code.get("FORBIDDEN"), Some(&1));
+        assert_eq!(stats.frames_by_direction.get("in"), Some(&2));
+
+        let (id, events) = timelines.iter().next().unwrap();
+        assert_eq!(id, "a");
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].offset_ms, 200);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::entry::TraceEntry;
+use crate::error::ReadError;
+
+/// A parsed trace entry together with the 1-based line it was read from.
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+    pub line: usize,
+    pub entry: TraceEntry,
+}
+
+/// Streaming reader over a JSONL trace. Blank lines are skipped; entries are
+/// parsed one at a time so multi-GB logs never have to fit in memory.
+pub struct TraceReader<R> {
+    lines: std::io::Lines<R>,
+    line: usize,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let raw = self.lines.next()?;
+            self.line += 1;
+            let line = self.line;
+            let raw = match raw {
+                Ok(raw) => raw,
+                Err(source) => return Some(Err(ReadError::Io { line, source })),
+            };
+            if raw.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(&raw)
+                    .map(|entry| TraceRecord { line, entry })
+                    .map_err(|source| ReadError::Parse { line, source }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_blank_lines_and_reports_line_numbers() {
+        let input = concat!(
+            r#"{"i":0,"timestamp_ms":1,"direction":"in","session_id":"s","frame":{},"prev_hash":"a","hash":"b"}"#,
+            "\n\n",
+            "not json\n"
+        );
+        let mut reader = TraceReader::new(input.as_bytes());
+
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.line, 1);
+        assert_eq!(first.entry.session_id, "s");
+
+        let err = reader.next().unwrap().unwrap_err();
+        assert!(matches!(err, ReadError::Parse { line: 3, .. }));
+        assert!(reader.next().is_none());
+    }
+}
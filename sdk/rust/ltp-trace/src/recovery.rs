@@ -0,0 +1,343 @@
+//! Repair of a trace whose final write was interrupted.
+//!
+//! A crash mid-write leaves a torn (unparseable) line at the end of the file,
+//! and `recover_state` cannot resume from it. `repair_tail` walks back to the
+//! last entry whose hash still recomputes, re-checks the chain for a few
+//! entries before it, moves everything after it into a sidecar
+//! `<trace>.quarantine-<epoch_ms>` file and truncates the trace there. The
+//! writer then appends a `recovery` entry describing what was removed, so the
+//! truncation is itself part of the (signed) chain.
+
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::entry::TraceEntry;
+use crate::error::{Result, TraceError};
+
+/// Entries before the last valid one whose linkage is re-checked by default.
+pub const DEFAULT_VERIFY_DEPTH: usize = 32;
+
+/// `direction` of entries written by the trace writer itself.
+pub const RECOVERY_DIRECTION: &str = crate::session::NODE_DIRECTION;
+
+/// Frame `type` of the entry recording a tail repair.
+pub const RECOVERY_FRAME_TYPE: &str = "recovery";
+
+const INITIAL_WINDOW: u64 = 64 * 1024;
+
+/// What `repair_tail` removed from a trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TailRepair {
+    pub quarantine_file: PathBuf,
+    pub quarantined_bytes: u64,
+    pub quarantined_lines: usize,
+    /// SHA-256 of the quarantined bytes, so the sidecar can be matched later.
+    pub quarantined_sha256: String,
+    /// Index of the entry the trace now ends with, if any survived.
+    pub last_valid_i: Option<u64>,
+}
+
+impl TailRepair {
+    /// Frame recorded in the trace after the repair.
+    pub fn to_frame(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": RECOVERY_FRAME_TYPE,
+            "reason": "torn_tail",
+            "quarantine_file": self
+                .quarantine_file
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            "quarantined_bytes": self.quarantined_bytes,
+            "quarantined_lines": self.quarantined_lines,
+            "quarantined_sha256": self.quarantined_sha256,
+            "last_valid_i": self.last_valid_i,
+        })
+    }
+}
+
+/// One line of the tail window with its absolute byte offset.
+struct TailLine<'a> {
+    offset: u64,
+    bytes: &'a [u8],
+    terminated: bool,
+}
+
+/// Make the trace at `path` safe to append to.
+///
+/// Returns `None` when the tail is intact (a missing final newline is fixed
+/// in place). Otherwise quarantines the bytes after the last valid entry and
+/// returns what was removed. Fails with `TraceError::CorruptChain` instead of
+/// truncating when any of the `verify_depth` entries before the last valid one
+/// do not chain, since that is tampering or media damage rather than a crash.
+pub fn repair_tail(path: &Path, verify_depth: usize) -> Result<Option<TailRepair>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let mut window = INITIAL_WINDOW;
+    let (cut, needs_newline) = loop {
+        let start = len.saturating_sub(window);
+        let mut bytes = vec![0u8; (len - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut bytes)?;
+
+        if let Some(found) = find_cut(&split_lines(start, &bytes), verify_depth, start == 0)? {
+            break found;
+        }
+        window = window.saturating_mul(4);
+    };
+
+    if cut.offset == len {
+        if needs_newline {
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(b"\n")?;
+            file.sync_data()?;
+        }
+        return Ok(None);
+    }
+
+    let mut quarantined = vec![0u8; (len - cut.offset) as usize];
+    file.seek(SeekFrom::Start(cut.offset))?;
+    file.read_exact(&mut quarantined)?;
+
+    let quarantine_file = quarantine_path(path)?;
+    let mut sidecar = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&quarantine_file)?;
+    sidecar.write_all(&quarantined)?;
+    sidecar.sync_all()?;
+
+    file.set_len(cut.offset)?;
+    file.sync_all()?;
+
+    Ok(Some(TailRepair {
+        quarantine_file,
+        quarantined_bytes: quarantined.len() as u64,
+        quarantined_lines: quarantined
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .count(),
+        quarantined_sha256: format!("{:x}", Sha256::digest(&quarantined)),
+        last_valid_i: cut.last_valid_i,
+    }))
+}
+
+struct Cut {
+    /// Byte offset the trace should end at.
+    offset: u64,
+    last_valid_i: Option<u64>,
+}
+
+/// Split `bytes` (read from absolute offset `start`) into lines, dropping a
+/// leading partial line unless the window starts at the beginning of the file.
+fn split_lines(start: u64, bytes: &[u8]) -> Vec<TailLine<'_>> {
+    let mut lines = Vec::new();
+    let mut pos = 0usize;
+    if start > 0 {
+        match bytes.iter().position(|&b| b == b'\n') {
+            Some(idx) => pos = idx + 1,
+            None => return lines,
+        }
+    }
+    while pos < bytes.len() {
+        let (end, terminated) = match bytes[pos..].iter().position(|&b| b == b'\n') {
+            Some(idx) => (pos + idx, true),
+            None => (bytes.len(), false),
+        };
+        lines.push(TailLine {
+            offset: start + pos as u64,
+            bytes: &bytes[pos..end],
+            terminated,
+        });
+        pos = end + 1;
+    }
+    lines
+}
+
+/// Locate the end of the last valid entry. `None` means the window is too
+/// small to decide and must grow.
+fn find_cut(
+    lines: &[TailLine<'_>],
+    verify_depth: usize,
+    at_start: bool,
+) -> Result<Option<(Cut, bool)>> {
+    let parsed: Vec<Option<TraceEntry>> = lines.iter().map(parse_valid).collect();
+
+    let Some(last) = parsed.iter().rposition(Option::is_some) else {
+        if !at_start {
+            return Ok(None);
+        }
+        // Nothing salvageable: the whole file is quarantined.
+        return Ok(Some((
+            Cut {
+                offset: 0,
+                last_valid_i: None,
+            },
+            false,
+        )));
+    };
+
+    // Line indices of the entries whose linkage is re-checked, newest first.
+    let mut checked: Vec<usize> = Vec::new();
+    for idx in (0..=last).rev() {
+        if checked.len() > verify_depth {
+            break;
+        }
+        match &parsed[idx] {
+            Some(_) => checked.push(idx),
+            None if is_blank(lines[idx].bytes) => {}
+            None => {
+                let next = checked.last().and_then(|&k| parsed[k].as_ref());
+                return Err(TraceError::CorruptChain {
+                    index: next.map(|e| e.i).unwrap_or_default(),
+                    reason: format!("unreadable or altered line at byte {}", lines[idx].offset),
+                });
+            }
+        }
+    }
+    if checked.len() <= verify_depth && !at_start {
+        return Ok(None);
+    }
+    for pair in checked.windows(2) {
+        let (newer, older) = (entry_at(&parsed, pair[0]), entry_at(&parsed, pair[1]));
+        if newer.i != older.i + 1 || newer.prev_hash != older.hash {
+            return Err(TraceError::CorruptChain {
+                index: newer.i,
+                reason: "entry does not link to its predecessor".to_string(),
+            });
+        }
+    }
+
+    let line = &lines[last];
+    let needs_newline = !line.terminated;
+    let mut offset = line.offset + line.bytes.len() as u64 + u64::from(line.terminated);
+    // Trailing blank lines are harmless; keep them rather than quarantine.
+    if lines[last + 1..]
+        .iter()
+        .all(|line| is_blank(line.bytes) && line.terminated)
+    {
+        offset = lines
+            .last()
+            .map(|l| l.offset + l.bytes.len() as u64 + u64::from(l.terminated))
+            .unwrap_or(offset);
+    }
+    Ok(Some((
+        Cut {
+            offset,
+            last_valid_i: Some(entry_at(&parsed, last).i),
+        },
+        needs_newline,
+    )))
+}
+
+/// The entry on `line`, if it parses and its hash recomputes.
+fn parse_valid(line: &TailLine<'_>) -> Option<TraceEntry> {
+    let entry: TraceEntry = serde_json::from_slice(line.bytes).ok()?;
+    let hash = entry.chain_hash().ok()?;
+    (hash == entry.hash).then_some(entry)
+}
+
+fn entry_at(parsed: &[Option<TraceEntry>], idx: usize) -> &TraceEntry {
+    parsed[idx].as_ref().expect("index of a parsed entry")
+}
+
+fn is_blank(bytes: &[u8]) -> bool {
+    bytes.iter().all(u8::is_ascii_whitespace)
+}
+
+fn quarantine_path(path: &Path) -> Result<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let mut name = path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(format!(".quarantine-{}", now));
+    Ok(path.with_file_name(name))
+}
+
+/// Used by tests to simulate a crash mid-write.
+#[cfg(test)]
+pub(crate) fn append_raw(path: &Path, bytes: &[u8]) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::TraceReader;
+    use crate::verify::TraceVerifier;
+    use crate::writer::TraceLogger;
+    use tempfile::TempDir;
+
+    async fn write_entries(path: &Path, n: u64) {
+        let logger = TraceLogger::open(path, None).await.unwrap();
+        for k in 0..n {
+            logger
+                .log("in", "s1", &serde_json::json!({"n": k}))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn intact_trace_is_left_alone() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        write_entries(&path, 3).await;
+        let before = std::fs::read(&path).unwrap();
+        assert_eq!(repair_tail(&path, 8).unwrap(), None);
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+    }
+
+    #[tokio::test]
+    async fn quarantines_torn_tail() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        write_entries(&path, 3).await;
+        let intact_len = std::fs::metadata(&path).unwrap().len();
+        append_raw(&path, b"{\"i\":3,\"timestamp_ms\":1,\"dire");
+
+        let repair = repair_tail(&path, 8).unwrap().expect("tail repaired");
+        assert_eq!(repair.last_valid_i, Some(2));
+        assert_eq!(repair.quarantined_lines, 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), intact_len);
+        assert_eq!(
+            std::fs::read(&repair.quarantine_file).unwrap(),
+            b"{\"i\":3,\"timestamp_ms\":1,\"dire"
+        );
+
+        let report = TraceVerifier::new()
+            .verify_reader(TraceReader::open(&path).unwrap())
+            .unwrap();
+        assert_eq!(report.entries, 3);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_truncate_a_broken_chain() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        write_entries(&path, 3).await;
+        let content = std::fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = content.lines().collect();
+        lines.remove(1);
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        append_raw(&path, b"{\"torn");
+
+        assert!(matches!(
+            repair_tail(&path, 8),
+            Err(TraceError::CorruptChain { index: 2, .. })
+        ));
+    }
+}
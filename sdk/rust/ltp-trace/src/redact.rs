@@ -0,0 +1,183 @@
+//! Field redaction with salted hash commitments.
+//!
+//! A `RedactionPolicy` lists JSON pointers per frame `type` (or `"*"` for all
+//! frames). Before an entry is written, each listed value is replaced by
+//! `"redacted:<commitment>"` and the commitment is recorded in the entry's
+//! `redactions` map, which is covered by the chain hash. The commitment is
+//! `SHA256(salt || canonical_json_bytes(value))`, where the salt is derived
+//! from the operator's redaction key, the entry index and the pointer, so no
+//! salts need to be stored: the operator can re-derive one later and disclose
+//! it with the original value, and anyone holding the trace can check that
+//! the pair matches the commitment.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::canonical::canonical_json_bytes;
+
+/// Policy key that applies to every frame regardless of its `type`.
+pub const ANY_FRAME_TYPE: &str = "*";
+
+/// Prefix of the placeholder string that replaces a redacted value.
+pub const REDACTED_PREFIX: &str = "redacted:";
+
+/// JSON pointers to redact, keyed by frame `type`.
+///
+/// File form: `{ "hello": ["/api_key"], "*": ["/client_label"] }`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RedactionPolicy {
+    pub rules: BTreeMap<String, Vec<String>>,
+}
+
+impl RedactionPolicy {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(std::io::Error::from)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.values().all(Vec::is_empty)
+    }
+
+    fn pointers_for<'a>(&'a self, frame_type: Option<&str>) -> impl Iterator<Item = &'a String> {
+        let specific = frame_type.and_then(|t| self.rules.get(t));
+        let wildcard = self.rules.get(ANY_FRAME_TYPE);
+        specific.into_iter().chain(wildcard).flatten()
+    }
+}
+
+/// Applies a `RedactionPolicy`, deriving per-value salts from a secret key.
+#[derive(Clone)]
+pub struct Redactor {
+    policy: RedactionPolicy,
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redactor")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redactor {
+    pub fn new(policy: RedactionPolicy, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            policy,
+            key: key.into(),
+        }
+    }
+
+    pub fn policy(&self) -> &RedactionPolicy {
+        &self.policy
+    }
+
+    /// Salt for the value at `pointer` in entry `i`, hex encoded.
+    pub fn salt_for(&self, i: u64, pointer: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key)
+            .expect("HMAC can take key of any size");
+        mac.update(b"ltp-trace-redaction-salt");
+        mac.update(&i.to_be_bytes());
+        mac.update(pointer.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Redact `frame` in place for entry `i`, returning pointer -> commitment
+    /// for every value that was present.
+    pub fn redact(&self, i: u64, frame: &mut Value) -> BTreeMap<String, String> {
+        let frame_type = frame
+            .get("type")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let mut commitments = BTreeMap::new();
+        for pointer in self.policy.pointers_for(frame_type.as_deref()) {
+            if commitments.contains_key(pointer) {
+                continue;
+            }
+            let Some(slot) = frame.pointer_mut(pointer) else {
+                continue;
+            };
+            let salt = self.salt_for(i, pointer);
+            let commitment = commit(&salt, slot);
+            *slot = Value::String(format!("{}{}", REDACTED_PREFIX, commitment));
+            commitments.insert(pointer.clone(), commitment);
+        }
+        commitments
+    }
+}
+
+/// `SHA256(salt_hex || canonical_json_bytes(value))`, hex encoded.
+pub fn commit(salt_hex: &str, value: &Value) -> String {
+    let bytes = canonical_json_bytes(value).expect("serializing a JSON value cannot fail");
+    let mut hasher = Sha256::new();
+    hasher.update(salt_hex.as_bytes());
+    hasher.update(&bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+/// Check a disclosed `(value, salt)` pair against a recorded commitment.
+pub fn verify_disclosure(commitment: &str, salt_hex: &str, value: &Value) -> bool {
+    commit(salt_hex, value) == commitment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redactor() -> Redactor {
+        let policy: RedactionPolicy = serde_json::from_value(json!({
+            "hello": ["/api_key"],
+            "*": ["/client_label", "/missing"]
+        }))
+        .unwrap();
+        Redactor::new(policy, b"secret".to_vec())
+    }
+
+    #[test]
+    fn redacts_listed_pointers_and_commitments_open() {
+        let redactor = redactor();
+        let mut frame =
+            json!({"type": "hello", "api_key": "k-123", "client_label": {"name": "alice"}});
+        let commitments = redactor.redact(5, &mut frame);
+
+        assert_eq!(commitments.len(), 2);
+        let api_key_commitment = &commitments["/api_key"];
+        assert_eq!(frame["api_key"], format!("redacted:{}", api_key_commitment));
+        assert!(frame["client_label"]
+            .as_str()
+            .unwrap()
+            .starts_with(REDACTED_PREFIX));
+
+        let salt = redactor.salt_for(5, "/api_key");
+        assert!(verify_disclosure(
+            api_key_commitment,
+            &salt,
+            &json!("k-123")
+        ));
+        assert!(!verify_disclosure(
+            api_key_commitment,
+            &salt,
+            &json!("k-124")
+        ));
+        assert!(!verify_disclosure(
+            api_key_commitment,
+            &redactor.salt_for(6, "/api_key"),
+            &json!("k-123")
+        ));
+    }
+
+    #[test]
+    fn leaves_other_frame_types_alone() {
+        let mut frame = json!({"type": "heartbeat", "api_key": "k"});
+        assert!(redactor().redact(0, &mut frame).is_empty());
+        assert_eq!(frame["api_key"], "k");
+    }
+}
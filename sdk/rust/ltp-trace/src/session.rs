@@ -0,0 +1,117 @@
+//! Session lifecycle records.
+//!
+//! The node writes a `session_open` entry when a session is authorized (before
+//! its `hello_ack`) and a `session_close` entry when the connection ends, both
+//! with direction `"node"` and the session's id. Together they say which
+//! identity owned every frame in between. Fields an operator considers
+//! personal data (`client_ip`, `client_label`) are hashed by listing them in
+//! the redaction policy under these frame types.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::entry::TraceEntry;
+
+/// `direction` of entries written by the node rather than received or sent.
+pub const NODE_DIRECTION: &str = "node";
+
+/// Frame `type` of the record opening a session.
+pub const SESSION_OPEN_FRAME_TYPE: &str = "session_open";
+
+/// Frame `type` of the record closing a session.
+pub const SESSION_CLOSE_FRAME_TYPE: &str = "session_close";
+
+/// A session lifecycle frame.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionRecord {
+    SessionOpen(SessionOpen),
+    SessionClose(SessionClose),
+}
+
+/// Who opened a session and from where.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionOpen {
+    /// Key id the client authenticated with.
+    pub auth_id: String,
+    /// How the node establishes identity (`api_key`, `mtls_subject`, `off`).
+    pub identity_source: String,
+    /// Client address, taken from the proxy headers when the proxy is trusted.
+    pub client_ip: String,
+    /// Address of the trusted proxy the connection arrived through, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_hop: Option<String>,
+    /// `client_label` the client sent in `hello`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_label: Option<String>,
+    pub node_id: String,
+}
+
+/// Why and after how long a session ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClose {
+    pub auth_id: String,
+    pub reason: String,
+    pub duration_ms: u64,
+}
+
+impl SessionRecord {
+    /// The lifecycle record carried by `entry`, if it is one.
+    pub fn from_entry(entry: &TraceEntry) -> Option<Self> {
+        if entry.direction != NODE_DIRECTION {
+            return None;
+        }
+        match entry.frame_type() {
+            Some(SESSION_OPEN_FRAME_TYPE) | Some(SESSION_CLOSE_FRAME_TYPE) => {
+                Self::deserialize(&entry.frame).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Frame to pass to `TraceLogger::log`.
+    pub fn to_frame(&self) -> Value {
+        serde_json::to_value(self).expect("session record serializes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::GENESIS_HASH;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_through_an_entry() {
+        let record = SessionRecord::SessionOpen(SessionOpen {
+            auth_id: "key-1".to_string(),
+            identity_source: "api_key".to_string(),
+            client_ip: "203.0.113.7".to_string(),
+            proxy_hop: Some("10.0.0.2:443".to_string()),
+            client_label: None,
+            node_id: "node-a".to_string(),
+        });
+        let frame = record.to_frame();
+        assert_eq!(frame["type"], json!("session_open"));
+        assert!(frame.get("client_label").is_none());
+
+        let mut entry = TraceEntry {
+            i: 0,
+            timestamp_ms: 0,
+            direction: NODE_DIRECTION.to_string(),
+            session_id: "s1".to_string(),
+            frame,
+            redactions: Default::default(),
+            prev_hash: GENESIS_HASH.to_string(),
+            session_seq: None,
+            session_prev_hash: None,
+            hash: String::new(),
+            signature: None,
+            alg: None,
+        };
+        assert_eq!(SessionRecord::from_entry(&entry), Some(record));
+
+        entry.direction = "out".to_string();
+        assert_eq!(SessionRecord::from_entry(&entry), None);
+    }
+}
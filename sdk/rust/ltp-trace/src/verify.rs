@@ -0,0 +1,400 @@
+use std::io::BufRead;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+use crate::entry::{TraceEntry, GENESIS_HASH, SIGNATURE_ALG_ED25519};
+use crate::error::VerificationError;
+use crate::reader::TraceReader;
+
+/// Summary of a successfully verified trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+    pub entries: u64,
+    pub signed_entries: u64,
+    pub last_hash: String,
+}
+
+/// Incremental checker for the trace hash chain.
+///
+/// Entries are fed in file order; each one is checked for sequence (`i`),
+/// linkage (`prev_hash`), integrity (recomputed `hash`) and, when a
+/// verifying key is configured, its ed25519 signature over `hash`.
+#[derive(Debug, Clone)]
+pub struct TraceVerifier {
+    prev_hash: String,
+    expected_i: u64,
+    signed_entries: u64,
+    verifying_key: Option<VerifyingKey>,
+}
+
+impl Default for TraceVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceVerifier {
+    pub fn new() -> Self {
+        Self {
+            prev_hash: GENESIS_HASH.to_string(),
+            expected_i: 0,
+            signed_entries: 0,
+            verifying_key: None,
+        }
+    }
+
+    /// Require every entry to carry a valid signature from `key`.
+    pub fn with_verifying_key(mut self, key: VerifyingKey) -> Self {
+        self.verifying_key = Some(key);
+        self
+    }
+
+    /// Start from a known chain position instead of genesis, e.g. when
+    /// verifying a rotated log that carried over the previous file's last hash.
+    pub fn starting_at(mut self, next_i: u64, prev_hash: impl Into<String>) -> Self {
+        self.expected_i = next_i;
+        self.prev_hash = prev_hash.into();
+        self
+    }
+
+    /// Check one entry read from `line` (1-based) and advance the chain.
+    pub fn verify_entry(
+        &mut self,
+        line: usize,
+        entry: &TraceEntry,
+    ) -> Result<(), VerificationError> {
+        if entry.i != self.expected_i {
+            return Err(VerificationError::SequenceBreak {
+                line,
+                expected: self.expected_i,
+                found: entry.i,
+            });
+        }
+
+        if entry.prev_hash != self.prev_hash {
+            return Err(VerificationError::ChainBreak {
+                line,
+                index: entry.i,
+                expected: self.prev_hash.clone(),
+                found: entry.prev_hash.clone(),
+            });
+        }
+
+        check_hash(line, entry)?;
+        if let Some(key) = &self.verifying_key {
+            check_signature(key, line, entry)?;
+        }
+        if entry.signature.is_some() {
+            self.signed_entries += 1;
+        }
+
+        self.prev_hash = entry.hash.clone();
+        self.expected_i += 1;
+        Ok(())
+    }
+
+    /// Verify every entry produced by `reader`, stopping at the first failure.
+    pub fn verify_reader<R: BufRead>(
+        mut self,
+        reader: TraceReader<R>,
+    ) -> Result<VerificationReport, VerificationError> {
+        for record in reader {
+            let record = record?;
+            self.verify_entry(record.line, &record.entry)?;
+        }
+        Ok(self.report())
+    }
+
+    /// Entries accepted so far and the current chain head.
+    pub fn report(&self) -> VerificationReport {
+        VerificationReport {
+            entries: self.expected_i,
+            signed_entries: self.signed_entries,
+            last_hash: self.prev_hash.clone(),
+        }
+    }
+}
+
+/// Checks one session's chain (`session_seq`/`session_prev_hash`) in
+/// isolation, e.g. a session extracted with `ltp-trace filter --session`.
+///
+/// Entries of other sessions are skipped, so the full trace works as input
+/// too. Each entry's `hash` is recomputed from its own fields and, with a
+/// verifying key, its signature is checked; the global `prev_hash` is taken
+/// as given, since the other sessions' frames are not needed to trust it.
+#[derive(Debug, Clone)]
+pub struct SessionVerifier {
+    session_id: String,
+    prev_hash: String,
+    expected_seq: u64,
+    signed_entries: u64,
+    verifying_key: Option<VerifyingKey>,
+}
+
+impl SessionVerifier {
+    pub fn new(session_id: impl Into<String>) -> Self {
+        Self {
+            session_id: session_id.into(),
+            prev_hash: GENESIS_HASH.to_string(),
+            expected_seq: 0,
+            signed_entries: 0,
+            verifying_key: None,
+        }
+    }
+
+    /// Require every entry to carry a valid signature from `key`.
+    pub fn with_verifying_key(mut self, key: VerifyingKey) -> Self {
+        self.verifying_key = Some(key);
+        self
+    }
+
+    /// Check one entry read from `line` (1-based) and advance the session chain.
+    pub fn verify_entry(
+        &mut self,
+        line: usize,
+        entry: &TraceEntry,
+    ) -> Result<(), VerificationError> {
+        if entry.session_id != self.session_id {
+            return Ok(());
+        }
+        let (Some(seq), Some(session_prev_hash)) = (entry.session_seq, &entry.session_prev_hash)
+        else {
+            return Err(VerificationError::MissingSessionChain {
+                line,
+                index: entry.i,
+            });
+        };
+
+        if seq != self.expected_seq {
+            return Err(VerificationError::SequenceBreak {
+                line,
+                expected: self.expected_seq,
+                found: seq,
+            });
+        }
+        if session_prev_hash != &self.prev_hash {
+            return Err(VerificationError::ChainBreak {
+                line,
+                index: entry.i,
+                expected: self.prev_hash.clone(),
+                found: session_prev_hash.clone(),
+            });
+        }
+
+        check_hash(line, entry)?;
+        if let Some(key) = &self.verifying_key {
+            check_signature(key, line, entry)?;
+        }
+        if entry.signature.is_some() {
+            self.signed_entries += 1;
+        }
+
+        self.prev_hash = entry.hash.clone();
+        self.expected_seq += 1;
+        Ok(())
+    }
+
+    /// Verify this session's entries produced by `reader`, stopping at the
+    /// first failure.
+    pub fn verify_reader<R: BufRead>(
+        mut self,
+        reader: TraceReader<R>,
+    ) -> Result<VerificationReport, VerificationError> {
+        for record in reader {
+            let record = record?;
+            self.verify_entry(record.line, &record.entry)?;
+        }
+        Ok(self.report())
+    }
+
+    /// Session entries accepted so far and the session chain head.
+    pub fn report(&self) -> VerificationReport {
+        VerificationReport {
+            entries: self.expected_seq,
+            signed_entries: self.signed_entries,
+            last_hash: self.prev_hash.clone(),
+        }
+    }
+}
+
+fn check_hash(line: usize, entry: &TraceEntry) -> Result<(), VerificationError> {
+    let computed = entry
+        .chain_hash()
+        .map_err(|e| VerificationError::HashMismatch {
+            line,
+            index: entry.i,
+            expected: format!("<unhashable frame: {}>", e),
+            found: entry.hash.clone(),
+        })?;
+    if computed != entry.hash {
+        return Err(VerificationError::HashMismatch {
+            line,
+            index: entry.i,
+            expected: computed,
+            found: entry.hash.clone(),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn check_signature(
+    key: &VerifyingKey,
+    line: usize,
+    entry: &TraceEntry,
+) -> Result<(), VerificationError> {
+    let bad = |reason: String| VerificationError::BadSignature {
+        line,
+        index: entry.i,
+        reason,
+    };
+
+    let signature_hex = entry
+        .signature
+        .as_ref()
+        .ok_or_else(|| bad("missing signature".to_string()))?;
+    match entry.alg.as_deref() {
+        Some(SIGNATURE_ALG_ED25519) => {}
+        other => return Err(bad(format!("unsupported alg {:?}", other))),
+    }
+
+    let bytes = hex::decode(signature_hex).map_err(|e| bad(format!("invalid hex: {}", e)))?;
+    let signature =
+        Signature::from_slice(&bytes).map_err(|e| bad(format!("malformed signature: {}", e)))?;
+    key.verify(entry.hash.as_bytes(), &signature)
+        .map_err(|_| bad("signature does not match hash".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::TraceLogger;
+    use ed25519_dalek::SigningKey;
+    use tempfile::NamedTempFile;
+
+    async fn write_trace(signing_key: Option<SigningKey>) -> NamedTempFile {
+        let temp_file = NamedTempFile::new().unwrap();
+        let logger = TraceLogger::open(temp_file.path(), signing_key)
+            .await
+            .unwrap();
+        logger
+            .log(
+                "in",
+                "s1",
+                &serde_json::json!({"type": "hello", "api_key": "k"}),
+            )
+            .await
+            .unwrap();
+        logger
+            .log(
+                "out",
+                "s1",
+                &serde_json::json!({"type": "hello_ack", "accepted": true}),
+            )
+            .await
+            .unwrap();
+        logger
+            .log(
+                "in",
+                "s1",
+                &serde_json::json!({"type": "heartbeat", "timestamp_ms": 1}),
+            )
+            .await
+            .unwrap();
+        temp_file
+    }
+
+    fn rewrite_line(path: &std::path::Path, index: usize, edit: impl FnOnce(&mut TraceEntry)) {
+        let content = std::fs::read_to_string(path).unwrap();
+        let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+        let mut entry: TraceEntry = serde_json::from_str(&lines[index]).unwrap();
+        edit(&mut entry);
+        lines[index] = serde_json::to_string(&entry).unwrap();
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    fn verify(
+        path: &std::path::Path,
+        verifier: TraceVerifier,
+    ) -> Result<VerificationReport, VerificationError> {
+        verifier.verify_reader(TraceReader::open(path).unwrap())
+    }
+
+    #[tokio::test]
+    async fn accepts_untampered_trace() {
+        let file = write_trace(None).await;
+        let report = verify(file.path(), TraceVerifier::new()).unwrap();
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.signed_entries, 0);
+    }
+
+    #[tokio::test]
+    async fn detects_tampered_frame() {
+        let file = write_trace(None).await;
+        rewrite_line(file.path(), 1, |e| e.frame["accepted"] = false.into());
+        let err = verify(file.path(), TraceVerifier::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            VerificationError::HashMismatch {
+                line: 2,
+                index: 1,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn detects_sequence_and_chain_breaks() {
+        let file = write_trace(None).await;
+        rewrite_line(file.path(), 2, |e| e.i = 7);
+        let err = verify(file.path(), TraceVerifier::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            VerificationError::SequenceBreak {
+                line: 3,
+                expected: 2,
+                found: 7
+            }
+        ));
+
+        let file = write_trace(None).await;
+        rewrite_line(file.path(), 2, |e| e.prev_hash = GENESIS_HASH.to_string());
+        let err = verify(file.path(), TraceVerifier::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            VerificationError::ChainBreak {
+                line: 3,
+                index: 2,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn checks_signatures_against_verifying_key() {
+        let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+        let verifying_key = signing_key.verifying_key();
+        let file = write_trace(Some(signing_key)).await;
+
+        let report = verify(
+            file.path(),
+            TraceVerifier::new().with_verifying_key(verifying_key),
+        )
+        .unwrap();
+        assert_eq!(report.signed_entries, 3);
+
+        let other_key = SigningKey::generate(&mut rand::rngs::OsRng).verifying_key();
+        let err = verify(
+            file.path(),
+            TraceVerifier::new().with_verifying_key(other_key),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            VerificationError::BadSignature {
+                line: 1,
+                index: 0,
+                ..
+            }
+        ));
+    }
+}
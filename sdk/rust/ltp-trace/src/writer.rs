@@ -0,0 +1,1074 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signer, SigningKey};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::entry::{TraceEntry, GENESIS_HASH, SIGNATURE_ALG_ED25519};
+use crate::error::{Result, TraceError};
+use crate::recovery::{repair_tail, TailRepair, DEFAULT_VERIFY_DEPTH, RECOVERY_DIRECTION};
+use crate::redact::Redactor;
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 4096;
+pub const DEFAULT_MAX_BATCH: usize = 256;
+
+/// When written entries are pushed to the OS and to stable storage.
+///
+/// If an fsync fails, the entries of that batch are acknowledged with
+/// `TraceError::NotDurable`: they are written and stay on the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Flush to the OS after every entry (no fsync).
+    #[default]
+    Flush,
+    /// Flush and fsync once per batch.
+    FsyncBatch,
+    /// Flush once per batch; fsync at most once per interval.
+    FsyncInterval(Duration),
+}
+
+/// Per-batch numbers reported to a `WriterObserver`.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchStats {
+    pub entries: usize,
+    /// Time spent writing, flushing and (if due) syncing the batch.
+    pub write_latency: Duration,
+    /// Entries still queued when the batch finished.
+    pub queue_depth: usize,
+    pub synced: bool,
+}
+
+/// Hooks for exporting writer metrics. Called from the writer thread, so
+/// implementations must be cheap.
+pub trait WriterObserver: Send + Sync {
+    fn batch_written(&self, _stats: &BatchStats) {}
+
+    /// A `log` call found the queue full and is waiting for room.
+    fn queue_full(&self) {}
+}
+
+/// Options for `TraceLogger::open_with_config`.
+#[derive(Clone)]
+pub struct TraceWriterConfig {
+    /// Sign every entry's hash with this key.
+    pub signing_key: Option<SigningKey>,
+    /// Redact frames before they are hashed and written.
+    pub redactor: Option<Redactor>,
+    pub durability: Durability,
+    /// Entries that may wait for the writer before `log` applies backpressure.
+    pub queue_capacity: usize,
+    /// Upper bound on entries written per batch.
+    pub max_batch: usize,
+    /// Entries re-checked before a torn tail is quarantined (see `repair_tail`).
+    pub verify_depth: usize,
+    pub observer: Option<Arc<dyn WriterObserver>>,
+}
+
+impl Default for TraceWriterConfig {
+    fn default() -> Self {
+        Self {
+            signing_key: None,
+            redactor: None,
+            durability: Durability::default(),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            max_batch: DEFAULT_MAX_BATCH,
+            verify_depth: DEFAULT_VERIFY_DEPTH,
+            observer: None,
+        }
+    }
+}
+
+/// Work for the writer thread, processed in queue order.
+enum Request {
+    Log(Pending),
+    /// Forget a session's chain head once it will log no more entries.
+    EndSession(String),
+}
+
+/// A frame waiting for the writer, with the channel its caller awaits.
+struct Pending {
+    timestamp_ms: u64,
+    direction: String,
+    session_id: String,
+    frame: Value,
+    ack: oneshot::Sender<Result<()>>,
+}
+
+/// Append-only writer that extends the hash chain of an existing trace file.
+///
+/// `log` hands frames to a dedicated writer thread over a bounded queue. The
+/// writer assigns indices and hashes in queue order, so the chain is exactly
+/// as if entries were written one at a time, but groups whatever is queued
+/// into one write (and one fsync, depending on `Durability`). `log` returns
+/// once its entry has been written.
+pub struct TraceLogger {
+    tx: mpsc::Sender<Request>,
+    signing: bool,
+    redacting: bool,
+    observer: Option<Arc<dyn WriterObserver>>,
+    recovery: Option<TailRepair>,
+}
+
+impl TraceLogger {
+    /// Open (or create) the trace at `path`, resuming the chain from its last
+    /// valid entry. When `signing_key` is set every entry's hash is signed.
+    pub async fn open<P: AsRef<Path>>(path: P, signing_key: Option<SigningKey>) -> Result<Self> {
+        Self::open_with_config(
+            path,
+            TraceWriterConfig {
+                signing_key,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn open_with_config<P: AsRef<Path>>(
+        path: P,
+        config: TraceWriterConfig,
+    ) -> Result<Self> {
+        // A crash mid-write must not keep the node from starting.
+        let recovery = repair_tail(path.as_ref(), config.verify_depth)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written_len = file.metadata()?.len();
+        let (last_hash, next_i) = recover_state(path.as_ref())?;
+
+        let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+        let signing = config.signing_key.is_some();
+        let redacting = config.redactor.is_some();
+        let observer = config.observer.clone();
+        let max_batch = config.max_batch.max(1);
+        let writer = ChainWriter::new(file, written_len, last_hash, next_i, config);
+        // A thread of its own keeps blocking writes and fsyncs off the
+        // caller's runtime and outlives whichever runtime opened the log.
+        std::thread::Builder::new()
+            .name("ltp-trace-writer".to_string())
+            .spawn(move || writer.run(rx, max_batch))?;
+
+        let logger = Self {
+            tx,
+            signing,
+            redacting,
+            observer,
+            recovery,
+        };
+        if let Some(repair) = &logger.recovery {
+            logger
+                .log(RECOVERY_DIRECTION, "", &repair.to_frame())
+                .await?;
+        }
+        Ok(logger)
+    }
+
+    /// The tail repair performed when the trace was opened, if any.
+    pub fn recovery(&self) -> Option<&TailRepair> {
+        self.recovery.as_ref()
+    }
+
+    pub fn is_signing(&self) -> bool {
+        self.signing
+    }
+
+    pub fn is_redacting(&self) -> bool {
+        self.redacting
+    }
+
+    /// Entries queued but not yet taken by the writer.
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    pub async fn log(
+        &self,
+        direction: &str,
+        session_id: &str,
+        payload: &impl Serialize,
+    ) -> Result<()> {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let frame = serde_json::to_value(payload)?;
+
+        let (ack, done) = oneshot::channel();
+        self.enqueue(Request::Log(Pending {
+            timestamp_ms,
+            direction: direction.to_string(),
+            session_id: session_id.to_string(),
+            frame,
+            ack,
+        }))
+        .await?;
+        done.await.map_err(|_| TraceError::WriterClosed)?
+    }
+
+    /// Close `session_id`'s chain: its head is dropped from the writer's
+    /// memory, so later entries for the same id would start a new chain.
+    pub async fn end_session(&self, session_id: &str) -> Result<()> {
+        self.enqueue(Request::EndSession(session_id.to_string()))
+            .await
+    }
+
+    async fn enqueue(&self, request: Request) -> Result<()> {
+        match self.tx.try_send(request) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(request)) => {
+                if let Some(observer) = &self.observer {
+                    observer.queue_full();
+                }
+                self.tx
+                    .send(request)
+                    .await
+                    .map_err(|_| TraceError::WriterClosed)
+            }
+            Err(TrySendError::Closed(_)) => Err(TraceError::WriterClosed),
+        }
+    }
+}
+
+/// Head of one session's chain.
+struct SessionHead {
+    next_seq: u64,
+    last_hash: String,
+}
+
+/// The file a `ChainWriter` appends to.
+trait TraceSink: Write {
+    fn truncate(&mut self, len: u64) -> std::io::Result<()>;
+    fn sync_data(&mut self) -> std::io::Result<()>;
+}
+
+impl TraceSink for File {
+    fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+        // Opened in append mode, so the next write lands at the new end.
+        self.set_len(len)
+    }
+
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+/// Chain state owned by the writer thread.
+///
+/// Entries are chained into `buffer` and only become part of the chain once
+/// the buffer has been written out. A failed write truncates the file back to
+/// `written_len` and restores the head as of that offset, so the next entry
+/// chains onto the last one actually on disk.
+struct ChainWriter<F = File> {
+    file: F,
+    buffer: Vec<u8>,
+    written_len: u64,
+    last_hash: String,
+    next_i: u64,
+    sessions: HashMap<String, SessionHead>,
+    /// Global head as of `written_len`.
+    written_head: (String, u64),
+    /// Session heads replaced by buffered entries, oldest first.
+    replaced_heads: Vec<(String, Option<SessionHead>)>,
+    signing_key: Option<SigningKey>,
+    redactor: Option<Redactor>,
+    durability: Durability,
+    last_sync: Instant,
+    unsynced: bool,
+    observer: Option<Arc<dyn WriterObserver>>,
+}
+
+impl<F: TraceSink> ChainWriter<F> {
+    fn new(
+        file: F,
+        written_len: u64,
+        last_hash: String,
+        next_i: u64,
+        config: TraceWriterConfig,
+    ) -> Self {
+        Self {
+            file,
+            buffer: Vec::new(),
+            written_len,
+            written_head: (last_hash.clone(), next_i),
+            last_hash,
+            next_i,
+            sessions: HashMap::new(),
+            replaced_heads: Vec::new(),
+            signing_key: config.signing_key,
+            redactor: config.redactor,
+            durability: config.durability,
+            last_sync: Instant::now(),
+            unsynced: false,
+            observer: config.observer,
+        }
+    }
+
+    fn run(mut self, mut rx: mpsc::Receiver<Request>, max_batch: usize) {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            // Dropping `rx` fails every `log` call with `WriterClosed`.
+            Err(_) => return,
+        };
+        runtime.block_on(async move {
+            let mut batch = Vec::with_capacity(max_batch);
+            loop {
+                let sync_due = match self.durability {
+                    Durability::FsyncInterval(interval) if self.unsynced => {
+                        Some(self.last_sync + interval)
+                    }
+                    _ => None,
+                };
+                let received = match sync_due {
+                    Some(deadline) => tokio::select! {
+                        n = rx.recv_many(&mut batch, max_batch) => n,
+                        _ = tokio::time::sleep_until(deadline.into()) => {
+                            let _ = self.sync();
+                            continue;
+                        }
+                    },
+                    None => rx.recv_many(&mut batch, max_batch).await,
+                };
+                if received == 0 {
+                    break;
+                }
+                self.commit(&mut batch, rx.len());
+            }
+            if self.unsynced {
+                let _ = self.sync();
+            }
+        });
+    }
+
+    fn commit(&mut self, batch: &mut Vec<Request>, queue_depth: usize) {
+        let started = Instant::now();
+        let mut entries = 0;
+        let mut acks = Vec::with_capacity(batch.len());
+        let mut written = Ok(());
+        for request in batch.drain(..) {
+            let pending = match request {
+                Request::Log(pending) => pending,
+                Request::EndSession(session_id) => {
+                    self.sessions.remove(&session_id);
+                    self.replaced_heads.retain(|(id, _)| *id != session_id);
+                    continue;
+                }
+            };
+            entries += 1;
+            if written.is_err() {
+                acks.push(pending.ack);
+                continue;
+            }
+            match self.append(
+                pending.timestamp_ms,
+                pending.direction,
+                pending.session_id,
+                pending.frame,
+            ) {
+                Ok(()) => acks.push(pending.ack),
+                // Only this entry is lost; the chain was not advanced.
+                Err(err) => {
+                    let _ = pending.ack.send(Err(err));
+                    continue;
+                }
+            }
+            if self.durability == Durability::Flush {
+                written = self.write_buffer();
+                if written.is_ok() {
+                    for ack in acks.drain(..) {
+                        let _ = ack.send(Ok(()));
+                    }
+                }
+            }
+        }
+
+        let mut synced = false;
+        let mut durable = Ok(());
+        if written.is_ok() {
+            written = self.write_buffer();
+        }
+        if written.is_ok() {
+            let sync_now = match self.durability {
+                Durability::Flush => false,
+                Durability::FsyncBatch => true,
+                Durability::FsyncInterval(interval) => self.last_sync.elapsed() >= interval,
+            };
+            self.unsynced = !matches!(self.durability, Durability::Flush);
+            if sync_now {
+                // The entries stay chained: they are in the file, only their
+                // durability is in doubt.
+                durable = self.sync();
+                synced = durable.is_ok();
+            }
+        }
+
+        let copy = |err: &std::io::Error| std::io::Error::new(err.kind(), err.to_string());
+        for ack in acks.drain(..) {
+            let result = match (&written, &durable) {
+                (Err(err), _) => Err(TraceError::Io(copy(err))),
+                (Ok(()), Err(err)) => Err(TraceError::NotDurable(copy(err))),
+                (Ok(()), Ok(())) => Ok(()),
+            };
+            let _ = ack.send(result);
+        }
+
+        if let Some(observer) = &self.observer {
+            observer.batch_written(&BatchStats {
+                entries,
+                write_latency: started.elapsed(),
+                queue_depth,
+                synced,
+            });
+        }
+    }
+
+    /// Chain, sign and buffer one entry.
+    fn append(
+        &mut self,
+        timestamp_ms: u64,
+        direction: String,
+        session_id: String,
+        mut frame: Value,
+    ) -> Result<()> {
+        let i = self.next_i;
+        let redactions = match &self.redactor {
+            Some(redactor) => redactor.redact(i, &mut frame),
+            None => Default::default(),
+        };
+        // Entries outside any session (e.g. recovery records) are only on
+        // the global chain.
+        let (session_seq, session_prev_hash) = match self.sessions.get(&session_id) {
+            _ if session_id.is_empty() => (None, None),
+            Some(head) => (Some(head.next_seq), Some(head.last_hash.clone())),
+            None => (Some(0), Some(GENESIS_HASH.to_string())),
+        };
+
+        let mut entry = TraceEntry {
+            i,
+            timestamp_ms,
+            direction,
+            session_id,
+            frame,
+            redactions,
+            prev_hash: self.last_hash.clone(),
+            session_seq,
+            session_prev_hash,
+            hash: String::new(),
+            signature: None,
+            alg: None,
+        };
+        entry.hash = entry.chain_hash()?;
+
+        // P1-3: Optional Signing
+        if let Some(key) = &self.signing_key {
+            entry.signature = Some(hex::encode(key.sign(entry.hash.as_bytes()).to_bytes()));
+            entry.alg = Some(SIGNATURE_ALG_ED25519.to_string());
+        }
+
+        let start = self.buffer.len();
+        if let Err(err) = serde_json::to_writer(&mut self.buffer, &entry) {
+            self.buffer.truncate(start);
+            return Err(err.into());
+        }
+        self.buffer.push(b'\n');
+
+        self.next_i += 1;
+        if let Some(seq) = entry.session_seq {
+            let replaced = self.sessions.insert(
+                entry.session_id.clone(),
+                SessionHead {
+                    next_seq: seq + 1,
+                    last_hash: entry.hash.clone(),
+                },
+            );
+            self.replaced_heads.push((entry.session_id, replaced));
+        }
+        self.last_hash = entry.hash;
+        Ok(())
+    }
+
+    /// Write the buffered entries out, or roll the file and the chain back
+    /// to the last entry written.
+    fn write_buffer(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let written = self
+            .file
+            .write_all(&self.buffer)
+            .and_then(|()| self.file.flush());
+        match written {
+            Ok(()) => {
+                self.written_len += self.buffer.len() as u64;
+                self.written_head = (self.last_hash.clone(), self.next_i);
+                self.replaced_heads.clear();
+            }
+            Err(_) => {
+                // Part of the buffer may have reached the file. If this
+                // fails too, the torn tail is repaired on the next open.
+                let _ = self.file.truncate(self.written_len);
+                (self.last_hash, self.next_i) = self.written_head.clone();
+                for (session_id, head) in self.replaced_heads.drain(..).rev() {
+                    match head {
+                        Some(head) => self.sessions.insert(session_id, head),
+                        None => self.sessions.remove(&session_id),
+                    };
+                }
+            }
+        }
+        self.buffer.clear();
+        written
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.sync_data()?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+/// Read the last entry of the trace at `path` and return the chain head
+/// (`hash`) and the next index. Missing or empty files start at genesis.
+pub fn recover_state(path: &Path) -> Result<(String, u64)> {
+    if !path.exists() {
+        return Ok((GENESIS_HASH.to_string(), 0));
+    }
+
+    let mut file = std::fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    if metadata.len() == 0 {
+        return Ok((GENESIS_HASH.to_string(), 0));
+    }
+
+    let mut pos = metadata.len() as i64;
+    let chunk_size = 4096;
+
+    if pos > 0 {
+        file.seek(SeekFrom::End(-1))?;
+        let mut buf = [0u8; 1];
+        file.read_exact(&mut buf)?;
+        if buf[0] == b'\n' {
+            pos -= 1;
+        }
+    }
+
+    while pos > 0 {
+        let read_len = std::cmp::min(pos, chunk_size) as usize;
+        pos -= read_len as i64;
+        file.seek(SeekFrom::Start(pos as u64))?;
+
+        let mut chunk = vec![0u8; read_len];
+        file.read_exact(&mut chunk)?;
+
+        if let Some(idx) = chunk.iter().rposition(|&b| b == b'\n') {
+            pos += idx as i64 + 1;
+            break;
+        }
+    }
+
+    file.seek(SeekFrom::Start(pos as u64))?;
+
+    let mut reader = std::io::BufReader::new(file);
+    let mut line = String::new();
+    reader.read_to_string(&mut line)?;
+
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return Ok((GENESIS_HASH.to_string(), 0));
+    }
+
+    let entry: TraceEntry = serde_json::from_str(trimmed).map_err(TraceError::Recovery)?;
+
+    Ok((entry.hash, entry.i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonical::canonical_json_bytes;
+    use sha2::{Digest, Sha256};
+    use std::io::{BufRead, BufReader};
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_trace_chain_integrity() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_owned();
+        let logger = TraceLogger::open(&path, None).await?;
+
+        let msg1 = serde_json::json!({"b": 2, "a": 1});
+        logger.log("in", "s1", &msg1).await?;
+
+        let msg2 = serde_json::json!({"z": 9, "y": 8});
+        logger.log("out", "s1", &msg2).await?;
+
+        let file = std::fs::File::open(&path)?;
+        let reader = BufReader::new(file);
+        let lines: Vec<String> = reader.lines().collect::<std::result::Result<_, _>>()?;
+
+        assert_eq!(lines.len(), 2);
+
+        let entry1: TraceEntry = serde_json::from_str(&lines[0])?;
+        assert_eq!(entry1.i, 0);
+        assert_eq!(entry1.prev_hash, GENESIS_HASH);
+
+        let frame1_bytes = canonical_json_bytes(&entry1.frame)?;
+        let mut hasher1 = Sha256::new();
+        hasher1.update(entry1.prev_hash.as_bytes());
+        hasher1.update(&frame1_bytes);
+        hasher1.update(canonical_json_bytes(&serde_json::json!({
+            "session_prev_hash": GENESIS_HASH,
+            "session_seq": 0,
+        }))?);
+        assert_eq!(entry1.hash, format!("{:x}", hasher1.finalize()));
+
+        let entry2: TraceEntry = serde_json::from_str(&lines[1])?;
+        assert_eq!(entry2.i, 1);
+        assert_eq!(entry2.prev_hash, entry1.hash);
+        assert_eq!(entry2.session_seq, Some(1));
+        assert_eq!(
+            entry2.session_prev_hash.as_deref(),
+            Some(entry1.hash.as_str())
+        );
+
+        let frame2_bytes = canonical_json_bytes(&entry2.frame)?;
+        let mut hasher2 = Sha256::new();
+        hasher2.update(entry2.prev_hash.as_bytes());
+        hasher2.update(&frame2_bytes);
+        hasher2.update(canonical_json_bytes(&serde_json::json!({
+            "session_prev_hash": entry1.hash,
+            "session_seq": 1,
+        }))?);
+        assert_eq!(entry2.hash, format!("{:x}", hasher2.finalize()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resumes_chain_after_reopen() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_owned();
+
+        let logger = TraceLogger::open(&path, None).await?;
+        logger.log("in", "s1", &serde_json::json!({"n": 1})).await?;
+        drop(logger);
+
+        let logger = TraceLogger::open(&path, None).await?;
+        logger.log("in", "s1", &serde_json::json!({"n": 2})).await?;
+
+        let (head, next) = recover_state(&path)?;
+        assert_eq!(next, 2);
+
+        let content = std::fs::read_to_string(&path)?;
+        let entries: Vec<TraceEntry> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(entries[1].prev_hash, entries[0].hash);
+        assert_eq!(entries[1].hash, head);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redacted_entries_verify_and_disclose() -> Result<()> {
+        use crate::redact::{verify_disclosure, RedactionPolicy};
+        use crate::verify::TraceVerifier;
+
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_owned();
+        let policy: RedactionPolicy = serde_json::from_value(serde_json::json!({
+            "hello": ["/api_key"]
+        }))?;
+        let redactor = Redactor::new(policy, b"operator-key".to_vec());
+        let logger = TraceLogger::open_with_config(
+            &path,
+            TraceWriterConfig {
+                redactor: Some(redactor.clone()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        logger
+            .log(
+                "in",
+                "s1",
+                &serde_json::json!({"type": "hello", "api_key": "secret"}),
+            )
+            .await?;
+        logger
+            .log("out", "s1", &serde_json::json!({"type": "hello_ack"}))
+            .await?;
+
+        let content = std::fs::read_to_string(&path)?;
+        assert!(!content.contains("secret"));
+        let mut entries: Vec<TraceEntry> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert!(entries[1].redactions.is_empty());
+
+        let mut verifier = TraceVerifier::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            verifier.verify_entry(idx + 1, entry).unwrap();
+        }
+
+        let commitment = &entries[0].redactions["/api_key"];
+        let salt = redactor.salt_for(0, "/api_key");
+        assert!(verify_disclosure(
+            commitment,
+            &salt,
+            &serde_json::json!("secret")
+        ));
+
+        // Swapping the commitment breaks the chain hash.
+        entries[0]
+            .redactions
+            .insert("/api_key".to_string(), "0".repeat(64));
+        assert!(TraceVerifier::new().verify_entry(1, &entries[0]).is_err());
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct CountingObserver {
+        batches: std::sync::atomic::AtomicUsize,
+        entries: std::sync::atomic::AtomicUsize,
+        synced: std::sync::atomic::AtomicUsize,
+    }
+
+    impl WriterObserver for CountingObserver {
+        fn batch_written(&self, stats: &BatchStats) {
+            use std::sync::atomic::Ordering::Relaxed;
+            self.batches.fetch_add(1, Relaxed);
+            self.entries.fetch_add(stats.entries, Relaxed);
+            if stats.synced {
+                self.synced.fetch_add(1, Relaxed);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_logging_keeps_a_single_chain() -> Result<()> {
+        use crate::reader::TraceReader;
+        use crate::verify::TraceVerifier;
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let temp_file = NamedTempFile::new()?;
+        let observer = Arc::new(CountingObserver::default());
+        let logger = Arc::new(
+            TraceLogger::open_with_config(
+                temp_file.path(),
+                TraceWriterConfig {
+                    durability: Durability::FsyncBatch,
+                    queue_capacity: 4,
+                    max_batch: 16,
+                    observer: Some(observer.clone()),
+                    ..Default::default()
+                },
+            )
+            .await?,
+        );
+
+        let tasks: Vec<_> = (0..200)
+            .map(|n| {
+                let logger = logger.clone();
+                tokio::spawn(async move {
+                    logger
+                        .log("in", &format!("s{}", n % 7), &serde_json::json!({"n": n}))
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap()?;
+        }
+
+        let report = TraceVerifier::new()
+            .verify_reader(TraceReader::open(temp_file.path())?)
+            .unwrap();
+        assert_eq!(report.entries, 200);
+        assert_eq!(observer.entries.load(Relaxed), 200);
+        let batches = observer.batches.load(Relaxed);
+        assert!(batches <= 200);
+        assert_eq!(observer.synced.load(Relaxed), batches);
+        assert_eq!(logger.queue_depth(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn interval_durability_flushes_without_syncing() -> Result<()> {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let temp_file = NamedTempFile::new()?;
+        let observer = Arc::new(CountingObserver::default());
+        let logger = TraceLogger::open_with_config(
+            temp_file.path(),
+            TraceWriterConfig {
+                durability: Durability::FsyncInterval(Duration::from_secs(3600)),
+                observer: Some(observer.clone()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        logger.log("in", "s1", &serde_json::json!({"n": 1})).await?;
+        logger.log("in", "s1", &serde_json::json!({"n": 2})).await?;
+
+        // Flushed (readable) even though the fsync interval has not elapsed.
+        assert_eq!(
+            std::fs::read_to_string(temp_file.path())?.lines().count(),
+            2
+        );
+        assert_eq!(observer.synced.load(Relaxed), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reopening_after_a_torn_write_records_a_signed_recovery() -> Result<()> {
+        use crate::reader::TraceReader;
+        use crate::recovery::{append_raw, RECOVERY_FRAME_TYPE};
+        use crate::verify::TraceVerifier;
+
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("trace.jsonl");
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+
+        let logger = TraceLogger::open(&path, Some(signing_key.clone())).await?;
+        logger.log("in", "s1", &serde_json::json!({"n": 1})).await?;
+        drop(logger);
+        append_raw(&path, b"{\"i\":1,\"times");
+
+        let logger = TraceLogger::open(&path, Some(signing_key.clone())).await?;
+        let repair = logger.recovery().expect("torn tail repaired").clone();
+        assert_eq!(repair.last_valid_i, Some(0));
+        logger.log("in", "s1", &serde_json::json!({"n": 2})).await?;
+
+        let entries: Vec<TraceEntry> = TraceReader::open(&path)?
+            .map(|r| r.unwrap().entry)
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].direction, RECOVERY_DIRECTION);
+        assert_eq!(entries[1].frame_type(), Some(RECOVERY_FRAME_TYPE));
+        assert_eq!(
+            entries[1].frame["quarantined_sha256"],
+            repair.quarantined_sha256
+        );
+
+        let report = TraceVerifier::new()
+            .with_verifying_key(signing_key.verifying_key())
+            .verify_reader(TraceReader::open(&path)?)
+            .unwrap();
+        assert_eq!(report.entries, 3);
+        assert!(repair.quarantine_file.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_chains_verify_in_isolation() -> Result<()> {
+        use crate::reader::TraceReader;
+        use crate::verify::SessionVerifier;
+        use crate::VerificationError;
+
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_owned();
+        let signing_key = SigningKey::from_bytes(&[9u8; 32]);
+        let logger = TraceLogger::open(&path, Some(signing_key.clone())).await?;
+        for n in 0..6 {
+            let session = if n % 2 == 0 { "a" } else { "b" };
+            logger
+                .log("in", session, &serde_json::json!({"n": n}))
+                .await?;
+        }
+        logger.end_session("a").await?;
+        logger.log("in", "a", &serde_json::json!({"n": 6})).await?;
+
+        // Only session "b" is handed over.
+        let content = std::fs::read_to_string(&path)?;
+        let extract: Vec<&str> = content
+            .lines()
+            .filter(|line| line.contains("\"session_id\":\"b\""))
+            .collect();
+        let extract_file = NamedTempFile::new()?;
+        std::fs::write(extract_file.path(), extract.join("\n") + "\n")?;
+
+        let report = SessionVerifier::new("b")
+            .with_verifying_key(signing_key.verifying_key())
+            .verify_reader(TraceReader::open(extract_file.path())?)
+            .unwrap();
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.signed_entries, 3);
+
+        // A dropped entry breaks the session chain.
+        std::fs::write(extract_file.path(), [extract[0], extract[2]].join("\n"))?;
+        let err = SessionVerifier::new("b")
+            .verify_reader(TraceReader::open(extract_file.path())?)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            VerificationError::SequenceBreak {
+                expected: 1,
+                found: 2,
+                ..
+            }
+        ));
+
+        // Ending a session restarts its chain.
+        let entries: Vec<TraceEntry> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(entries[6].session_seq, Some(0));
+        assert_eq!(entries[6].session_prev_hash.as_deref(), Some(GENESIS_HASH));
+        Ok(())
+    }
+
+    /// Shared with the test so writes can be made to fail after the fact.
+    #[derive(Clone, Default)]
+    struct FailingSink {
+        data: Arc<std::sync::Mutex<Vec<u8>>>,
+        failing: Arc<std::sync::atomic::AtomicBool>,
+        sync_failing: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Write for FailingSink {
+        /// While failing, lets a few bytes through before erroring, like a
+        /// disk filling up mid-entry.
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let mut data = self.data.lock().unwrap();
+            if self
+                .failing
+                .swap(false, std::sync::atomic::Ordering::Relaxed)
+            {
+                data.extend_from_slice(&buf[..buf.len().min(7)]);
+                return Ok(buf.len().min(7));
+            }
+            if data.last().is_some_and(|&b| b != b'\n') {
+                return Err(std::io::Error::other("disk full"));
+            }
+            data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TraceSink for FailingSink {
+        fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+            self.data.lock().unwrap().truncate(len as usize);
+            Ok(())
+        }
+
+        fn sync_data(&mut self) -> std::io::Result<()> {
+            if self.sync_failing.load(std::sync::atomic::Ordering::Relaxed) {
+                return Err(std::io::Error::other("fsync failed"));
+            }
+            Ok(())
+        }
+    }
+
+    fn log_request(n: u64) -> (Request, oneshot::Receiver<Result<()>>) {
+        let (ack, done) = oneshot::channel();
+        let pending = Pending {
+            timestamp_ms: n,
+            direction: "in".to_string(),
+            session_id: "s1".to_string(),
+            frame: serde_json::json!({"n": n}),
+            ack,
+        };
+        (Request::Log(pending), done)
+    }
+
+    #[test]
+    fn failed_write_rolls_back_to_the_last_written_entry() -> Result<()> {
+        use crate::reader::TraceReader;
+        use crate::verify::TraceVerifier;
+
+        for durability in [Durability::Flush, Durability::FsyncBatch] {
+            let sink = FailingSink::default();
+            let config = TraceWriterConfig {
+                durability,
+                ..Default::default()
+            };
+            let mut writer = ChainWriter::new(sink.clone(), 0, GENESIS_HASH.to_string(), 0, config);
+
+            let (request, mut done) = log_request(0);
+            writer.commit(&mut vec![request], 0);
+            assert!(done.try_recv().unwrap().is_ok());
+            let written = sink.data.lock().unwrap().clone();
+
+            sink.failing
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            let (first, mut first_done) = log_request(1);
+            let (second, mut second_done) = log_request(2);
+            writer.commit(&mut vec![first, second], 0);
+            assert!(matches!(
+                first_done.try_recv().unwrap(),
+                Err(TraceError::Io(_))
+            ));
+            assert!(matches!(
+                second_done.try_recv().unwrap(),
+                Err(TraceError::Io(_))
+            ));
+            // The torn bytes are gone and the head is back at entry 0.
+            assert_eq!(*sink.data.lock().unwrap(), written);
+            assert_eq!(writer.next_i, 1);
+            assert_eq!(writer.sessions["s1"].next_seq, 1);
+
+            let (request, mut done) = log_request(3);
+            writer.commit(&mut vec![request], 0);
+            assert!(done.try_recv().unwrap().is_ok());
+
+            let data = sink.data.lock().unwrap().clone();
+            let report = TraceVerifier::new()
+                .verify_reader(TraceReader::new(data.as_slice()))
+                .unwrap();
+            assert_eq!(report.entries, 2);
+            let last: TraceEntry =
+                serde_json::from_str(std::str::from_utf8(&data).unwrap().lines().last().unwrap())?;
+            assert_eq!(last.session_seq, Some(1));
+            assert_eq!(last.frame, serde_json::json!({"n": 3}));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn failed_fsync_keeps_the_written_entries_chained() {
+        use crate::reader::TraceReader;
+        use crate::verify::TraceVerifier;
+
+        let sink = FailingSink::default();
+        let config = TraceWriterConfig {
+            durability: Durability::FsyncBatch,
+            ..Default::default()
+        };
+        let mut writer = ChainWriter::new(sink.clone(), 0, GENESIS_HASH.to_string(), 0, config);
+
+        sink.sync_failing
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let (request, mut done) = log_request(0);
+        writer.commit(&mut vec![request], 0);
+        assert!(matches!(
+            done.try_recv().unwrap(),
+            Err(TraceError::NotDurable(_))
+        ));
+        assert!(writer.unsynced);
+        assert_eq!(writer.next_i, 1);
+
+        sink.sync_failing
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+        let (request, mut done) = log_request(1);
+        writer.commit(&mut vec![request], 0);
+        assert!(done.try_recv().unwrap().is_ok());
+        assert!(!writer.unsynced);
+
+        let data = sink.data.lock().unwrap().clone();
+        let report = TraceVerifier::new()
+            .verify_reader(TraceReader::new(data.as_slice()))
+            .unwrap();
+        assert_eq!(report.entries, 2);
+    }
+}